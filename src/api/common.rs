@@ -1,7 +1,99 @@
+use futures::{future::ready, StreamExt};
+
+use crate::{
+    bot::SendTarget,
+    messages::{Message, MessageStream},
+    Bot,
+};
+
 /// 发送消息的返回
 #[derive(Debug, Deserialize)]
 pub struct SendMessageResponse {
     /// 标识本条消息，用于撤回和引用回复
     #[serde(rename = "messageId")]
     pub message_id: i64,
+
+    /// 消息发送到的聊天，通过 [`Bot`] 发送时会记录
+    #[serde(skip)]
+    pub(crate) target: Option<SendTarget>,
+}
+
+impl SendMessageResponse {
+    /// 获取引用回复这条消息的后续消息，只包括这条消息所在的聊天。
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use miraie::prelude::*;
+    /// use futures::StreamExt;
+    /// use std::time::Duration;
+    /// # tokio_test::block_on(async {
+    /// # let (bot, _) = Bot::new("127.0.0.1", "secret", QQ(123456)).await.unwrap();
+    /// let poll = bot
+    ///     .send_group_message(QQ(10000), "投票：火锅还是烧烤？请引用这条消息回复")
+    ///     .await?;
+    /// // 收集 10 分钟内的回复
+    /// let mut replies = poll
+    ///     .replies(&bot)
+    ///     .take_until(Box::pin(tokio::time::sleep(Duration::from_secs(600))));
+    /// while let Some(reply) = replies.next().await {
+    ///     // ...
+    /// }
+    /// # Result::<(), miraie::Error>::Ok(()) });
+    /// ```
+    pub fn replies(&self, bot: &Bot) -> MessageStream<Message> {
+        let id = self.message_id;
+        let target = self.target;
+        MessageStream::new(bot.messages().filter(move |msg| {
+            let in_target = match (target, msg) {
+                (None, _) => true,
+                (Some(SendTarget::Group(group)), Message::Group(msg)) => {
+                    msg.sender.group.id == group
+                }
+                (Some(SendTarget::Friend(friend)), Message::Friend(msg)) => msg.sender.id == friend,
+                _ => false,
+            };
+            let quoted = msg
+                .message_chain()
+                .is_some_and(|chain| chain.quote_id() == Some(id));
+            ready(in_target && quoted)
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        bot::QQ,
+        messages::{MessageBlock, MessageChain},
+        test_utils::group_message,
+        App,
+    };
+    use std::time::Duration;
+
+    fn reply(group: u64, quote: Option<i64>) -> Message {
+        let mut message = MessageChain::new();
+        if let Some(id) = quote {
+            message.0.push(MessageBlock::quote(id));
+        }
+        Message::Group(group_message(group, 10, message.text("火锅")))
+    }
+
+    #[tokio::test]
+    async fn test_replies() {
+        let bot = Bot::mock(QQ(1));
+        let response = SendMessageResponse {
+            message_id: 7,
+            target: Some(SendTarget::Group(QQ(100))),
+        };
+        let replies = response
+            .replies(&bot)
+            .take_until(tokio::time::sleep(Duration::from_millis(20)));
+        for (group, quote) in [(100, Some(7)), (200, Some(7)), (100, Some(8)), (100, None)] {
+            bot.event_bus().send(reply(group, quote)).unwrap();
+        }
+        let replies: Vec<_> = replies.collect().await;
+        assert_eq!(replies.len(), 1);
+        assert!(matches!(&replies[0], Message::Group(msg) if msg.sender.group.id == QQ(100)));
+    }
 }
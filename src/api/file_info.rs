@@ -0,0 +1,59 @@
+//! 获取文件信息
+//!
+//! 使用此方法获取群文件或好友文件的信息，可以同时获取下载链接
+
+use crate::bot::QQ;
+
+#[derive(Debug, Serialize)]
+pub struct Request {
+    /// 文件夹id, 空串为根目录
+    pub id: String,
+    /// 文件夹路径, 文件夹允许重名, 不保证准确, 准确定位使用 id
+    pub path: Option<String>,
+    /// 群号或好友QQ号
+    pub target: QQ,
+    /// 是否携带下载信息，额外请求，无必要不要携带
+    #[serde(rename = "withDownloadInfo")]
+    pub with_download_info: bool,
+}
+
+/// 文件信息
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Response {
+    /// 文件名
+    pub name: String,
+    /// 文件 id
+    pub id: Option<String>,
+    /// 文件路径
+    pub path: String,
+    /// 是否是文件
+    pub is_file: bool,
+    /// 是否是文件夹
+    pub is_directory: bool,
+    /// 文件大小
+    #[serde(default)]
+    pub size: u64,
+    /// 下载信息，只有请求时 `with_download_info` 为真才有
+    pub download_info: Option<DownloadInfo>,
+}
+
+/// 文件的下载信息
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DownloadInfo {
+    pub sha1: String,
+    pub md5: String,
+    /// 下载次数
+    pub download_times: u32,
+    /// 上传者QQ
+    pub uploader_id: QQ,
+    /// 上传时间
+    pub upload_time: i64,
+    /// 最后修改时间
+    pub last_modify_time: i64,
+    /// 下载 url
+    pub url: String,
+}
+
+crate::api!(command = "file_info", Request, Response);
@@ -1,6 +1,7 @@
 //! 实现 mirai 提供的 API 接口，如拉取群列表等
 //!
 pub mod common;
+pub mod file_info;
 pub mod friend_list;
 pub mod group_list;
 pub mod member_list;
@@ -66,7 +67,6 @@ pub struct ApiRequestData<T: Serialize> {
 ///
 /// miraie::api!(command = "memberList", Request, Response);
 /// ```
-
 #[macro_export]
 macro_rules! api {
     (
@@ -1,22 +1,31 @@
 use super::{
-    connection::Connection, extensions::Extensions, KeywordCommandHandler, KeywordCommandHandlers,
-    QQ,
+    command_pattern::CommandRegex,
+    connection::Connection,
+    keyword_command::leading_text,
+    plugin::{PluginScope, Plugins},
+    Command, CommandMatch, KeywordCommandHandler, KeywordCommandHandlers, SendThrottle, QQ,
 };
 use crate::{
     api::ApiRequest,
-    messages::{Event, FriendMessage, GroupMessage, Message},
-    msg_framework::{FromRequest, Request, Return},
-    App, Error, Result,
+    messages::{ActiveSessions, Event, FriendMessage, GroupMessage, Message},
+    msg_framework::{
+        App, Dispatcher, Extensions, FromRequest, Middlewares, Outcome, Request, Return, Scheduler,
+    },
+    Error, Result,
 };
-use futures::{Future, Stream, StreamExt};
+use futures::{future::join_all, Future, Stream, StreamExt};
 use parking_lot::RwLock;
+use regex::Regex;
 use serde_json::Value;
 use std::{
     future::ready,
     sync::Arc,
     time::{Duration, Instant},
 };
-use tokio::sync::{broadcast, mpsc};
+use tokio::sync::{broadcast, mpsc, watch};
+
+/// 发往 mirai 的请求以及它的 sync id
+type ApiPacket = (i64, Box<dyn ApiRequest>);
 
 /// [`Bot`] 代表跟一个 mirai QQ 机器人的链接。
 /// 内部保存 bot 中的状态，如消息队列、跟连接的沟通、数据库连接等。
@@ -26,16 +35,34 @@ use tokio::sync::{broadcast, mpsc};
 /// [`Bot`] 会被共享，它可能会被克隆很多份并在多个线程中被访问。它被设计为 [`Send`]。
 #[derive(Clone)]
 pub struct Bot {
+    /// 机器人的 QQ 号
+    qq: QQ,
     /// 在 handler 内广播消息，如群消息等
     message_channel: broadcast::Sender<Message>,
     /// 处理主动消息，如发送消息等
-    request_channel: mpsc::Sender<(i64, Box<dyn ApiRequest>)>,
-    ///
+    request_channel: mpsc::Sender<ApiPacket>,
+    /// 接收 API 请求的返回
     response_channel: broadcast::Sender<(i64, Value)>,
-    ///
+    /// 通过关键词注册的指令
     pub(crate) kw_command_handlers: KeywordCommandHandlers,
 
     pub(crate) extensions: Arc<RwLock<Extensions>>,
+    /// 全局中间件
+    middlewares: Middlewares<Bot>,
+    /// 按照优先级分发消息，关键词指令也在其中
+    dispatcher: Dispatcher<Bot>,
+    /// 全局的并发限制以及串行模式
+    scheduler: Scheduler<Bot>,
+    /// 正在进行的多轮对话
+    pub(crate) sessions: ActiveSessions,
+    /// [`Connection`] 被丢弃时关闭
+    closed: watch::Receiver<()>,
+    /// 注册的所有插件
+    pub(crate) plugins: Plugins,
+    /// 发送消息的全局限速，插件中注册的也是全局的
+    pub(crate) send_throttle: Arc<RwLock<Option<SendThrottle>>>,
+    /// 插件注册时使用的 bot 所属的插件
+    plugin: Option<Arc<PluginScope>>,
 }
 
 impl crate::msg_framework::App for Bot {
@@ -43,6 +70,18 @@ impl crate::msg_framework::App for Bot {
     fn event_bus(&self) -> broadcast::Sender<Self::Message> {
         self.message_channel.clone()
     }
+
+    fn middlewares(&self) -> Middlewares<Self> {
+        self.middlewares.clone()
+    }
+
+    fn dispatcher(&self) -> Dispatcher<Self> {
+        self.dispatcher.clone()
+    }
+
+    fn scheduler(&self) -> Scheduler<Self> {
+        self.scheduler.clone()
+    }
 }
 
 impl Bot {
@@ -88,24 +127,135 @@ impl Bot {
         let (ws_stream, _) = async_tungstenite::tokio::connect_async(url).await?;
         let (request_tx, request_rx) = mpsc::channel(4096);
         let (response_tx, _) = broadcast::channel(4096);
+        let (closed_tx, closed) = watch::channel(());
         debug!("bot {} connected.", qq);
-        let connection =
-            super::Connection::new(ws_stream, request_rx, tx.clone(), response_tx.clone());
+        let connection = super::Connection::new(
+            ws_stream,
+            request_rx,
+            tx.clone(),
+            response_tx.clone(),
+            closed_tx,
+        );
 
-        let mut bot = Bot {
+        let bot = Bot {
+            qq,
             message_channel: tx,
             request_channel: request_tx,
             response_channel: response_tx,
             kw_command_handlers: KeywordCommandHandlers::new(),
             extensions: Arc::new(RwLock::new(Extensions::new())),
+            middlewares: Middlewares::new(),
+            dispatcher: Dispatcher::new(),
+            scheduler: Scheduler::new(),
+            sessions: ActiveSessions::default(),
+            closed,
+            plugins: Plugins::default(),
+            send_throttle: Arc::default(),
+            plugin: None,
         };
 
-        // 注册关键词 handler
-        bot = bot.handler(Self::process_keyword_command);
+        bot.register_dispatch();
 
         Ok((bot, connection))
     }
 
+    /// 注册内部的分发：关键词指令以及会话
+    fn register_dispatch(&self) {
+        // 注册关键词 handler，优先级为 0，中间件只包裹具体的指令，不包裹分发
+        self.dispatcher
+            .register(self.clone(), Some(0), Self::process_keyword_command, false);
+        // 会话中的消息只交给会话处理，优先级最高
+        self.dispatcher
+            .register(self.clone(), Some(i32::MAX), Self::process_sessions, false);
+    }
+
+    /// 不连接服务器的 bot，只用于测试，发送的请求会失败，也不会分发消息
+    #[cfg(test)]
+    pub(crate) fn mock(qq: QQ) -> Self {
+        Self::mock_parts(qq).0
+    }
+
+    /// 不连接服务器的 bot，只用于测试，API 请求交给 `respond` 回复，参数是请求的指令和内容。
+    /// 和真正的 bot 一样分发收到的消息，需要在 tokio 运行时中调用
+    #[cfg(test)]
+    pub(crate) fn mock_api(
+        qq: QQ,
+        respond: impl Fn(&str, &Value) -> Value + Send + 'static,
+    ) -> Self {
+        let (bot, mut requests) = Self::mock_parts(qq);
+        bot.register_dispatch();
+        let responses = bot.response_channel.clone();
+        tokio::spawn(async move {
+            while let Some((sync_id, request)) = requests.recv().await {
+                let packet: Value = serde_json::from_str(&request.encode(sync_id)).unwrap();
+                let response = respond(request.command(), &packet["content"]);
+                let _ = responses.send((sync_id, response));
+            }
+        });
+        bot
+    }
+
+    #[cfg(test)]
+    fn mock_parts(qq: QQ) -> (Self, mpsc::Receiver<ApiPacket>) {
+        let (message_channel, _) = broadcast::channel(16);
+        let (request_channel, requests) = mpsc::channel(16);
+        let (response_channel, _) = broadcast::channel(16);
+        let (closed_tx, closed) = watch::channel(());
+        // 测试中的连接不会关闭
+        std::mem::forget(closed_tx);
+        let bot = Bot {
+            qq,
+            message_channel,
+            request_channel,
+            response_channel,
+            kw_command_handlers: KeywordCommandHandlers::new(),
+            extensions: Arc::new(RwLock::new(Extensions::new())),
+            middlewares: Middlewares::new(),
+            dispatcher: Dispatcher::new(),
+            scheduler: Scheduler::new(),
+            sessions: ActiveSessions::default(),
+            closed,
+            plugins: Plugins::default(),
+            send_throttle: Arc::default(),
+            plugin: None,
+        };
+        (bot, requests)
+    }
+
+    /// 机器人的 QQ 号
+    pub fn qq(&self) -> QQ {
+        self.qq
+    }
+
+    /// 插件 `plugin` 中使用的 bot，注册的 handler 经过插件的中间件，使用插件的数据
+    pub(crate) fn scoped(&self, plugin: &Arc<PluginScope>) -> Bot {
+        Bot {
+            middlewares: plugin.middlewares.clone(),
+            plugin: Some(plugin.clone()),
+            ..self.clone()
+        }
+    }
+
+    /// 插件中的 bot 所属插件的名字
+    pub(crate) fn plugin_name(&self) -> Option<&str> {
+        self.plugin.as_ref().map(|plugin| plugin.name.as_str())
+    }
+
+    /// 通过 [`Bot::bot_data`] 注册的数据，插件中优先使用插件自己注册的数据
+    pub(crate) fn data<T: Clone + 'static>(&self) -> Option<T> {
+        let local = self
+            .plugin
+            .as_ref()
+            .and_then(|plugin| plugin.extensions.read().get::<T>().cloned());
+        local.or_else(|| self.extensions.read().get::<T>().cloned())
+    }
+
+    /// 等待跟 mirai 的连接关闭
+    pub(crate) async fn closed(&self) {
+        let mut closed = self.closed.clone();
+        while closed.changed().await.is_ok() {}
+    }
+
     /// 对 mirai bot 发送一个请求，默认超时 10s，如果需要调整超时，使用 [`Self::request_timeout`]。
     pub async fn request<Request>(&self, request: Request) -> Result<Request::Response>
     where
@@ -211,9 +361,14 @@ impl Bot {
         })
     }
 
-    /// 通过 **前缀关键词** 来注册一个回调。目前不可取消。
+    /// 通过 **前缀关键词** 来注册一个回调。
+    ///
+    /// 只有前缀完全匹配的消息才会进行匹配，多个关键词都能匹配时只会触发最长的那个，
+    /// 如注册了【帮助】和【帮助 详细】，【帮助 详细】只会触发后者。
+    /// 返回的 [`Command`] 可以用来添加别名、设置忽略大小写，以及暂停或者取消注册指令。
     ///
-    /// 只有前缀完全匹配的消息才会进行匹配。
+    /// 指令在优先级为 0 的位置分发，指令处理了消息后，更低优先级的 handler 以及 fallback handler
+    /// 不会再收到这条消息，见 [`App::handler_priority`](crate::App::handler_priority)。
     ///
     /// `command` 方法比 `handler` 的方法相对而言实现更加高效，如果可能，尽量使用 `command` 来注册。
     ///
@@ -232,49 +387,176 @@ impl Bot {
     /// let bot = bot.command("在吗", |_: GroupMessage| async { "嘎哈" });
     /// # });
     /// ```
-    pub fn command<F, I, Fut>(self, command: impl Into<String>, handler: F) -> Self
+    pub fn command<F, I, Fut>(self, command: impl Into<String>, handler: F) -> Command
     where
         F: crate::msg_framework::Func<I, Fut>,
         I: Send + 'static + FromRequest<Bot>,
         Fut: Future + Send + 'static,
         Fut::Output: Return<Bot>,
     {
-        let mut handlers = self.kw_command_handlers.0.write();
-        let pair = (command.into(), KeywordCommandHandler::new(handler));
-        handlers.push(pair);
-        std::mem::drop(handlers);
+        let handler = KeywordCommandHandler::new(handler).with_plugin(self.plugin.clone());
+        Command::register(self, |registry, state| {
+            registry.register(command.into(), handler, state)
+        })
+    }
 
-        self
+    /// 注册一个通过正则表达式匹配的指令，匹配的对象是消息中所有的文字，
+    /// 可以在回调中使用 [`RegexMatch`] 提取匹配到的内容。
+    ///
+    /// 关键词指令优先，只有没有关键词指令匹配时才会尝试正则表达式，多个正则表达式都能匹配时只会触发最先注册的那个。
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use miraie::prelude::*;
+    /// use miraie::bot::RegexMatch;
+    /// use regex::Regex;
+    /// # tokio_test::block_on(async {
+    /// # let (bot, _) = Bot::new("127.0.0.1", "secret", QQ(123456)).await.unwrap();
+    /// bot.command_regex(Regex::new(r"^(.+)是什么$").unwrap(), |m: RegexMatch| async move {
+    ///     format!("{}就是{}", m.get(1).unwrap(), m.get(1).unwrap())
+    /// });
+    /// # });
+    /// ```
+    pub fn command_regex<F, I, Fut>(self, regex: Regex, handler: F) -> Command
+    where
+        F: crate::msg_framework::Func<I, Fut>,
+        I: Send + 'static + FromRequest<Bot>,
+        Fut: Future + Send + 'static,
+        Fut::Output: Return<Bot>,
+    {
+        let handler = KeywordCommandHandler::new(handler).with_plugin(self.plugin.clone());
+        Command::register(self, |registry, state| {
+            registry.register_regex(CommandRegex::from_regex(regex), handler, state)
+        })
     }
 
-    async fn process_keyword_command(msg: Message, handlers: KeywordCommandHandlers, bot: Bot) {
+    /// 注册一个通过模式匹配的指令，如 `roll {n:u32}d{sides:u32}`、`{thing}是什么`，
+    /// 模式需要匹配消息中所有的文字，可以在回调中使用 [`RegexMatch`] 提取捕获到的内容。
+    ///
+    /// 模式中可以使用的类型：
+    /// - 不写类型或者 `str`：任意文字；
+    /// - `word`：不含空白的文字；
+    /// - `u8`、`i32`、`f64` 等数字类型：对应类型的数字，不能解析成对应类型时不会触发指令。
+    ///
+    /// 模式中的空白可以匹配任意个数的空白，`{{` 和 `}}` 分别表示 `{` 和 `}`。
+    /// 匹配的优先级同 [`Bot::command_regex`]。
+    ///
+    /// # Panics
+    /// 模式无效时 panic。
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use miraie::prelude::*;
+    /// use miraie::bot::RegexMatch;
+    /// # tokio_test::block_on(async {
+    /// # let (bot, _) = Bot::new("127.0.0.1", "secret", QQ(123456)).await.unwrap();
+    /// bot.command_pattern("{thing}是什么", |m: RegexMatch| async move {
+    ///     format!("{}是嘉然小姐的狗", m.name("thing").unwrap())
+    /// });
+    /// # });
+    /// ```
+    pub fn command_pattern<F, I, Fut>(self, pattern: &str, handler: F) -> Command
+    where
+        F: crate::msg_framework::Func<I, Fut>,
+        I: Send + 'static + FromRequest<Bot>,
+        Fut: Future + Send + 'static,
+        Fut::Output: Return<Bot>,
+    {
+        let regex = CommandRegex::from_pattern(pattern).unwrap_or_else(|e| panic!("{}", e));
+        let handler = KeywordCommandHandler::new(handler).with_plugin(self.plugin.clone());
+        Command::register(self, |registry, state| {
+            registry.register_regex(regex, handler, state)
+        })
+    }
+
+    /// 消费属于某个 [`Session`](crate::messages::Session) 的消息
+    async fn process_sessions(msg: Message, bot: Bot) -> Outcome {
+        match bot.sessions.owns(&msg) {
+            true => Outcome::Handled,
+            false => Outcome::Skipped,
+        }
+    }
+
+    /// 分发关键词指令，有指令处理了消息时消费这条消息
+    async fn process_keyword_command(
+        msg: Message,
+        handlers: KeywordCommandHandlers,
+        bot: Bot,
+    ) -> Outcome {
         debug!("processing keyword command");
-        let msg_s = match &msg {
-            Message::Friend(f) => f.message.to_string(),
-            Message::Group(g) => g.message.to_string(),
-            Message::Temp(t) => t.message.to_string(),
-            Message::Stranger(s) => s.message.to_string(),
-            Message::Event(_ev) => {
-                return;
-            }
+        let chain = match msg.message_chain() {
+            Some(chain) => chain,
+            None => return Outcome::Skipped,
         };
-        let handlers = handlers.0.read();
-        // TODO: 这里可以用前缀树
-        for (command, handler) in handlers.iter() {
-            if msg_s.starts_with(command) {
-                let app = bot.clone();
-                let message = msg.clone();
-                let handler = handler.clone();
-                let fut = async move {
-                    handler.handle(Request { app, message }).await;
-                };
-                tokio::spawn(fut);
+        let text = leading_text(chain);
+        // 只有最长的关键词对应的指令会被触发，没有匹配的关键词时再尝试正则表达式
+        let found = handlers.0.read().find(&text);
+        // 指令开始执行后就算处理了消息，不等待指令执行完
+        let handled = match found {
+            Some((len, matched)) => {
+                let command = &text[..len];
+                let args = chain
+                    .strip_prefix(command)
+                    .expect("关键词是消息开头文字的前缀");
+                let tasks = matched.iter().map(|handler| {
+                    let mut request = Request::new(bot.clone(), msg.clone());
+                    request
+                        .extensions
+                        .insert(CommandMatch::new(command, args.clone()));
+                    handler.spawn(request)
+                });
+                join_all(tasks).await
+            }
+            None => {
+                let found = handlers.0.read().find_regex(chain);
+                match found {
+                    Some((regex_match, handler)) => {
+                        let mut request = Request::new(bot, msg.clone());
+                        request.extensions.insert(regex_match);
+                        vec![handler.spawn(request).await]
+                    }
+                    None => return Outcome::Skipped,
+                }
             }
+        };
+        // 指令被守卫等拦截时不算处理了消息
+        match handled.contains(&true) {
+            true => Outcome::Handled,
+            false => Outcome::Skipped,
         }
     }
 
+    /// 开启按会话串行的模式：同一个群或者同一个用户的私聊消息按照收到的顺序依次处理，
+    /// 不同的会话之间仍然并发处理。事件不受影响。见 [`App::serial_by`]。
+    ///
+    /// 指令开始执行后就会分发同一个会话的下一条消息，不会等待指令执行完，
+    /// 所以等待用户回答的指令不会阻塞这个会话中的其他消息。
+    ///
+    /// ```no_run
+    /// # use miraie::prelude::*;
+    /// # tokio_test::block_on(async {
+    /// # let (bot, _) = Bot::new("127.0.0.1", "secret", QQ(123456)).await.unwrap();
+    /// bot.serial_per_conversation()
+    ///     .max_concurrency(64)
+    ///     .command("查询", |_: GroupMessage| async { "..." })
+    ///     // 这个指令最多同时处理 4 条消息
+    ///     .max_concurrency(4);
+    /// # });
+    /// ```
+    pub fn serial_per_conversation(self) -> Self {
+        self.serial_by(|message: &Message| match message {
+            Message::Group(msg) => Some(ConversationKey::Group(msg.sender.group.id)),
+            Message::Temp(msg) => Some(ConversationKey::Temp(msg.sender.group.id, msg.sender.id)),
+            Message::Friend(msg) => Some(ConversationKey::Private(msg.sender.id)),
+            Message::Stranger(msg) => Some(ConversationKey::Private(msg.sender.id)),
+            Message::Event(_) | Message::Scheduled(_) => None,
+        })
+    }
+
     /// 可以使用 `bot_data` 注册配置/数据库连接池等，并使用 [`crate::Data`] 进行提取。
     ///
+    /// 在插件中注册的数据只在这个插件中可见，见 [`Plugin`](super::Plugin)。
+    ///
     /// ```rust,ignore
     /// bot
     ///     .bot_data(Data::new(Config {
@@ -286,11 +568,23 @@ impl Bot {
     ///     });
     /// ```
     pub fn bot_data<U: Send + Sync + 'static>(self, ext: U) -> Self {
-        self.extensions.write().insert(ext);
+        match &self.plugin {
+            Some(plugin) => plugin.extensions.write().insert(ext),
+            None => self.extensions.write().insert(ext),
+        };
         self
     }
 }
 
+/// 串行模式下区分会话
+#[derive(Hash)]
+enum ConversationKey {
+    Group(QQ),
+    /// 临时会话，群号以及用户
+    Temp(QQ, QQ),
+    Private(QQ),
+}
+
 impl crate::msg_framework::FromRequest<Bot> for Bot {
     fn from_request(request: &crate::msg_framework::Request<Bot>) -> Option<Self> {
         Some(request.app.clone())
@@ -0,0 +1,718 @@
+//! 指令参数的解析
+//!
+//! 通过 [`Bot::command`] 注册的指令匹配之后，关键词之后的内容会被拆分成参数：
+//! 文字按照空白拆分，引号括起来的部分算作一个参数，at、图片等其他消息块各自算作一个参数。
+//! 使用 [`Args`] 提取器可以直接得到解析好的参数，解析失败时会自动回复用法。
+//! 只有一个参数时也可以直接使用 [`Arg`]、[`Optional`] 或者 [`Rest`] 作为提取器。
+use super::QQ;
+use crate::{
+    messages::{MessageBlock, MessageChain},
+    msg_framework::{FromRequest, Request},
+    Bot,
+};
+use parking_lot::Mutex;
+use std::{collections::VecDeque, fmt, ops::Deref};
+
+/// 匹配到的指令，由指令分发放入 [`Request::extensions`] 中
+pub struct CommandMatch {
+    /// 匹配到的关键词
+    pub command: String,
+    /// 关键词之后的内容
+    pub args: MessageChain,
+    /// 参数解析失败时需要回复的内容
+    rejection: Mutex<Option<String>>,
+}
+
+impl CommandMatch {
+    pub(crate) fn new(command: impl Into<String>, args: MessageChain) -> Self {
+        Self {
+            command: command.into(),
+            args,
+            rejection: Mutex::new(None),
+        }
+    }
+
+    /// 记录参数解析失败的原因，指令分发会把它回复给发送者
+    pub fn reject(&self, reply: impl Into<String>) {
+        *self.rejection.lock() = Some(reply.into());
+    }
+
+    pub(crate) fn is_rejected(&self) -> bool {
+        self.rejection.lock().is_some()
+    }
+
+    pub(crate) fn take_rejection(&self) -> Option<String> {
+        self.rejection.lock().take()
+    }
+}
+
+/// 参数解析失败的原因
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ArgError {
+    /// 缺少参数，包含参数类型的名字
+    Missing(&'static str),
+    /// 参数格式不对
+    Invalid {
+        expected: &'static str,
+        found: String,
+    },
+    /// 多余的参数
+    TooMany(String),
+}
+
+impl fmt::Display for ArgError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ArgError::Missing(name) => write!(f, "缺少参数 <{}>", name),
+            ArgError::Invalid { expected, found } => {
+                write!(f, "参数 `{}` 不是{}", found, expected)
+            }
+            ArgError::TooMany(rest) => write!(f, "多余的参数 `{}`", rest),
+        }
+    }
+}
+
+impl std::error::Error for ArgError {}
+
+/// 按顺序读取指令的参数
+pub struct ArgsParser {
+    blocks: VecDeque<MessageBlock>,
+}
+
+impl ArgsParser {
+    /// 从关键词之后的内容建立，Source 和引用回复会被忽略
+    pub fn new(args: &MessageChain) -> Self {
+        let blocks = args
+            .0
+            .iter()
+            .filter(|block| {
+                !matches!(
+                    block,
+                    MessageBlock::Source { .. } | MessageBlock::Quote { .. }
+                )
+            })
+            .cloned()
+            .collect();
+        Self { blocks }
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(MessageBlock::Text { text }) = self.blocks.front_mut() {
+            let trimmed = text.trim_start();
+            if trimmed.is_empty() {
+                self.blocks.pop_front();
+            } else {
+                if trimmed.len() != text.len() {
+                    *text = trimmed.to_string();
+                }
+                break;
+            }
+        }
+    }
+
+    /// 没有剩余的参数
+    pub fn is_empty(&mut self) -> bool {
+        self.skip_whitespace();
+        self.blocks.is_empty()
+    }
+
+    /// 读取下一个参数，文字参数会被拆分出来作为 [`MessageBlock::Text`]，其他消息块原样返回
+    pub fn next_token(&mut self) -> Option<MessageBlock> {
+        self.skip_whitespace();
+        let text = match self.blocks.pop_front()? {
+            MessageBlock::Text { text } => text,
+            block => return Some(block),
+        };
+        let (token, rest) = split_token(&text);
+        if !rest.is_empty() {
+            self.blocks.push_front(MessageBlock::text(rest));
+        }
+        Some(MessageBlock::text(token))
+    }
+
+    /// 把下一个参数解析为 `T`，格式不对时不会消耗这个参数
+    pub fn next_arg<T: FromArg>(&mut self) -> Result<T, ArgError> {
+        let saved = self.blocks.clone();
+        let token = self.next_token().ok_or(ArgError::Missing(T::NAME))?;
+        T::from_arg(&token).ok_or_else(|| {
+            self.blocks = saved;
+            ArgError::Invalid {
+                expected: T::NAME,
+                found: token.to_string(),
+            }
+        })
+    }
+
+    /// 取出剩余的全部内容
+    pub fn rest(&mut self) -> MessageChain {
+        self.skip_whitespace();
+        if let Some(MessageBlock::Text { text }) = self.blocks.back_mut() {
+            text.truncate(text.trim_end().len());
+        }
+        MessageChain(self.blocks.drain(..).collect())
+    }
+}
+
+/// 拆出第一个参数，返回参数和剩余的文字。`text` 的开头不能是空白。
+fn split_token(text: &str) -> (String, &str) {
+    let mut chars = text.chars();
+    let close = match chars.next() {
+        Some('"') => Some('"'),
+        Some('“') => Some('”'),
+        _ => None,
+    };
+    if let Some(close) = close {
+        let inner = chars.as_str();
+        if let Some(end) = inner.find(close) {
+            return (inner[..end].to_string(), &inner[end + close.len_utf8()..]);
+        }
+    }
+    let end = text.find(char::is_whitespace).unwrap_or(text.len());
+    (text[..end].to_string(), &text[end..])
+}
+
+/// 可以从单个参数解析的类型
+pub trait FromArg: Sized {
+    /// 类型在用法中显示的名字
+    const NAME: &'static str;
+
+    fn from_arg(token: &MessageBlock) -> Option<Self>;
+}
+
+macro_rules! impl_from_arg_for_num {
+    ($name:literal, $($t:ty),*) => {
+        $(
+            impl FromArg for $t {
+                const NAME: &'static str = $name;
+
+                fn from_arg(token: &MessageBlock) -> Option<Self> {
+                    match token {
+                        MessageBlock::Text { text } => text.parse().ok(),
+                        _ => None,
+                    }
+                }
+            }
+        )*
+    };
+}
+
+impl_from_arg_for_num!("整数", i8, i16, i32, i64, u8, u16, u32, u64, usize);
+impl_from_arg_for_num!("数字", f32, f64);
+
+impl FromArg for String {
+    const NAME: &'static str = "文字";
+
+    fn from_arg(token: &MessageBlock) -> Option<Self> {
+        match token {
+            MessageBlock::Text { text } => Some(text.clone()),
+            _ => None,
+        }
+    }
+}
+
+impl FromArg for bool {
+    const NAME: &'static str = "是/否";
+
+    fn from_arg(token: &MessageBlock) -> Option<Self> {
+        match token {
+            MessageBlock::Text { text } => MessageChain::from(text.as_str()).as_confirm(),
+            _ => None,
+        }
+    }
+}
+
+/// at 某人或者直接写 QQ 号
+impl FromArg for QQ {
+    const NAME: &'static str = "QQ号";
+
+    fn from_arg(token: &MessageBlock) -> Option<Self> {
+        match token {
+            MessageBlock::At { target, .. } => Some(*target),
+            MessageBlock::Text { text } => text.parse().ok(),
+            _ => None,
+        }
+    }
+}
+
+/// 任意一个参数
+impl FromArg for MessageBlock {
+    const NAME: &'static str = "消息";
+
+    fn from_arg(token: &MessageBlock) -> Option<Self> {
+        Some(token.clone())
+    }
+}
+
+/// 可以从剩余全部参数解析的类型，用于 [`Rest`]
+pub trait FromRest: Sized {
+    /// 类型在用法中显示的名字
+    const NAME: &'static str;
+
+    fn from_rest(rest: MessageChain) -> Result<Self, ArgError>;
+}
+
+/// 剩余的内容，非文字的消息块使用其显示的文字
+impl FromRest for String {
+    const NAME: &'static str = "文字";
+
+    fn from_rest(rest: MessageChain) -> Result<Self, ArgError> {
+        Ok(rest.0.iter().map(ToString::to_string).collect())
+    }
+}
+
+/// 剩余的内容，保留原样
+impl FromRest for MessageChain {
+    const NAME: &'static str = "消息";
+
+    fn from_rest(rest: MessageChain) -> Result<Self, ArgError> {
+        Ok(rest)
+    }
+}
+
+/// 剩余的每个参数都解析为 `T`
+impl<T: FromArg> FromRest for Vec<T> {
+    const NAME: &'static str = T::NAME;
+
+    fn from_rest(rest: MessageChain) -> Result<Self, ArgError> {
+        let mut parser = ArgsParser::new(&rest);
+        let mut args = Vec::new();
+        while !parser.is_empty() {
+            args.push(parser.next_arg()?);
+        }
+        Ok(args)
+    }
+}
+
+/// 可以从指令参数解析的类型，用于 [`Args`]。
+///
+/// 已经为 [`Arg`]、[`Optional`]、[`Rest`] 以及它们组成的元组实现，
+/// 也可以使用 [`command_args!`](crate::command_args) 为结构体生成实现。
+pub trait FromCommandArgs: Sized {
+    fn parse(parser: &mut ArgsParser) -> Result<Self, ArgError>;
+
+    /// 参数的用法，如 `<QQ号> [整数]`
+    fn usage() -> String;
+
+    /// 使用参数名 `name` 的用法，如 `<target>`，默认与 [`FromCommandArgs::usage`] 相同
+    fn named_usage(name: &str) -> String {
+        let _ = name;
+        Self::usage()
+    }
+}
+
+/// 必须提供的参数
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Arg<T>(pub T);
+
+/// 可以省略的参数，格式不对时视为省略
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Optional<T>(pub Option<T>);
+
+/// 剩余的全部参数，见 [`FromRest`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rest<T>(pub T);
+
+impl<T: FromArg> FromCommandArgs for Arg<T> {
+    fn parse(parser: &mut ArgsParser) -> Result<Self, ArgError> {
+        parser.next_arg().map(Arg)
+    }
+
+    fn usage() -> String {
+        format!("<{}>", T::NAME)
+    }
+
+    fn named_usage(name: &str) -> String {
+        format!("<{}>", name)
+    }
+}
+
+impl<T: FromArg> FromCommandArgs for Optional<T> {
+    fn parse(parser: &mut ArgsParser) -> Result<Self, ArgError> {
+        Ok(Optional(parser.next_arg().ok()))
+    }
+
+    fn usage() -> String {
+        format!("[{}]", T::NAME)
+    }
+
+    fn named_usage(name: &str) -> String {
+        format!("[{}]", name)
+    }
+}
+
+impl<T: FromRest> FromCommandArgs for Rest<T> {
+    fn parse(parser: &mut ArgsParser) -> Result<Self, ArgError> {
+        T::from_rest(parser.rest()).map(Rest)
+    }
+
+    fn usage() -> String {
+        format!("[{}...]", T::NAME)
+    }
+
+    fn named_usage(name: &str) -> String {
+        format!("[{}...]", name)
+    }
+}
+
+impl<T> Deref for Arg<T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> Deref for Optional<T> {
+    type Target = Option<T>;
+    fn deref(&self) -> &Option<T> {
+        &self.0
+    }
+}
+
+impl<T> Deref for Rest<T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+/// 没有参数
+impl FromCommandArgs for () {
+    fn parse(_parser: &mut ArgsParser) -> Result<Self, ArgError> {
+        Ok(())
+    }
+
+    fn usage() -> String {
+        String::new()
+    }
+}
+
+#[rustfmt::skip]
+mod _impl_from_command_args {
+    use super::*;
+
+    macro_rules! f {
+        ($($Ts:ident),*) => {
+            impl<$($Ts,)*> FromCommandArgs for ($($Ts,)*)
+            where
+                $($Ts: FromCommandArgs,)*
+            {
+                fn parse(parser: &mut ArgsParser) -> Result<Self, ArgError> {
+                    Ok(($($Ts::parse(parser)?,)*))
+                }
+
+                fn usage() -> String {
+                    let usages: Vec<String> = vec![$($Ts::usage()),*];
+                    join_usages(usages)
+                }
+            }
+        };
+    }
+
+    f!(T1);
+    f!(T1, T2);
+    f!(T1, T2, T3);
+    f!(T1, T2, T3, T4);
+    f!(T1, T2, T3, T4, T5);
+    f!(T1, T2, T3, T4, T5, T6);
+    f!(T1, T2, T3, T4, T5, T6, T7);
+    f!(T1, T2, T3, T4, T5, T6, T7, T8);
+}
+
+#[doc(hidden)]
+pub fn join_usages(usages: Vec<String>) -> String {
+    usages
+        .into_iter()
+        .filter(|usage| !usage.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// 为结构体实现 [`FromCommandArgs`]，字段按照顺序解析，用法中使用字段名。
+///
+/// # Example
+/// ```
+/// use miraie::prelude::*;
+/// use miraie::bot::FromCommandArgs;
+///
+/// miraie::command_args! {
+///     /// 禁言的参数
+///     pub struct Mute {
+///         pub target: Arg<QQ>,
+///         pub minutes: Optional<u32>,
+///     }
+/// }
+///
+/// assert_eq!(Mute::usage(), "<target> [minutes]");
+/// let Args(mute) = Args::<Mute>::parse(&MessageChain::new().at(QQ(123)).text(" 10")).unwrap();
+/// assert_eq!(*mute.target, QQ(123));
+/// assert_eq!(*mute.minutes, Some(10));
+/// ```
+#[macro_export]
+macro_rules! command_args {
+    (
+        $(#[$meta:meta])*
+        $vis:vis struct $name:ident {
+            $(
+                $(#[$field_meta:meta])*
+                $field_vis:vis $field:ident : $ty:ty
+            ),* $(,)?
+        }
+    ) => {
+        $(#[$meta])*
+        $vis struct $name {
+            $(
+                $(#[$field_meta])*
+                $field_vis $field: $ty,
+            )*
+        }
+
+        impl $crate::bot::FromCommandArgs for $name {
+            fn parse(
+                parser: &mut $crate::bot::ArgsParser,
+            ) -> ::std::result::Result<Self, $crate::bot::ArgError> {
+                ::std::result::Result::Ok(Self {
+                    $(
+                        $field: <$ty as $crate::bot::FromCommandArgs>::parse(parser)?,
+                    )*
+                })
+            }
+
+            fn usage() -> ::std::string::String {
+                let usages: ::std::vec::Vec<::std::string::String> = vec![
+                    $(
+                        <$ty as $crate::bot::FromCommandArgs>::named_usage(stringify!($field)),
+                    )*
+                ];
+                $crate::bot::join_usages(usages)
+            }
+        }
+    };
+}
+
+/// 提取器，把指令的参数解析为 `T`。
+///
+/// 只能用在 [`Bot::command`] 注册的指令中。解析失败时不会调用回调，而是自动回复错误原因和用法。
+///
+/// # Example
+/// ```no_run
+/// # use miraie::prelude::*;
+/// # tokio_test::block_on(async {
+/// # let (bot, _) = Bot::new("127.0.0.1", "secret", QQ(123456)).await.unwrap();
+/// // “禁言 @某人 10” 或者 “禁言 123456”
+/// let bot = bot.command(
+///     "禁言",
+///     |Args((target, minutes)): Args<(Arg<QQ>, Optional<u32>)>| async move {
+///         let minutes = minutes.unwrap_or(10);
+///         format!("禁言 {} {} 分钟", *target, minutes)
+///     },
+/// );
+/// # });
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Args<T>(pub T);
+
+impl<T: FromCommandArgs> Args<T> {
+    /// 解析关键词之后的内容，要求所有的参数都被用到
+    pub fn parse(args: &MessageChain) -> Result<Self, ArgError> {
+        let mut parser = ArgsParser::new(args);
+        let args = T::parse(&mut parser)?;
+        if !parser.is_empty() {
+            return Err(ArgError::TooMany(parser.rest().to_string()));
+        }
+        Ok(Args(args))
+    }
+}
+
+impl<T> Deref for Args<T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T: FromCommandArgs> FromRequest<Bot> for Args<T> {
+    fn from_request(request: &Request<Bot>) -> Option<Self> {
+        let matched = request.extensions.get::<CommandMatch>()?;
+        match Self::parse(&matched.args) {
+            Ok(args) => Some(args),
+            Err(e) => {
+                debug!("指令 `{}` 的参数解析失败：{}", matched.command, e);
+                let usage = format!("{} {}", matched.command, T::usage());
+                matched.reject(format!("{}\n用法：{}", e, usage.trim_end()));
+                None
+            }
+        }
+    }
+}
+
+/// 指令只有一个参数时可以直接作为提取器，与 `Args<(Arg<T>,)>` 相同。
+///
+/// 每个提取器都会从头解析全部的参数，有多个参数时请使用 [`Args`]。
+impl<T: FromArg> FromRequest<Bot> for Arg<T> {
+    fn from_request(request: &Request<Bot>) -> Option<Self> {
+        Args::<(Self,)>::from_request(request).map(|Args((arg,))| arg)
+    }
+}
+
+/// 与 `Args<(Optional<T>,)>` 相同，见 [`Arg`] 的提取器
+impl<T: FromArg> FromRequest<Bot> for Optional<T> {
+    fn from_request(request: &Request<Bot>) -> Option<Self> {
+        Args::<(Self,)>::from_request(request).map(|Args((arg,))| arg)
+    }
+}
+
+/// 与 `Args<(Rest<T>,)>` 相同，见 [`Arg`] 的提取器
+impl<T: FromRest> FromRequest<Bot> for Rest<T> {
+    fn from_request(request: &Request<Bot>) -> Option<Self> {
+        Args::<(Self,)>::from_request(request).map(|Args((arg,))| arg)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        messages::{GroupMessage, Message},
+        msg_framework::App,
+        test_utils::{group_message, mock_bot},
+    };
+    use std::time::Duration;
+
+    fn tokens(chain: MessageChain) -> Vec<MessageBlock> {
+        let mut parser = ArgsParser::new(&chain);
+        std::iter::from_fn(|| parser.next_token()).collect()
+    }
+
+    #[test]
+    fn test_tokenize() {
+        assert_eq!(
+            tokens(
+                MessageChain::new()
+                    .text("  a \"b c\" “d e”")
+                    .at(QQ(1))
+                    .text("f\"g \"h")
+            ),
+            vec![
+                MessageBlock::text("a"),
+                MessageBlock::text("b c"),
+                MessageBlock::text("d e"),
+                MessageBlock::at(QQ(1)),
+                MessageBlock::text("f\"g"),
+                MessageBlock::text("\"h"),
+            ]
+        );
+        assert!(tokens(MessageChain::new().text("   ")).is_empty());
+    }
+
+    #[test]
+    fn test_parse_args() {
+        type Ban = (Arg<QQ>, Optional<u32>, Rest<String>);
+        assert_eq!(<Ban as FromCommandArgs>::usage(), "<QQ号> [整数] [文字...]");
+
+        let Args((target, minutes, reason)) = Args::<Ban>::parse(
+            &MessageChain::new()
+                .text(" ")
+                .at(QQ(1))
+                .text(" 10 刷屏 太多"),
+        )
+        .unwrap();
+        assert_eq!(target.0, QQ(1));
+        assert_eq!(minutes.0, Some(10));
+        assert_eq!(reason.0, "刷屏 太多");
+
+        let Args((target, minutes, reason)) =
+            Args::<Ban>::parse(&MessageChain::new().text("123 刷屏")).unwrap();
+        assert_eq!(target.0, QQ(123));
+        assert_eq!(minutes.0, None);
+        assert_eq!(reason.0, "刷屏");
+
+        assert_eq!(
+            Args::<Ban>::parse(&MessageChain::new()),
+            Err(ArgError::Missing("QQ号"))
+        );
+        assert_eq!(
+            Args::<Ban>::parse(&MessageChain::new().text("abc")),
+            Err(ArgError::Invalid {
+                expected: "QQ号",
+                found: "abc".to_string()
+            })
+        );
+        assert_eq!(
+            Args::<(Arg<u32>,)>::parse(&MessageChain::new().text("1 2")),
+            Err(ArgError::TooMany("2".to_string()))
+        );
+        assert_eq!(
+            Args::<Rest<Vec<u32>>>::parse(&MessageChain::new().text("1 2 3"))
+                .unwrap()
+                .0
+                 .0,
+            vec![1, 2, 3]
+        );
+        assert!(Args::<()>::parse(&MessageChain::new().text("  ")).is_ok());
+    }
+
+    crate::command_args! {
+        struct Roll {
+            times: Optional<u32>,
+            sides: Arg<u32>,
+        }
+    }
+
+    #[test]
+    fn test_command_args_macro() {
+        assert_eq!(Roll::usage(), "[times] <sides>");
+        let Args(roll) = Args::<Roll>::parse(&MessageChain::new().text("3 6")).unwrap();
+        assert_eq!((roll.times.0, roll.sides.0), (Some(3), 6));
+        // 可选参数会优先使用
+        assert!(matches!(
+            Args::<Roll>::parse(&MessageChain::new().text("6")),
+            Err(ArgError::Missing("整数"))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_single_arg_extractors() {
+        let (bot, sent) = mock_bot(|_, _| None);
+        bot.clone()
+            .command("踢", |_: GroupMessage, Arg(target): Arg<QQ>| async move {
+                format!("踢 {}", target)
+            })
+            .command(
+                "骰子",
+                |_: GroupMessage, Optional(sides): Optional<u32>| async move {
+                    format!("骰子 {}", sides.unwrap_or(6))
+                },
+            )
+            .command(
+                "复读",
+                |_: GroupMessage, Rest(text): Rest<String>| async move { format!("复读 {}", text) },
+            );
+        let send = |text: &str| {
+            bot.event_bus()
+                .send(Message::Group(group_message(100, 10, text)))
+                .unwrap();
+        };
+        for text in [
+            "踢 123",
+            "踢",
+            "骰子",
+            "骰子 20",
+            "骰子 二十",
+            "复读 你好 世界",
+        ] {
+            send(text);
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert_eq!(
+            sent.take(),
+            vec![
+                "踢 123",
+                "缺少参数 <QQ号>\n用法：踢 <QQ号>",
+                "骰子 6",
+                "骰子 20",
+                "多余的参数 `二十`\n用法：骰子 [整数]",
+                "复读 你好 世界",
+            ]
+        );
+    }
+}
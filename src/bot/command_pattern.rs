@@ -0,0 +1,251 @@
+//! 通过正则表达式或者模式注册的指令
+use regex::{CaptureLocations, Regex, RegexBuilder};
+use std::str::FromStr;
+
+use crate::{
+    msg_framework::{FromRequest, Request},
+    Bot,
+};
+
+/// 通过 [`Bot::command_regex`] 或 [`Bot::command_pattern`] 注册的指令匹配到的内容，可以在回调中直接提取。
+///
+/// # Example
+/// ```no_run
+/// # use miraie::prelude::*;
+/// use miraie::bot::RegexMatch;
+/// # tokio_test::block_on(async {
+/// # let (bot, _) = Bot::new("127.0.0.1", "secret", QQ(123456)).await.unwrap();
+/// bot.command_pattern("roll {n:u32}d{sides:u32}", |m: RegexMatch| async move {
+///     let n: u32 = m.parse("n").unwrap();
+///     let sides: u32 = m.parse("sides").unwrap();
+///     format!("投掷 {} 个 {} 面骰子", n, sides)
+/// });
+/// # });
+/// ```
+#[derive(Debug, Clone)]
+pub struct RegexMatch {
+    regex: Regex,
+    text: String,
+    /// 匹配时记录下的捕获组的位置，提取时不需要再匹配一次
+    locations: CaptureLocations,
+}
+
+impl RegexMatch {
+    /// 用来匹配的文字，即消息中所有的文字
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// 按顺序排列的所有捕获组，第一个是整个匹配到的文字，没有参与匹配的捕获组为 `None`
+    pub fn groups(&self) -> impl Iterator<Item = Option<&str>> + '_ {
+        (0..self.locations.len()).map(move |i| self.get(i))
+    }
+
+    /// 第 `i` 个捕获组，0 表示整个匹配到的文字
+    pub fn get(&self, i: usize) -> Option<&str> {
+        let (start, end) = self.locations.get(i)?;
+        Some(&self.text[start..end])
+    }
+
+    /// 具名捕获组
+    pub fn name(&self, name: &str) -> Option<&str> {
+        let i = self.regex.capture_names().position(|n| n == Some(name))?;
+        self.get(i)
+    }
+
+    /// 把具名捕获组解析为 `T`，捕获组不存在或者解析失败时返回 `None`。
+    ///
+    /// 通过 [`Bot::command_pattern`] 注册时，带类型的捕获组在匹配时已经检查过可以解析。
+    pub fn parse<T: FromStr>(&self, name: &str) -> Option<T> {
+        self.name(name)?.parse().ok()
+    }
+}
+
+impl FromRequest<Bot> for RegexMatch {
+    fn from_request(request: &Request<Bot>) -> Option<Self> {
+        request.extensions.get::<RegexMatch>().cloned()
+    }
+}
+
+/// 检查捕获组能否解析成对应的类型
+type Check = fn(&str) -> bool;
+
+fn check<T: FromStr>(s: &str) -> bool {
+    s.parse::<T>().is_ok()
+}
+
+/// 正则表达式指令的匹配器
+pub(crate) struct CommandRegex {
+    regex: Regex,
+    checks: Vec<(String, Check)>,
+    /// 注册时的模式或者正则表达式，用于展示
+    source: String,
+}
+
+impl CommandRegex {
+    pub fn from_regex(regex: Regex) -> Self {
+        Self {
+            source: regex.as_str().to_string(),
+            regex,
+            checks: Vec::new(),
+        }
+    }
+
+    /// 注册时的模式或者正则表达式
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// 把 `roll {n:u32}d{sides:u32}` 这样的模式编译成正则表达式。
+    ///
+    /// - `{name}`、`{name:str}` 匹配任意文字；
+    /// - `{name:word}` 匹配不含空白的文字；
+    /// - `{name:u32}` 等整数和浮点数类型匹配数字，并且要求能够解析成对应的类型；
+    /// - 空白匹配任意个数的空白，`{{` 和 `}}` 分别表示 `{` 和 `}`，其他的字符原样匹配。
+    pub fn from_pattern(pattern: &str) -> Result<Self, String> {
+        let mut re = String::from(r"^\s*");
+        let mut checks = Vec::new();
+        let mut chars = pattern.trim().chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    re.push_str(r"\{");
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    re.push_str(r"\}");
+                }
+                '{' => {
+                    let mut capture = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => capture.push(c),
+                            None => return Err(format!("模式 `{}` 中的 `{{` 没有闭合", pattern)),
+                        }
+                    }
+                    let (name, ty) = match capture.split_once(':') {
+                        Some((name, ty)) => (name.trim(), ty.trim()),
+                        None => (capture.trim(), "str"),
+                    };
+                    let (capture_re, capture_check) = capture_kind(ty)
+                        .ok_or_else(|| format!("模式 `{}` 中有未知的类型 `{}`", pattern, ty))?;
+                    re.push_str(&format!("(?P<{}>{})", name, capture_re));
+                    if let Some(capture_check) = capture_check {
+                        checks.push((name.to_string(), capture_check));
+                    }
+                }
+                '}' => return Err(format!("模式 `{}` 中的 `}}` 没有对应的 `{{`", pattern)),
+                c if c.is_whitespace() => {
+                    while chars.peek().is_some_and(|c| c.is_whitespace()) {
+                        chars.next();
+                    }
+                    re.push_str(r"\s*");
+                }
+                c => re.push_str(&regex::escape(c.encode_utf8(&mut [0; 4]))),
+            }
+        }
+        re.push_str(r"\s*$");
+        let regex = Regex::new(&re).map_err(|e| format!("模式 `{}` 无效：{}", pattern, e))?;
+        Ok(Self {
+            regex,
+            checks,
+            source: pattern.trim().to_string(),
+        })
+    }
+
+    /// 匹配时忽略大小写
+    pub fn ignore_case(&mut self) {
+        self.regex = RegexBuilder::new(self.regex.as_str())
+            .case_insensitive(true)
+            .build()
+            .expect("已经编译过的正则表达式");
+    }
+
+    pub fn matches(&self, text: &str) -> Option<RegexMatch> {
+        let mut locations = self.regex.capture_locations();
+        self.regex.captures_read(&mut locations, text)?;
+        let matched = RegexMatch {
+            regex: self.regex.clone(),
+            text: text.to_string(),
+            locations,
+        };
+        let valid = self
+            .checks
+            .iter()
+            .all(|(name, check)| matched.name(name).is_none_or(check));
+        valid.then_some(matched)
+    }
+}
+
+/// 模式中的类型对应的正则表达式以及检查
+fn capture_kind(ty: &str) -> Option<(&'static str, Option<Check>)> {
+    const UNSIGNED: &str = r"\+?\d+";
+    const SIGNED: &str = r"[+-]?\d+";
+    const FLOAT: &str = r"[+-]?(?:\d+(?:\.\d*)?|\.\d+)";
+    Some(match ty {
+        "" | "str" | "String" => (r".+?", None),
+        "word" => (r"\S+", None),
+        "u8" => (UNSIGNED, Some(check::<u8> as Check)),
+        "u16" => (UNSIGNED, Some(check::<u16>)),
+        "u32" => (UNSIGNED, Some(check::<u32>)),
+        "u64" => (UNSIGNED, Some(check::<u64>)),
+        "usize" => (UNSIGNED, Some(check::<usize>)),
+        "i8" => (SIGNED, Some(check::<i8>)),
+        "i16" => (SIGNED, Some(check::<i16>)),
+        "i32" => (SIGNED, Some(check::<i32>)),
+        "i64" => (SIGNED, Some(check::<i64>)),
+        "isize" => (SIGNED, Some(check::<isize>)),
+        "f32" => (FLOAT, Some(check::<f32>)),
+        "f64" => (FLOAT, Some(check::<f64>)),
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pattern() {
+        let roll = CommandRegex::from_pattern("roll {n:u32}d{sides:u32}").unwrap();
+        let m = roll.matches(" roll 3d6 ").unwrap();
+        assert_eq!(m.parse::<u32>("n"), Some(3));
+        assert_eq!(m.parse::<u32>("sides"), Some(6));
+        assert!(roll.matches("roll3d6").is_some());
+        assert!(roll.matches("roll 3d").is_none());
+        assert!(roll.matches("roll -1d6").is_none());
+        assert!(roll.matches("roll 99999999999d6").is_none());
+        assert!(roll.matches("ROLL 1d6").is_none());
+
+        let what = CommandRegex::from_pattern("{thing}是什么{{?}}").unwrap();
+        let m = what.matches("嘉然是什么{?}").unwrap();
+        assert_eq!(m.name("thing"), Some("嘉然"));
+        assert!(what.matches("是什么{?}").is_none());
+
+        let mut word = CommandRegex::from_pattern("Echo {a:word} {b:f64}").unwrap();
+        word.ignore_case();
+        let m = word.matches("echo hello -1.5").unwrap();
+        assert_eq!(m.name("a"), Some("hello"));
+        assert_eq!(m.parse::<f64>("b"), Some(-1.5));
+
+        assert!(CommandRegex::from_pattern("{n:u128}").is_err());
+        assert!(CommandRegex::from_pattern("{n").is_err());
+        assert!(CommandRegex::from_pattern("n}").is_err());
+    }
+
+    #[test]
+    fn test_regex() {
+        let regex = CommandRegex::from_regex(Regex::new(r"(\d+)\s*\+\s*(\d+)").unwrap());
+        let m = regex.matches("算一下 1 + 2").unwrap();
+        assert_eq!(m.get(0), Some("1 + 2"));
+        assert_eq!(m.get(2), Some("2"));
+        assert_eq!(m.get(3), None);
+        assert_eq!(
+            m.groups().collect::<Vec<_>>(),
+            vec![Some("1 + 2"), Some("1"), Some("2")]
+        );
+        assert_eq!(m.text(), "算一下 1 + 2");
+    }
+}
@@ -0,0 +1,103 @@
+//! 指令关键词的前缀树
+use std::collections::HashMap;
+
+/// 关键词前缀树，边是关键词转为小写后的字符，节点上记录以此结尾的关键词对应的值。
+///
+/// 查找时同样把输入转为小写，是否需要区分大小写由调用者根据 [`CommandTrie::prefixes`] 返回的前缀自行判断。
+pub(crate) struct CommandTrie<T> {
+    root: Node<T>,
+}
+
+struct Node<T> {
+    children: HashMap<char, Node<T>>,
+    values: Vec<T>,
+}
+
+impl<T> Default for Node<T> {
+    fn default() -> Self {
+        Self {
+            children: HashMap::new(),
+            values: Vec::new(),
+        }
+    }
+}
+
+impl<T> Default for CommandTrie<T> {
+    fn default() -> Self {
+        Self {
+            root: Node::default(),
+        }
+    }
+}
+
+impl<T: PartialEq> CommandTrie<T> {
+    /// 插入关键词，同一个关键词上重复插入相同的值不会有效果
+    pub fn insert(&mut self, keyword: &str, value: T) {
+        let mut node = &mut self.root;
+        for c in keyword.chars().flat_map(char::to_lowercase) {
+            node = node.children.entry(c).or_default();
+        }
+        if !node.values.contains(&value) {
+            node.values.push(value);
+        }
+    }
+
+    /// 删除关键词上的值
+    pub fn remove(&mut self, keyword: &str, value: &T) {
+        let mut node = &mut self.root;
+        for c in keyword.chars().flat_map(char::to_lowercase) {
+            node = match node.children.get_mut(&c) {
+                Some(child) => child,
+                None => return,
+            };
+        }
+        node.values.retain(|v| v != value);
+    }
+
+    /// 找出 `text` 中所有是关键词的前缀，按照从长到短的顺序返回前缀的字节长度以及对应的值
+    pub fn prefixes(&self, text: &str) -> Vec<(usize, &[T])> {
+        let mut found = Vec::new();
+        let mut node = &self.root;
+        'outer: for (i, c) in text.char_indices() {
+            for lower in c.to_lowercase() {
+                node = match node.children.get(&lower) {
+                    Some(child) => child,
+                    None => break 'outer,
+                };
+            }
+            if !node.values.is_empty() {
+                found.push((i + c.len_utf8(), &node.values[..]));
+            }
+        }
+        found.reverse();
+        found
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_trie_prefixes() {
+        let mut trie = CommandTrie::default();
+        trie.insert("帮助", 1);
+        trie.insert("帮助 详细", 2);
+        trie.insert("Help", 3);
+        trie.insert("help", 3);
+        trie.insert("help", 4);
+
+        assert_eq!(
+            trie.prefixes("帮助 详细 指令"),
+            vec![(13, &[2][..]), (6, &[1][..])]
+        );
+        assert_eq!(trie.prefixes("帮助 简略"), vec![(6, &[1][..])]);
+        assert_eq!(trie.prefixes("HELP me"), vec![(4, &[3, 4][..])]);
+        assert!(trie.prefixes("帮").is_empty());
+        assert!(trie.prefixes("").is_empty());
+
+        trie.remove("HELP", &3);
+        assert_eq!(trie.prefixes("help"), vec![(4, &[4][..])]);
+        trie.remove("不存在", &3);
+    }
+}
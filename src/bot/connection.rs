@@ -8,7 +8,7 @@ use futures::{sink::SinkExt, stream::StreamExt};
 use serde::Deserialize;
 use serde_json::Value;
 use std::sync::atomic::AtomicI64;
-use tokio::sync::{broadcast, mpsc};
+use tokio::sync::{broadcast, mpsc, watch};
 
 pub static SYNC_ID: AtomicI64 = AtomicI64::new(10);
 
@@ -36,6 +36,9 @@ pub struct Connection {
 
     /// 用来消除掉接收到的第一个 packet 的 warning
     inited: bool,
+
+    /// 连接关闭（被丢弃）时通知 bot，定时任务等随之停止
+    _closed: watch::Sender<()>,
 }
 
 /// 从 mirai 接收到的 ws 包
@@ -55,6 +58,7 @@ impl Connection {
         request_receive: mpsc::Receiver<(i64, Box<dyn ApiRequest>)>,
         message_channel: broadcast::Sender<Message>,
         response_channel: broadcast::Sender<(i64, Value)>,
+        closed: watch::Sender<()>,
     ) -> Self {
         let (write, read) = ws.split();
         Self {
@@ -67,6 +71,8 @@ impl Connection {
             read,
 
             inited: false,
+
+            _closed: closed,
         }
     }
 
@@ -49,8 +49,6 @@ impl<T: ?Sized> From<Arc<T>> for Data<T> {
 
 impl<T: ?Sized + 'static> FromRequest<Bot> for Data<T> {
     fn from_request(request: &crate::msg_framework::Request<Bot>) -> Option<Self> {
-        let app = &request.app;
-        let ext = app.extensions.read();
-        ext.get::<Data<T>>().cloned()
+        request.app.data::<Data<T>>()
     }
 }
@@ -0,0 +1,275 @@
+//! handler 出错时的处理，包括 handler 返回的错误以及 handler 和中间件中的 panic。
+//!
+//! 通过 [`Bot::on_error`] 注册，可以使用闭包自定义处理方式，也可以使用内置的 [`ErrorNotifier`]
+//! 回复一条友好的错误消息，或者把错误私聊发送给管理员。
+use std::{fmt, future::Future, sync::Arc};
+
+use super::QQ;
+use crate::{
+    messages::{Conversation, Message, MessageChain},
+    msg_framework::{catch_panic, Middleware, Next, Outcome, Request},
+    App, Bot,
+};
+
+/// handler 执行失败的原因
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HandlerError {
+    /// handler 返回了错误，内容是错误的描述
+    Failed(String),
+    /// handler panic 了，内容是 panic 的信息
+    Panicked(String),
+}
+
+impl HandlerError {
+    fn from_outcome(outcome: &Outcome) -> Option<Self> {
+        match outcome {
+            Outcome::Failed(e) => Some(HandlerError::Failed(e.clone())),
+            Outcome::Panicked(e) => Some(HandlerError::Panicked(e.clone())),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for HandlerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HandlerError::Failed(e) => write!(f, "handler 返回了错误: {}", e),
+            HandlerError::Panicked(e) => write!(f, "handler panic: {}", e),
+        }
+    }
+}
+
+/// 接收 handler 的错误以及触发错误的请求，见 [`Bot::on_error`]
+#[async_trait]
+pub trait ErrorSink: Send + Sync + 'static {
+    async fn report(&self, error: HandlerError, request: Request<Bot>);
+}
+
+#[async_trait]
+impl<F, Fut> ErrorSink for F
+where
+    F: Fn(HandlerError, Request<Bot>) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = ()> + Send,
+{
+    async fn report(&self, error: HandlerError, request: Request<Bot>) {
+        self(error, request).await
+    }
+}
+
+/// 内置的错误处理：回复一条友好的错误消息，并且/或者把错误私聊发送给管理员
+///
+/// ```no_run
+/// # use miraie::prelude::*;
+/// use miraie::bot::ErrorNotifier;
+/// # tokio_test::block_on(async {
+/// # let (bot, _) = Bot::new("127.0.0.1", "secret", QQ(123456)).await.unwrap();
+/// bot.on_error(
+///     ErrorNotifier::new()
+///         .reply("出错了，请稍后再试")
+///         .notify_admin(QQ(10000)),
+/// );
+/// # });
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct ErrorNotifier {
+    reply: Option<MessageChain>,
+    admins: Vec<QQ>,
+}
+
+impl ErrorNotifier {
+    /// 什么都不做的通知，通过 [`ErrorNotifier::reply`] 和 [`ErrorNotifier::notify_admin`] 设置
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 在出错的群聊或者私聊中回复这条消息
+    pub fn reply(mut self, message: impl Into<MessageChain>) -> Self {
+        self.reply = Some(message.into());
+        self
+    }
+
+    /// 把错误以及触发错误的消息私聊发送给管理员，可以多次调用添加多个管理员
+    pub fn notify_admin(mut self, admin: QQ) -> Self {
+        self.admins.push(admin);
+        self
+    }
+}
+
+#[async_trait]
+impl ErrorSink for ErrorNotifier {
+    async fn report(&self, error: HandlerError, request: Request<Bot>) {
+        let bot = &request.app;
+        if let Some(reply) = &self.reply {
+            let reply = reply.clone();
+            let response = match &request.message {
+                Message::Friend(f) => Some(f.reply(reply, bot).await),
+                Message::Group(g) => Some(g.reply(reply, bot).await),
+                _ => None,
+            };
+            if let Some(Err(e)) = response {
+                warn!("failed to reply error message: {}", e);
+            }
+        }
+        if self.admins.is_empty() {
+            return;
+        }
+        let source = match &request.message {
+            Message::Friend(f) => format!("好友 {}", f.sender.id),
+            Message::Group(g) => format!("群 {} 的 {}", g.sender.group.id, g.sender.id),
+            Message::Temp(t) => format!("临时会话 {}", t.sender.id),
+            Message::Stranger(s) => format!("陌生人 {}", s.sender.id),
+            Message::Event(_) => "事件".to_string(),
+            Message::Scheduled(event) => match &event.name {
+                Some(name) => format!("定时任务 {}", name),
+                None => "定时任务".to_string(),
+            },
+        };
+        let content = request
+            .message
+            .message_chain()
+            .map(|chain| chain.plain_text())
+            .unwrap_or_default();
+        let text = format!("{}\n来自: {}\n消息: {}", error, source, content);
+        for admin in &self.admins {
+            if let Err(e) = bot.send_friend_message(*admin, text.clone()).await {
+                warn!("failed to notify admin {}: {}", admin, e);
+            }
+        }
+    }
+}
+
+/// 位于所有全局中间件的最外层，把 handler 的错误以及内层中间件的 panic 交给 [`ErrorSink`]
+struct ErrorReporter(Arc<dyn ErrorSink>);
+
+#[async_trait]
+impl Middleware<Bot> for ErrorReporter {
+    async fn handle(&self, request: Request<Bot>, next: Next<'_, Bot>) -> Outcome {
+        let origin = request.clone();
+        let outcome = catch_panic(next.run(request))
+            .await
+            .unwrap_or_else(|outcome| outcome);
+        if let Some(error) = HandlerError::from_outcome(&outcome) {
+            self.0.report(error, origin).await;
+        }
+        outcome
+    }
+}
+
+impl Bot {
+    /// 注册一个错误处理，handler 和指令返回错误或者 panic 时会收到错误以及触发错误的请求。
+    /// handler 以及在它之后注册的中间件中的 panic 都会被捕获，不会影响其他的 handler。
+    ///
+    /// 可以注册多个错误处理，它们都会收到错误。内置的处理方式见 [`ErrorNotifier`]。
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use miraie::prelude::*;
+    /// use miraie::{bot::HandlerError, msg_framework::Request};
+    /// # tokio_test::block_on(async {
+    /// # let (bot, _) = Bot::new("127.0.0.1", "secret", QQ(123456)).await.unwrap();
+    /// bot.on_error(|err: HandlerError, request: Request<Bot>| async move {
+    ///     eprintln!("{} when handling {:?}", err, request.message);
+    /// })
+    /// // handler 中的 panic 也会交给错误处理
+    /// .command("崩溃", |_: GroupMessage| async { None::<String>.unwrap() });
+    /// # });
+    /// ```
+    pub fn on_error(self, sink: impl ErrorSink) -> Self {
+        // 放在最外层，内层的中间件和 handler 都在它捕获 panic 的范围内
+        self.middlewares().push_front(ErrorReporter(Arc::new(sink)));
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{messages::FriendMessage, test_utils::friend_message};
+    use parking_lot::Mutex;
+
+    #[tokio::test]
+    async fn test_on_error() {
+        let errors = Arc::new(Mutex::new(Vec::new()));
+        let sink = errors.clone();
+        let bot = Bot::mock(QQ(1)).on_error(move |err, request: Request<Bot>| {
+            let sink = sink.clone();
+            async move {
+                let text = request.message.message_chain().unwrap().plain_text();
+                sink.lock().push((err, text));
+            }
+        });
+        bot.clone().handler(|msg: FriendMessage| async move {
+            match msg.message.plain_text().as_str() {
+                "panic" => panic!("boom"),
+                "fail" => Err("failed"),
+                _ => Ok(()),
+            }
+        });
+
+        let event_bus = bot.event_bus();
+        for text in ["ok", "fail", "panic"] {
+            event_bus
+                .send(Message::Friend(friend_message(10, text)))
+                .unwrap();
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        assert_eq!(
+            *errors.lock(),
+            vec![
+                (
+                    HandlerError::Failed("failed".to_string()),
+                    "fail".to_string()
+                ),
+                (
+                    HandlerError::Panicked("boom".to_string()),
+                    "panic".to_string()
+                ),
+            ]
+        );
+    }
+
+    /// 处理到某条消息时 panic 的中间件
+    struct PanicOn(&'static str);
+
+    #[async_trait]
+    impl Middleware<Bot> for PanicOn {
+        async fn handle(&self, request: Request<Bot>, next: Next<'_, Bot>) -> Outcome {
+            let text = request.message.message_chain().unwrap().plain_text();
+            if text == self.0 {
+                panic!("middleware boom");
+            }
+            next.run(request).await
+        }
+    }
+
+    #[tokio::test]
+    async fn test_on_error_catches_middleware_panic() {
+        let errors = Arc::new(Mutex::new(Vec::new()));
+        let sink = errors.clone();
+        let bot = Bot::mock(QQ(1))
+            .on_error(move |err, _: Request<Bot>| {
+                let sink = sink.clone();
+                async move { sink.lock().push(err) }
+            })
+            .middleware(PanicOn("panic"));
+        let handled = Arc::new(Mutex::new(Vec::new()));
+        let record = handled.clone();
+        bot.clone().handler(move |msg: FriendMessage| {
+            let record = record.clone();
+            async move { record.lock().push(msg.message.plain_text()) }
+        });
+
+        for text in ["panic", "ok"] {
+            bot.event_bus()
+                .send(Message::Friend(friend_message(10, text)))
+                .unwrap();
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        assert_eq!(
+            *errors.lock(),
+            vec![HandlerError::Panicked("middleware boom".to_string())]
+        );
+        // 中间件 panic 之后仍然可以处理之后的消息
+        assert_eq!(*handled.lock(), vec!["ok".to_string()]);
+    }
+}
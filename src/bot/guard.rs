@@ -0,0 +1,221 @@
+//! 指令的守卫，在执行指令前检查消息是否满足条件，如发送者是否是管理员、是否在指定的群里等。
+//!
+//! 守卫可以通过 [`Guard::and`]、[`Guard::or`]、[`Guard::not`] 组合，
+//! 通过 [`Command::guard`](super::Command::guard) 注册到指令上，不满足条件的消息会被忽略。
+//!
+//! # Example
+//! ```no_run
+//! # use miraie::prelude::*;
+//! use miraie::{bot::guard::*, msg_framework::Request};
+//! # tokio_test::block_on(async {
+//! # let (bot, _) = Bot::new("127.0.0.1", "secret", QQ(123456)).await.unwrap();
+//! bot.command("踢", |_: GroupMessage| async { "..." })
+//!     .guard(is_admin().and(in_groups([QQ(111), QQ(222)])))
+//!     // 也可以用闭包自定义检查
+//!     .command("私聊", |_: Message| async { "..." })
+//!     .guard(private_only().or(|req: &Request<Bot>| matches!(req.message, Message::Temp(_))));
+//! # });
+//! ```
+use std::collections::HashSet;
+
+use super::QQ;
+use crate::{
+    messages::{group::Permission, Message},
+    msg_framework::{Middleware, Next, Outcome, Request},
+    Bot,
+};
+
+/// 守卫，检查一个请求是否可以交给 handler 处理
+pub trait Guard: Send + Sync + 'static {
+    /// 返回 `true` 时请求才会交给 handler 处理
+    fn check(&self, request: &Request<Bot>) -> bool;
+
+    /// 两个守卫都满足
+    fn and<G: Guard>(self, other: G) -> And<Self, G>
+    where
+        Self: Sized,
+    {
+        And(self, other)
+    }
+
+    /// 满足任意一个守卫
+    fn or<G: Guard>(self, other: G) -> Or<Self, G>
+    where
+        Self: Sized,
+    {
+        Or(self, other)
+    }
+
+    /// 不满足守卫
+    fn not(self) -> Not<Self>
+    where
+        Self: Sized,
+    {
+        Not(self)
+    }
+}
+
+impl<F> Guard for F
+where
+    F: Fn(&Request<Bot>) -> bool + Send + Sync + 'static,
+{
+    fn check(&self, request: &Request<Bot>) -> bool {
+        self(request)
+    }
+}
+
+/// 见 [`Guard::and`]
+pub struct And<A, B>(A, B);
+
+impl<A: Guard, B: Guard> Guard for And<A, B> {
+    fn check(&self, request: &Request<Bot>) -> bool {
+        self.0.check(request) && self.1.check(request)
+    }
+}
+
+/// 见 [`Guard::or`]
+pub struct Or<A, B>(A, B);
+
+impl<A: Guard, B: Guard> Guard for Or<A, B> {
+    fn check(&self, request: &Request<Bot>) -> bool {
+        self.0.check(request) || self.1.check(request)
+    }
+}
+
+/// 见 [`Guard::not`]
+pub struct Not<A>(A);
+
+impl<A: Guard> Guard for Not<A> {
+    fn check(&self, request: &Request<Bot>) -> bool {
+        !self.0.check(request)
+    }
+}
+
+/// 把守卫当作中间件使用，不满足条件时不再继续处理请求。
+///
+/// 可以通过 [`App::middleware`](crate::App::middleware) 注册为全局的守卫。
+pub struct GuardMiddleware<G>(pub G);
+
+#[async_trait]
+impl<G: Guard> Middleware<Bot> for GuardMiddleware<G> {
+    async fn handle(&self, request: Request<Bot>, next: Next<'_, Bot>) -> Outcome {
+        if self.0.check(&request) {
+            next.run(request).await
+        } else {
+            Outcome::Skipped
+        }
+    }
+}
+
+/// 群消息的发送者至少有 `permission` 的权限
+pub(crate) fn has_permission(request: &Request<Bot>, permission: Permission) -> bool {
+    match &request.message {
+        Message::Group(msg) => msg.sender.permission >= permission,
+        _ => false,
+    }
+}
+
+/// 群消息的发送者是管理员或者群主
+pub fn is_admin() -> impl Guard {
+    |request: &Request<Bot>| has_permission(request, Permission::Administrator)
+}
+
+/// 群消息的发送者是群主
+pub fn is_owner() -> impl Guard {
+    |request: &Request<Bot>| has_permission(request, Permission::Owner)
+}
+
+/// 群消息来自这些群
+pub fn in_groups<T: Into<QQ>>(groups: impl IntoIterator<Item = T>) -> impl Guard {
+    let groups: HashSet<QQ> = groups.into_iter().map(Into::into).collect();
+    move |request: &Request<Bot>| match &request.message {
+        Message::Group(msg) => groups.contains(&msg.sender.group.id),
+        _ => false,
+    }
+}
+
+/// 消息（包括私聊）的发送者是这些用户
+pub fn from_users<T: Into<QQ>>(users: impl IntoIterator<Item = T>) -> impl Guard {
+    let users: HashSet<QQ> = users.into_iter().map(Into::into).collect();
+    move |request: &Request<Bot>| {
+        let sender = match &request.message {
+            Message::Friend(msg) => msg.sender.id,
+            Message::Group(msg) => msg.sender.id,
+            Message::Temp(msg) => msg.sender.id,
+            Message::Stranger(msg) => msg.sender.id,
+            Message::Event(_) | Message::Scheduled(_) => return false,
+        };
+        users.contains(&sender)
+    }
+}
+
+/// 群消息 at 了机器人，at 全体成员不算
+pub fn at_me() -> impl Guard {
+    |request: &Request<Bot>| match &request.message {
+        Message::Group(msg) => msg.message.mentions(request.app.qq()),
+        _ => false,
+    }
+}
+
+/// 私聊消息，包括好友、临时会话和陌生人消息
+pub fn private_only() -> impl Guard {
+    |request: &Request<Bot>| {
+        matches!(
+            request.message,
+            Message::Friend(_) | Message::Temp(_) | Message::Stranger(_)
+        )
+    }
+}
+
+/// 群消息
+pub fn group_only() -> impl Guard {
+    |request: &Request<Bot>| matches!(request.message, Message::Group(_))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        messages::MessageChain,
+        test_utils::{friend_message, group_message},
+    };
+
+    /// 群 `group` 中权限为 `permission` 的 `sender` 发送的【踢 @bot】
+    fn kick(sender: u64, group: u64, permission: Permission) -> Message {
+        let mut message = group_message(group, sender, MessageChain::new().text("踢").at(QQ(1)));
+        message.sender.permission = permission;
+        Message::Group(message)
+    }
+
+    #[test]
+    fn test_guards() {
+        let bot = Bot::mock(QQ(1));
+        let request = |message| Request::new(bot.clone(), message);
+        let admin = request(kick(10, 100, Permission::Administrator));
+        let member = request(kick(11, 200, Permission::Member));
+        let friend = request(Message::Friend(friend_message(10, "踢")));
+
+        assert!(is_admin().check(&admin));
+        assert!(!is_admin().check(&member));
+        assert!(!is_admin().check(&friend));
+        assert!(!is_owner().check(&admin));
+
+        let guard = is_admin().and(in_groups([QQ(100), QQ(300)]));
+        assert!(guard.check(&admin));
+        assert!(!guard.check(&member));
+
+        assert!(from_users([QQ(10)]).check(&admin));
+        assert!(from_users([QQ(10)]).check(&friend));
+        assert!(!from_users([QQ(10)]).check(&member));
+
+        assert!(at_me().check(&admin));
+        let other_bot = Request::new(Bot::mock(QQ(2)), admin.message.clone());
+        assert!(!at_me().check(&other_bot));
+
+        let private = private_only().or(is_admin());
+        assert!(private.check(&friend));
+        assert!(private.check(&admin));
+        assert!(!private.check(&member));
+        assert!(group_only().not().check(&friend));
+    }
+}
@@ -0,0 +1,321 @@
+//! 根据指令的说明自动生成的帮助。
+//!
+//! 注册指令时可以通过 [`Command::describe`]、[`Command::usage`]、[`Command::example`]、
+//! [`Command::permission`] 添加说明，[`Bot::help_command`] 注册的【帮助】指令会根据这些说明生成帮助。
+//!
+//! # Example
+//! ```no_run
+//! # use miraie::prelude::*;
+//! use miraie::messages::group::Permission;
+//! # tokio_test::block_on(async {
+//! # let (bot, _) = Bot::new("127.0.0.1", "secret", QQ(123456)).await.unwrap();
+//! bot.help_command()
+//!     .command("天气", |_: GroupMessage| async { "..." })
+//!     .describe("查询城市的天气")
+//!     .usage("天气 <城市>")
+//!     .example("天气 北京")
+//!     .command("踢", |_: GroupMessage| async { "..." })
+//!     .describe("把成员移出群聊")
+//!     // 只有管理员可以使用，也只会出现在管理员的帮助中
+//!     .permission(Permission::Administrator);
+//! # });
+//! ```
+use chrono::Utc;
+use std::sync::Arc;
+
+use super::{plugin::PluginSwitch, Args, Command, Guard, Rest, QQ};
+use crate::{
+    messages::{group::Permission, ForwardNode, Message, MessageBlock, MessageChain},
+    msg_framework::Request,
+    Bot,
+};
+
+/// 帮助超过这么多行时改为发送合并转发的聊天记录，每条记录最多这么多行
+const MAX_LINES: usize = 15;
+
+/// 一个指令的帮助信息
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommandHelp {
+    /// 指令的第一个关键词或者模式
+    pub name: String,
+    /// 其他的关键词
+    pub aliases: Vec<String>,
+    pub description: Option<String>,
+    pub usage: Option<String>,
+    pub examples: Vec<String>,
+    /// 使用指令需要的群权限
+    pub permission: Option<Permission>,
+    /// 注册指令的插件
+    pub plugin: Option<String>,
+}
+
+/// 使用一个指令的条件，不会出现在帮助中
+#[derive(Clone)]
+pub(crate) struct Availability {
+    /// 注册指令的插件的开关
+    pub plugin: Option<PluginSwitch>,
+    /// 指令的参数可能接受的消息
+    pub message_kind: fn(&Message) -> bool,
+    /// 指令的守卫，包括 [`Command::permission`] 添加的守卫
+    pub guards: Vec<Arc<dyn Guard>>,
+}
+
+impl Availability {
+    /// 发送者在当前的群或者私聊中是否可以使用这个指令
+    fn allows(&self, request: &Request<Bot>) -> bool {
+        if self
+            .plugin
+            .as_ref()
+            .is_some_and(|plugin| !plugin.allows(&request.message))
+        {
+            return false;
+        }
+        (self.message_kind)(&request.message) && self.guards.iter().all(|g| g.check(request))
+    }
+}
+
+impl CommandHelp {
+    /// 是否是这个指令的关键词或者模式，忽略大小写
+    fn is_named(&self, name: &str) -> bool {
+        std::iter::once(&self.name)
+            .chain(&self.aliases)
+            .any(|keyword| keyword.to_lowercase() == name.to_lowercase())
+    }
+
+    /// 列表中的一行，如 `天气 <城市>：查询城市的天气`
+    fn summary(&self) -> String {
+        let usage = self.usage.as_deref().unwrap_or(&self.name);
+        match &self.description {
+            Some(description) => format!("{}：{}", usage, description),
+            None => usage.to_string(),
+        }
+    }
+
+    /// 详细的说明
+    fn details(&self) -> Vec<String> {
+        let mut lines = vec![self.usage.clone().unwrap_or_else(|| self.name.clone())];
+        lines.extend(self.description.clone());
+        if !self.aliases.is_empty() {
+            lines.push(format!("别名：{}", self.aliases.join("、")));
+        }
+        if let Some(permission) = self.permission {
+            lines.push(format!("需要权限：{}", permission_name(permission)));
+        }
+        if !self.examples.is_empty() {
+            lines.push("示例：".to_string());
+            lines.extend(self.examples.iter().cloned());
+        }
+        lines
+    }
+}
+
+fn permission_name(permission: Permission) -> &'static str {
+    match permission {
+        Permission::Member => "群员",
+        Permission::Administrator => "管理员",
+        Permission::Owner => "群主",
+    }
+}
+
+/// 【帮助 `query`】的内容，`query` 为空时列出 `request` 的发送者可以使用的所有指令
+fn help_lines(
+    commands: &[(CommandHelp, Availability)],
+    request: &Request<Bot>,
+    query: &str,
+) -> Vec<String> {
+    let available = commands
+        .iter()
+        .filter(|(_, availability)| availability.allows(request))
+        .map(|(help, _)| help);
+    if !query.is_empty() {
+        return match available.clone().find(|help| help.is_named(query)) {
+            Some(help) => help.details(),
+            None => vec![format!("没有找到指令【{}】", query)],
+        };
+    }
+
+    let mut lines = vec!["可以使用的指令：".to_string()];
+    lines.extend(
+        available
+            .clone()
+            .filter(|help| help.plugin.is_none())
+            .map(CommandHelp::summary),
+    );
+    // 插件的指令按照插件分组
+    let mut plugins: Vec<&str> = Vec::new();
+    for plugin in available.clone().filter_map(|help| help.plugin.as_deref()) {
+        if !plugins.contains(&plugin) {
+            plugins.push(plugin);
+        }
+    }
+    for plugin in plugins {
+        lines.push(format!("【{}】", plugin));
+        lines.extend(
+            available
+                .clone()
+                .filter(|help| help.plugin.as_deref() == Some(plugin))
+                .map(CommandHelp::summary),
+        );
+    }
+    lines.push("发送【帮助 指令】查看指令的详细说明".to_string());
+    lines
+}
+
+/// 把帮助合成一条消息，过长时改为合并转发的聊天记录
+fn render(lines: Vec<String>, sender: QQ) -> MessageChain {
+    if lines.len() <= MAX_LINES {
+        return MessageChain::new().text(lines.join("\n"));
+    }
+    let time = Utc::now();
+    let nodes = lines
+        .chunks(MAX_LINES)
+        .map(|chunk| ForwardNode {
+            sender_id: sender,
+            time,
+            sender_name: "帮助".to_string(),
+            message: MessageChain::new().text(chunk.join("\n")),
+            message_id: None,
+        })
+        .collect();
+    MessageBlock::Forward { nodes }.into()
+}
+
+impl Bot {
+    /// 所有指令的帮助信息，按照注册的顺序排列，不包括暂停的指令
+    pub fn commands(&self) -> Vec<CommandHelp> {
+        let registry = self.kw_command_handlers.0.read();
+        registry.help().into_iter().map(|(help, _)| help).collect()
+    }
+
+    /// 注册内置的【帮助】指令，也可以使用【help】触发，见[模块文档](super::help)。
+    ///
+    /// - 【帮助】列出发送者在当前的群或者私聊中可以使用的指令，不包括权限不足、不满足守卫、
+    ///   不接受当前消息类型（如只接受 [`GroupMessage`](crate::messages::GroupMessage) 的指令在私聊中）
+    ///   或者插件被禁用的指令；
+    /// - 【帮助 指令】显示指令的详细说明，包括用法、别名、需要的权限以及示例。
+    ///
+    /// 帮助较长时改为发送合并转发的聊天记录。
+    pub fn help_command(self) -> Command {
+        self.command(
+            "帮助",
+            |message: Message, Args(Rest(query)): Args<Rest<String>>, bot: Bot| async move {
+                let commands = bot.kw_command_handlers.0.read().help();
+                let request = Request::new(bot.clone(), message);
+                let lines = help_lines(&commands, &request, query.trim());
+                render(lines, bot.qq())
+            },
+        )
+        .alias("help")
+        .ignore_case()
+        .describe("查看可以使用的指令，或者指令的详细说明")
+        .usage("帮助 [指令]")
+        .example("帮助 帮助")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        bot::guard::in_groups,
+        messages::{FriendMessage, GroupMessage},
+        msg_framework::App,
+        test_utils::{friend_message, group_message, mock_bot},
+    };
+    use std::time::Duration;
+
+    /// 群 100 中权限为 `permission` 的群员发送的消息
+    fn member_message(permission: Permission) -> Message {
+        let mut message = group_message(100, 10, "");
+        message.sender.permission = permission;
+        Message::Group(message)
+    }
+
+    #[tokio::test]
+    async fn test_help() {
+        let bot = Bot::mock(QQ(1)).help_command().into_app();
+        bot.clone()
+            .command("天气", |_: GroupMessage| async {})
+            .describe("查询城市的天气")
+            .usage("天气 <城市>")
+            .example("天气 北京")
+            .alias("weather")
+            .command("踢", |_: GroupMessage| async {})
+            .permission(Permission::Administrator)
+            .command("签到", |_: Message| async {})
+            .describe("每日签到")
+            .guard(in_groups([QQ(200)]))
+            .command("私信", |_: FriendMessage| async {});
+        let commands = bot.kw_command_handlers.0.read().help();
+        assert_eq!(bot.commands().len(), 5);
+
+        let help = |message: &Message, query: &str| {
+            help_lines(
+                &commands,
+                &Request::new(bot.clone(), message.clone()),
+                query,
+            )
+        };
+        // 只接受群消息的指令不会出现在私聊的帮助中
+        assert_eq!(
+            help(&Message::Friend(friend_message(10, "")), ""),
+            vec![
+                "可以使用的指令：",
+                "帮助 [指令]：查看可以使用的指令，或者指令的详细说明",
+                "私信",
+                "发送【帮助 指令】查看指令的详细说明",
+            ]
+        );
+        let admin = member_message(Permission::Owner);
+        assert_eq!(help(&admin, "").len(), 5);
+        // 满足守卫的群中才会列出
+        assert_eq!(
+            help(&Message::Group(group_message(200, 10, "")), ""),
+            vec![
+                "可以使用的指令：",
+                "帮助 [指令]：查看可以使用的指令，或者指令的详细说明",
+                "天气 <城市>：查询城市的天气",
+                "签到：每日签到",
+                "发送【帮助 指令】查看指令的详细说明",
+            ]
+        );
+        assert_eq!(help(&admin, "签到"), vec!["没有找到指令【签到】"]);
+        assert_eq!(help(&admin, "踢"), vec!["踢", "需要权限：管理员"]);
+        assert_eq!(
+            help(&member_message(Permission::Member), "WEATHER"),
+            vec![
+                "天气 <城市>",
+                "查询城市的天气",
+                "别名：weather",
+                "示例：",
+                "天气 北京"
+            ]
+        );
+        assert_eq!(
+            help(&member_message(Permission::Member), "踢"),
+            vec!["没有找到指令【踢】"]
+        );
+
+        let short = render(vec!["a".to_string(); MAX_LINES], QQ(1));
+        assert_eq!(short.plain_text(), vec!["a"; MAX_LINES].join("\n"));
+        let long = render(vec!["a".to_string(); MAX_LINES + 1], QQ(1));
+        match &long.0[..] {
+            [MessageBlock::Forward { nodes }] => assert_eq!(nodes.len(), 2),
+            blocks => panic!("unexpected {:?}", blocks),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_help_command() {
+        let (bot, sent) = mock_bot(|_, _| None);
+        bot.clone().help_command();
+        bot.event_bus()
+            .send(Message::Friend(friend_message(10, "HELP 帮助")))
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert_eq!(
+            sent.take(),
+            vec!["帮助 [指令]\n查看可以使用的指令，或者指令的详细说明\n别名：help\n示例：\n帮助 帮助"]
+        );
+    }
+}
@@ -1,20 +1,39 @@
 //! bot 注册的关键词
 //!
 
-use parking_lot::RwLock;
+use parking_lot::{Mutex, RwLock};
+use std::collections::BTreeMap;
 use std::future::Future;
-use std::{marker::PhantomData, pin::Pin, sync::Arc};
+use std::ops::Deref;
+use std::{
+    marker::PhantomData,
+    sync::{Arc, Weak},
+};
+
+use futures::future::BoxFuture;
+use tokio::sync::oneshot;
 
+use super::{
+    command_pattern::{CommandRegex, RegexMatch},
+    command_trie::CommandTrie,
+    guard::{has_permission, Guard, GuardMiddleware},
+    help::{Availability, CommandHelp},
+    plugin::PluginScope,
+    rate_limit::RateLimit,
+    CommandMatch,
+};
 use crate::{
-    msg_framework::{FromRequest, Func, Return},
-    Bot,
+    messages::{group::Permission, Message, MessageBlock, MessageChain},
+    msg_framework::{
+        acquire, catch_panic, run_middlewares, FromRequest, Func, HandlerHandle, HandlerState,
+        Limiter, Middleware, Middlewares, Next, Outcome, Return,
+    },
+    App, Bot,
 };
 type Request = crate::msg_framework::Request<Bot>;
 
 #[derive(Clone, Default)]
-pub(crate) struct KeywordCommandHandlers(
-    pub(crate) Arc<RwLock<Vec<(String, KeywordCommandHandler)>>>,
-);
+pub(crate) struct KeywordCommandHandlers(pub(crate) Arc<RwLock<CommandRegistry>>);
 
 impl KeywordCommandHandlers {
     pub fn new() -> Self {
@@ -27,9 +46,540 @@ impl FromRequest<Bot> for KeywordCommandHandlers {
     }
 }
 
+/// 注册的所有指令，关键词通过前缀树查找，正则表达式按照注册的顺序依次匹配
+#[derive(Default)]
+pub(crate) struct CommandRegistry {
+    next_id: usize,
+    commands: BTreeMap<usize, CommandEntry>,
+    trie: CommandTrie<usize>,
+    regexes: Vec<usize>,
+}
+
+struct CommandEntry {
+    keywords: Vec<String>,
+    regex: Option<CommandRegex>,
+    ignore_case: bool,
+    handler: KeywordCommandHandler,
+    state: Arc<HandlerState>,
+    meta: CommandMeta,
+}
+
+/// 指令的说明以及使用的条件，用于生成帮助
+#[derive(Clone)]
+pub(crate) struct CommandMeta {
+    pub description: Option<String>,
+    pub usage: Option<String>,
+    pub examples: Vec<String>,
+    pub permission: Option<Permission>,
+    /// 指令的参数可能接受的消息，见 [`FromRequest::may_extract`]
+    pub message_kind: fn(&Message) -> bool,
+    /// 通过 [`Command::guard`] 添加的守卫，按照添加的顺序排列
+    pub guards: Vec<Arc<dyn Guard>>,
+}
+
+impl CommandMeta {
+    fn new(handler: &KeywordCommandHandler) -> Self {
+        Self {
+            description: None,
+            usage: None,
+            examples: Vec::new(),
+            permission: None,
+            message_kind: handler.message_kind,
+            guards: Vec::new(),
+        }
+    }
+}
+
+impl CommandRegistry {
+    pub fn register(
+        &mut self,
+        keyword: String,
+        mut handler: KeywordCommandHandler,
+        state: Arc<HandlerState>,
+    ) -> usize {
+        handler.limiter = state.limiter.clone();
+        let id = self.next_id;
+        self.next_id += 1;
+        self.trie.insert(&keyword, id);
+        self.commands.insert(
+            id,
+            CommandEntry {
+                keywords: vec![keyword],
+                regex: None,
+                ignore_case: false,
+                meta: CommandMeta::new(&handler),
+                handler,
+                state,
+            },
+        );
+        id
+    }
+
+    pub fn register_regex(
+        &mut self,
+        regex: CommandRegex,
+        mut handler: KeywordCommandHandler,
+        state: Arc<HandlerState>,
+    ) -> usize {
+        handler.limiter = state.limiter.clone();
+        let id = self.next_id;
+        self.next_id += 1;
+        self.regexes.push(id);
+        self.commands.insert(
+            id,
+            CommandEntry {
+                keywords: Vec::new(),
+                regex: Some(regex),
+                ignore_case: false,
+                meta: CommandMeta::new(&handler),
+                handler,
+                state,
+            },
+        );
+        id
+    }
+
+    pub fn remove(&mut self, id: usize) {
+        if let Some(entry) = self.commands.remove(&id) {
+            for keyword in entry.keywords.iter() {
+                self.trie.remove(keyword, &id);
+            }
+            self.regexes.retain(|i| *i != id);
+        }
+    }
+
+    pub fn add_alias(&mut self, id: usize, keyword: String) {
+        if let Some(entry) = self.commands.get_mut(&id) {
+            if let Some(regex) = &entry.regex {
+                warn!(
+                    "正则表达式指令 {} 不能添加别名，忽略别名 {}",
+                    regex.source(),
+                    keyword
+                );
+                return;
+            }
+            self.trie.insert(&keyword, id);
+            entry.keywords.push(keyword);
+        }
+    }
+
+    pub fn middlewares(&self, id: usize) -> Option<Middlewares<Bot>> {
+        self.commands
+            .get(&id)
+            .map(|entry| entry.handler.middlewares.clone())
+    }
+
+    pub fn meta_mut(&mut self, id: usize) -> Option<&mut CommandMeta> {
+        self.commands.get_mut(&id).map(|entry| &mut entry.meta)
+    }
+
+    /// 指令的参数和守卫是否接受这个请求
+    pub fn accepts(&self, id: usize, request: &Request) -> bool {
+        self.commands.get(&id).is_some_and(|entry| {
+            (entry.meta.message_kind)(&request.message)
+                && entry.meta.guards.iter().all(|guard| guard.check(request))
+        })
+    }
+
+    /// 所有没有暂停的指令的帮助，以及使用指令的条件，按照注册的顺序排列
+    pub fn help(&self) -> Vec<(CommandHelp, Availability)> {
+        self.commands
+            .values()
+            .filter(|entry| !entry.state.is_paused())
+            .filter_map(|entry| {
+                let (name, aliases) = match (&entry.regex, entry.keywords.split_first()) {
+                    (Some(regex), _) => (regex.source().to_string(), Vec::new()),
+                    (None, Some((name, aliases))) => (name.clone(), aliases.to_vec()),
+                    (None, None) => return None,
+                };
+                let plugin = entry.handler.plugin.as_ref();
+                let help = CommandHelp {
+                    name,
+                    aliases,
+                    description: entry.meta.description.clone(),
+                    usage: entry.meta.usage.clone(),
+                    examples: entry.meta.examples.clone(),
+                    permission: entry.meta.permission,
+                    plugin: plugin.map(|plugin| plugin.name.clone()),
+                };
+                let availability = Availability {
+                    plugin: plugin.map(|plugin| plugin.switch.clone()),
+                    message_kind: entry.meta.message_kind,
+                    guards: entry.meta.guards.clone(),
+                };
+                Some((help, availability))
+            })
+            .collect()
+    }
+
+    pub fn set_ignore_case(&mut self, id: usize) {
+        if let Some(entry) = self.commands.get_mut(&id) {
+            if !entry.ignore_case {
+                if let Some(regex) = entry.regex.as_mut() {
+                    regex.ignore_case();
+                }
+            }
+            entry.ignore_case = true;
+        }
+    }
+
+    /// 插件中注册的指令的名字，即第一个关键词或者模式
+    pub fn plugin_commands(&self, plugin: &Arc<PluginScope>) -> Vec<String> {
+        self.commands
+            .values()
+            .filter(|entry| {
+                let owner = entry.handler.plugin.as_ref();
+                owner.is_some_and(|owner| Arc::ptr_eq(owner, plugin))
+            })
+            .filter_map(|entry| match entry.keywords.first() {
+                Some(keyword) => Some(keyword.clone()),
+                None => entry.regex.as_ref().map(|regex| regex.source().to_string()),
+            })
+            .collect()
+    }
+
+    /// 找到 `text` 开头最长的关键词，返回关键词在 `text` 中的字节长度以及对应的指令。
+    /// 多个指令注册了同一个关键词时都会返回，暂停的指令会被跳过。
+    pub fn find(&self, text: &str) -> Option<(usize, Vec<KeywordCommandHandler>)> {
+        self.trie.prefixes(text).into_iter().find_map(|(len, ids)| {
+            let prefix = &text[..len];
+            let handlers: Vec<_> = ids
+                .iter()
+                .filter_map(|id| self.commands.get(id))
+                .filter(|entry| !entry.state.is_paused())
+                .filter(|entry| entry.ignore_case || entry.keywords.iter().any(|k| k == prefix))
+                .map(|entry| entry.handler.clone())
+                .collect();
+            (!handlers.is_empty()).then_some((len, handlers))
+        })
+    }
+
+    /// 按照注册的顺序找到第一个匹配消息中文字的正则表达式指令
+    pub fn find_regex(&self, chain: &MessageChain) -> Option<(RegexMatch, KeywordCommandHandler)> {
+        if self.regexes.is_empty() {
+            return None;
+        }
+        let text = chain.plain_text();
+        self.regexes.iter().find_map(|id| {
+            let entry = self.commands.get(id).filter(|e| !e.state.is_paused())?;
+            let matched = entry.regex.as_ref()?.matches(&text)?;
+            Some((matched, entry.handler.clone()))
+        })
+    }
+}
+
+/// 消息开头用来匹配关键词的文字，会跳过 Source 和引用回复，遇到其他的消息块为止
+pub(crate) fn leading_text(chain: &MessageChain) -> String {
+    chain
+        .0
+        .iter()
+        .filter(|block| {
+            !matches!(
+                block,
+                MessageBlock::Source { .. } | MessageBlock::Quote { .. }
+            )
+        })
+        .map_while(|block| match block {
+            MessageBlock::Text { text } => Some(text.as_str()),
+            _ => None,
+        })
+        .collect()
+}
+
+/// 通过 [`Bot::command`]、[`Bot::command_regex`] 或 [`Bot::command_pattern`] 注册的指令，
+/// 可以继续为它添加别名、设置忽略大小写。
+///
+/// 指令可以通过 `Deref` 当作 [`HandlerHandle`] 使用，暂停或者取消注册指令，
+/// 也可以继续调用 `command`、`handler`、`bot_data` 进行链式注册，[`Bot`] 的其他方法可以直接调用。
+///
+/// # Example
+/// ```no_run
+/// # use miraie::prelude::*;
+/// # tokio_test::block_on(async {
+/// # let (bot, _) = Bot::new("127.0.0.1", "secret", QQ(123456)).await.unwrap();
+/// // 【帮助】、【help】、【HELP】都会触发
+/// let help = bot
+///     .command("帮助", |_: GroupMessage| async { "..." })
+///     .alias("help")
+///     .ignore_case();
+///
+/// // 之后不再响应帮助指令
+/// help.unregister();
+/// # });
+/// ```
+pub struct Command {
+    handle: HandlerHandle<Bot>,
+    id: usize,
+}
+
+impl Command {
+    /// 注册指令，取消注册时会把指令从 [`CommandRegistry`] 中删除
+    pub(crate) fn register(
+        bot: Bot,
+        register: impl FnOnce(&mut CommandRegistry, Arc<HandlerState>) -> usize,
+    ) -> Self {
+        let handlers = bot.kw_command_handlers.clone();
+        let handle = HandlerHandle::new(bot);
+        let id = register(&mut handlers.0.write(), handle.state());
+        handle.on_unregister(move || handlers.0.write().remove(id));
+        Self { handle, id }
+    }
+
+    /// 为指令添加一个别名，别名和原来的关键词一样会触发指令。
+    ///
+    /// 只有关键词指令可以添加别名，正则表达式和模式指令的别名会被忽略，
+    /// 需要匹配多种写法时请写在正则表达式或者模式中。
+    pub fn alias(self, keyword: impl Into<String>) -> Self {
+        self.kw_command_handlers
+            .0
+            .write()
+            .add_alias(self.id, keyword.into());
+        self
+    }
+
+    /// 匹配关键词（包括别名）或者正则表达式时忽略大小写
+    pub fn ignore_case(self) -> Self {
+        self.kw_command_handlers.0.write().set_ignore_case(self.id);
+        self
+    }
+
+    /// 注册只对这个指令生效的中间件，在全局中间件的内层执行，见 [`Middleware`]
+    pub fn middleware(self, middleware: impl Middleware<Bot>) -> Self {
+        let middlewares = self.kw_command_handlers.0.read().middlewares(self.id);
+        if let Some(middlewares) = middlewares {
+            middlewares.push(middleware);
+        }
+        self
+    }
+
+    /// 为指令添加守卫，不满足守卫的消息会被忽略，见 [`guard`](super::guard)。
+    ///
+    /// 守卫相当于一个中间件，和通过 [`Command::middleware`] 注册的中间件按照注册的顺序执行。
+    /// 帮助中只会列出满足守卫的指令。
+    pub fn guard(self, guard: impl Guard) -> Self {
+        let guard: Arc<dyn Guard> = Arc::new(guard);
+        let check = guard.clone();
+        self.update_meta(|meta| meta.guards.push(guard))
+            .middleware(GuardMiddleware(move |request: &Request| {
+                check.check(request)
+            }))
+    }
+
+    /// 为指令添加频率限制，冷却中的消息不会交给指令处理，见 [`rate_limit`](super::rate_limit)。
+    ///
+    /// 频率限制相当于一个中间件，和通过 [`Command::middleware`] 注册的中间件按照注册的顺序执行。
+    /// 参数提取失败的消息不会经过频率限制；被内层的守卫拦截的消息不消耗次数。
+    /// 冷却中只会回复满足指令所有守卫的消息，不能使用指令的人不会收到冷却的提示。
+    pub fn cooldown(self, limit: RateLimit) -> Self {
+        let cooldown = Cooldown {
+            limit,
+            commands: Arc::downgrade(&self.kw_command_handlers.0),
+            id: self.id,
+        };
+        self.middleware(cooldown)
+    }
+
+    /// 指令的说明，显示在帮助中，见 [`Bot::help_command`]
+    pub fn describe(self, description: impl Into<String>) -> Self {
+        self.update_meta(|meta| meta.description = Some(description.into()))
+    }
+
+    /// 指令的用法，如 `天气 <城市>`，显示在帮助中，默认显示关键词
+    pub fn usage(self, usage: impl Into<String>) -> Self {
+        self.update_meta(|meta| meta.usage = Some(usage.into()))
+    }
+
+    /// 添加一个使用的例子，显示在指令的详细帮助中，可以多次调用添加多个例子
+    pub fn example(self, example: impl Into<String>) -> Self {
+        self.update_meta(|meta| meta.examples.push(example.into()))
+    }
+
+    /// 使用指令需要的群权限，同时会添加守卫，权限不足的群消息以及私聊消息会被忽略。
+    /// 帮助中只会列出发送者有权限使用的指令。
+    pub fn permission(self, permission: Permission) -> Self {
+        self.update_meta(|meta| meta.permission = Some(permission))
+            .guard(move |request: &Request| has_permission(request, permission))
+    }
+
+    fn update_meta(self, f: impl FnOnce(&mut CommandMeta)) -> Self {
+        if let Some(meta) = self.kw_command_handlers.0.write().meta_mut(self.id) {
+            f(meta);
+        }
+        self
+    }
+
+    /// 设置这个指令同时处理的消息数的上限，见 [`HandlerHandle::max_concurrency`]
+    pub fn max_concurrency(self, max: usize) -> Self {
+        Self {
+            handle: self.handle.max_concurrency(max),
+            id: self.id,
+        }
+    }
+
+    /// 继续注册指令，见 [`Bot::command`]
+    pub fn command<F, I, Fut>(self, command: impl Into<String>, handler: F) -> Command
+    where
+        F: Func<I, Fut>,
+        I: Send + 'static + FromRequest<Bot>,
+        Fut: Future + Send + 'static,
+        Fut::Output: Return<Bot>,
+    {
+        self.handle.command(command, handler)
+    }
+
+    /// 继续注册正则表达式指令，见 [`Bot::command_regex`]
+    pub fn command_regex<F, I, Fut>(self, regex: regex::Regex, handler: F) -> Command
+    where
+        F: Func<I, Fut>,
+        I: Send + 'static + FromRequest<Bot>,
+        Fut: Future + Send + 'static,
+        Fut::Output: Return<Bot>,
+    {
+        self.handle.command_regex(regex, handler)
+    }
+
+    /// 继续注册模式指令，见 [`Bot::command_pattern`]
+    pub fn command_pattern<F, I, Fut>(self, pattern: &str, handler: F) -> Command
+    where
+        F: Func<I, Fut>,
+        I: Send + 'static + FromRequest<Bot>,
+        Fut: Future + Send + 'static,
+        Fut::Output: Return<Bot>,
+    {
+        self.handle.command_pattern(pattern, handler)
+    }
+
+    /// 继续注册 handler，见 [`App::handler`](crate::App::handler)
+    pub fn handler<F, I, Fut>(self, f: F) -> HandlerHandle<Bot>
+    where
+        F: Func<I, Fut>,
+        I: FromRequest<Bot> + Send + 'static,
+        Fut: Future + Send + 'static,
+        Fut::Output: Return<Bot>,
+    {
+        self.handle.handler(f)
+    }
+
+    /// 继续注册带有优先级的 handler，见 [`App::handler_priority`](crate::App::handler_priority)
+    pub fn handler_priority<F, I, Fut>(self, priority: i32, f: F) -> HandlerHandle<Bot>
+    where
+        F: Func<I, Fut>,
+        I: FromRequest<Bot> + Send + 'static,
+        Fut: Future + Send + 'static,
+        Fut::Output: Return<Bot>,
+    {
+        self.handle.handler_priority(priority, f)
+    }
+
+    /// 继续注册 fallback handler，见 [`App::fallback`](crate::App::fallback)
+    pub fn fallback<F, I, Fut>(self, f: F) -> HandlerHandle<Bot>
+    where
+        F: Func<I, Fut>,
+        I: FromRequest<Bot> + Send + 'static,
+        Fut: Future + Send + 'static,
+        Fut::Output: Return<Bot>,
+    {
+        self.handle.fallback(f)
+    }
+
+    /// 注册数据，见 [`Bot::bot_data`]
+    pub fn bot_data<U: Send + Sync + 'static>(self, ext: U) -> Bot {
+        self.handle.bot_data(ext)
+    }
+
+    /// 取回指令的句柄
+    pub fn into_handle(self) -> HandlerHandle<Bot> {
+        self.handle
+    }
+
+    /// 取回 [`Bot`]，见 [`HandlerHandle::into_app`]
+    pub fn into_app(self) -> Bot {
+        self.handle.into_app()
+    }
+}
+
+impl Deref for Command {
+    type Target = HandlerHandle<Bot>;
+
+    fn deref(&self) -> &HandlerHandle<Bot> {
+        &self.handle
+    }
+}
+
+/// 注册 handler 之后可以继续链式注册指令和数据
+impl HandlerHandle<Bot> {
+    /// 继续注册指令，见 [`Bot::command`]
+    pub fn command<F, I, Fut>(self, command: impl Into<String>, handler: F) -> Command
+    where
+        F: Func<I, Fut>,
+        I: Send + 'static + FromRequest<Bot>,
+        Fut: Future + Send + 'static,
+        Fut::Output: Return<Bot>,
+    {
+        self.into_app().command(command, handler)
+    }
+
+    /// 继续注册正则表达式指令，见 [`Bot::command_regex`]
+    pub fn command_regex<F, I, Fut>(self, regex: regex::Regex, handler: F) -> Command
+    where
+        F: Func<I, Fut>,
+        I: Send + 'static + FromRequest<Bot>,
+        Fut: Future + Send + 'static,
+        Fut::Output: Return<Bot>,
+    {
+        self.into_app().command_regex(regex, handler)
+    }
+
+    /// 继续注册模式指令，见 [`Bot::command_pattern`]
+    pub fn command_pattern<F, I, Fut>(self, pattern: &str, handler: F) -> Command
+    where
+        F: Func<I, Fut>,
+        I: Send + 'static + FromRequest<Bot>,
+        Fut: Future + Send + 'static,
+        Fut::Output: Return<Bot>,
+    {
+        self.into_app().command_pattern(pattern, handler)
+    }
+
+    /// 注册数据，见 [`Bot::bot_data`]
+    pub fn bot_data<U: Send + Sync + 'static>(self, ext: U) -> Bot {
+        self.into_app().bot_data(ext)
+    }
+}
+
+/// 指令的频率限制，冷却中时只回复指令会处理的消息，见 [`Command::cooldown`]
+struct Cooldown {
+    limit: RateLimit,
+    /// 中间件保存在指令中，使用弱引用避免循环引用
+    commands: Weak<RwLock<CommandRegistry>>,
+    id: usize,
+}
+
+#[async_trait]
+impl Middleware<Bot> for Cooldown {
+    async fn handle(&self, request: Request, next: Next<'_, Bot>) -> Outcome {
+        let would_run = |request: &Request| {
+            self.commands
+                .upgrade()
+                .is_some_and(|commands| commands.read().accepts(self.id, request))
+        };
+        self.limit.limit(request, next, would_run).await
+    }
+}
+
 /// 关键词消息处理的处理回调，是函数
 #[derive(Clone)]
-pub struct KeywordCommandHandler(Arc<dyn RequestHandler>);
+pub struct KeywordCommandHandler {
+    handler: Arc<dyn RequestHandler>,
+    /// 只对这个指令生效的中间件
+    middlewares: Middlewares<Bot>,
+    /// 只对这个指令生效的并发限制，注册时和 [`HandlerHandle`] 共享
+    limiter: Limiter,
+    /// 注册指令的插件
+    plugin: Option<Arc<PluginScope>>,
+    /// 指令的参数可能接受的消息，见 [`FromRequest::may_extract`]
+    message_kind: fn(&Message) -> bool,
+}
 
 impl KeywordCommandHandler {
     pub fn new<F, T, Fut>(f: F) -> Self
@@ -43,16 +593,83 @@ impl KeywordCommandHandler {
             f,
             _phantom: PhantomData,
         };
-        Self(Arc::new(handler))
+        Self {
+            handler: Arc::new(handler),
+            middlewares: Middlewares::new(),
+            limiter: Limiter::default(),
+            plugin: None,
+            message_kind: T::may_extract,
+        }
+    }
+
+    /// 指令属于插件 `plugin`
+    pub(crate) fn with_plugin(mut self, plugin: Option<Arc<PluginScope>>) -> Self {
+        self.plugin = plugin;
+        self
     }
 
-    pub(crate) async fn handle(&self, request: Request) {
-        self.0.handle_request(request).await;
+    /// 启动指令，返回指令是否处理了这条消息。
+    ///
+    /// 指令开始执行（参数提取成功，或者回复了用法）时就返回，不等待指令执行完，
+    /// 这样耗时的指令（如等待用户回答的 [`Session`](crate::messages::Session)）不会阻塞
+    /// 更低优先级的 handler、fallback handler 以及串行模式下同一个会话的下一条消息。
+    /// 指令被守卫、插件开关等拦截时会等到拦截的结果。
+    ///
+    /// 指令不处理的消息（参数提取失败，也不需要回复用法）不会交给中间件，如不会被频率限制计数。
+    pub(crate) async fn spawn(&self, mut request: Request) -> bool {
+        // 插件中注册的指令经过插件的中间件，使用插件的数据
+        if let Some(plugin) = &self.plugin {
+            request.app = request.app.scoped(plugin);
+        }
+        if !self.handler.accepts(&request) {
+            return false;
+        }
+        let (started, notified) = oneshot::channel();
+        request
+            .extensions
+            .insert(CommandStarted(Mutex::new(Some(started))));
+        let task = tokio::spawn(self.start(request).await);
+        tokio::select! {
+            biased;
+            outcome = task => !matches!(outcome, Ok(Outcome::Skipped)),
+            // 没有开始执行就被丢弃时，等待任务的结果
+            Ok(()) = notified => true,
+        }
+    }
+
+    /// 经过全局中间件以及指令的中间件处理请求的任务。
+    ///
+    /// 先拿到指令和全局的并发许可再返回任务，超过上限时在这里等待，不会为排队的指令启动任务。
+    async fn start(&self, request: Request) -> impl Future<Output = Outcome> + Send + 'static {
+        let mut middlewares = request.app.middlewares().snapshot();
+        middlewares.extend(self.middlewares.snapshot());
+        let handler = self.handler.clone();
+        let permits = acquire(&[self.limiter.clone(), request.app.scheduler().limiter()]).await;
+        async move {
+            let endpoint = move |request| handler.handle_request(request);
+            let run = run_middlewares(&middlewares, &endpoint, request);
+            permits.run(None, run).await
+        }
+    }
+}
+
+/// 指令开始执行的通知，见 [`KeywordCommandHandler::spawn`]
+struct CommandStarted(Mutex<Option<oneshot::Sender<()>>>);
+
+impl CommandStarted {
+    fn notify(request: &Request) {
+        let started = request.extensions.get::<CommandStarted>();
+        if let Some(started) = started.and_then(|started| started.0.lock().take()) {
+            let _ = started.send(());
+        }
     }
 }
 
 trait RequestHandler: Send + Sync + 'static {
-    fn handle_request(&self, request: Request) -> Pin<Box<dyn Future<Output = ()> + Send>>;
+    /// 是否处理这个请求：参数可以提取，或者参数解析失败、需要回复用法
+    fn accepts(&self, request: &Request) -> bool;
+
+    fn handle_request(&self, request: Request) -> BoxFuture<'static, Outcome>;
 }
 
 struct Callable<F, T, Fut> {
@@ -84,19 +701,148 @@ where
     Fut: Send + 'static + Future,
     Fut::Output: Return<Bot>,
 {
-    fn handle_request(&self, request: Request) -> Pin<Box<dyn Future<Output = ()> + Send>> {
+    fn accepts(&self, request: &Request) -> bool {
+        T::from_request(request).is_some()
+            || request
+                .extensions
+                .get::<CommandMatch>()
+                .is_some_and(CommandMatch::is_rejected)
+    }
+
+    fn handle_request(&self, request: Request) -> BoxFuture<'static, Outcome> {
         match T::from_request(&request) {
             Some(input) => {
+                CommandStarted::notify(&request);
                 let fut = self.f.call(input);
                 Box::pin(async move {
-                    let ret = fut.await;
-                    ret.on_return(request).await;
+                    match catch_panic(fut).await {
+                        Ok(ret) => {
+                            let outcome = ret.outcome();
+                            ret.on_return(request).await;
+                            outcome
+                        }
+                        Err(outcome) => outcome,
+                    }
                 })
             }
             None => {
-                debug!("failed to extract arguments from request.");
-                Box::pin(async {})
+                // 参数解析失败时回复用法
+                let rejection = request
+                    .extensions
+                    .get::<CommandMatch>()
+                    .and_then(CommandMatch::take_rejection);
+                match rejection {
+                    Some(reply) => Box::pin(async move {
+                        CommandStarted::notify(&request);
+                        reply.on_return(request).await;
+                        Outcome::Done
+                    }),
+                    None => {
+                        debug!("failed to extract arguments from request.");
+                        Box::pin(async { Outcome::Skipped })
+                    }
+                }
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messages::Message;
+
+    fn handler() -> KeywordCommandHandler {
+        KeywordCommandHandler::new(|_: Message| async {})
+    }
+
+    #[test]
+    fn test_registry_find() {
+        let mut registry = CommandRegistry::default();
+        let help = registry.register("帮助".to_string(), handler(), Arc::default());
+        let detail = registry.register("帮助 详细".to_string(), handler(), Arc::default());
+        registry.register("帮助 详细".to_string(), handler(), Arc::default());
+        registry.add_alias(help, "Help".to_string());
+
+        let find = |registry: &CommandRegistry, text| {
+            registry
+                .find(text)
+                .map(|(len, handlers)| (len, handlers.len()))
+        };
+        assert_eq!(find(&registry, "帮助 详细 指令"), Some((13, 2)));
+        assert_eq!(find(&registry, "帮助指令"), Some((6, 1)));
+        assert_eq!(find(&registry, "Help me"), Some((4, 1)));
+        assert_eq!(find(&registry, "help me"), None);
+        assert_eq!(find(&registry, "你好"), None);
+
+        registry.set_ignore_case(help);
+        assert_eq!(find(&registry, "HELP me"), Some((4, 1)));
+
+        registry.remove(detail);
+        assert_eq!(find(&registry, "帮助 详细 指令"), Some((13, 1)));
+        registry.remove(help);
+        assert_eq!(find(&registry, "帮助指令"), None);
+        assert_eq!(find(&registry, "Help me"), None);
+    }
+
+    #[test]
+    fn test_registry_paused() {
+        let mut registry = CommandRegistry::default();
+        let state = Arc::<HandlerState>::default();
+        registry.register("帮助".to_string(), handler(), Arc::default());
+        registry.register("帮助 详细".to_string(), handler(), state.clone());
+        registry.register_regex(
+            CommandRegex::from_regex(regex::Regex::new("详细").unwrap()),
+            handler(),
+            state.clone(),
+        );
+
+        let chain = MessageChain::new().text("帮助 详细");
+        assert_eq!(registry.find("帮助 详细").map(|(len, _)| len), Some(13));
+        assert!(registry.find_regex(&chain).is_some());
+
+        state.set_paused(true);
+        // 暂停的指令不参与匹配，较短的关键词会被触发
+        assert_eq!(registry.find("帮助 详细").map(|(len, _)| len), Some(6));
+        assert!(registry.find_regex(&chain).is_none());
+    }
+
+    #[test]
+    fn test_registry_find_regex() {
+        let mut registry = CommandRegistry::default();
+        let chain = MessageChain::new().text("ROLL 1d6");
+        assert!(registry.find_regex(&chain).is_none());
+
+        let roll = CommandRegex::from_pattern("roll {n:u32}d{sides:u32}").unwrap();
+        let id = registry.register_regex(roll, handler(), Arc::default());
+        registry.register_regex(
+            CommandRegex::from_regex(regex::Regex::new("d6").unwrap()),
+            handler(),
+            Arc::default(),
+        );
+        let (matched, _) = registry.find_regex(&chain).unwrap();
+        assert_eq!(matched.get(0), Some("d6"));
+
+        registry.set_ignore_case(id);
+        let (matched, _) = registry.find_regex(&chain).unwrap();
+        assert_eq!(matched.parse::<u32>("sides"), Some(6));
+
+        // 正则表达式指令不能添加别名，别名不会触发指令，帮助中也显示模式
+        registry.add_alias(id, "骰子".to_string());
+        assert!(registry.find("骰子 1d6").is_none());
+        let (help, _) = &registry.help()[0];
+        assert_eq!(help.name, "roll {n:u32}d{sides:u32}");
+        assert!(help.aliases.is_empty());
+    }
+
+    #[test]
+    fn test_leading_text() {
+        let chain = MessageChain(vec![MessageBlock::quote(1)])
+            .text("帮助")
+            .text(" 详细")
+            .at(crate::bot::QQ(1))
+            .text("其他");
+        assert_eq!(leading_text(&chain), "帮助 详细");
+        assert_eq!(leading_text(&MessageChain(vec![MessageBlock::face(1)])), "");
+    }
+}
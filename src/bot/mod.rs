@@ -1,18 +1,45 @@
 //! bot 的实现
 mod basic_types;
 mod botapp;
+mod command_args;
+mod command_pattern;
+mod command_trie;
 mod connection;
 mod data;
-mod extensions;
+mod error_sink;
+pub mod guard;
+pub mod help;
 mod keyword_command;
+pub mod plugin;
+pub mod rate_limit;
 mod return_handle;
+pub mod schedule;
+mod split_policy;
+pub mod store;
 mod utils;
 
 pub use basic_types::*;
 pub use botapp::Bot;
+#[doc(hidden)]
+pub use command_args::join_usages;
+pub use command_args::{
+    Arg, ArgError, Args, ArgsParser, CommandMatch, FromArg, FromCommandArgs, FromRest, Optional,
+    Rest,
+};
+pub use command_pattern::RegexMatch;
 pub use connection::Connection;
 pub use data::Data;
+pub use error_sink::{ErrorNotifier, ErrorSink, HandlerError};
+pub use guard::Guard;
+pub use help::CommandHelp;
+pub use keyword_command::Command;
 pub(crate) use keyword_command::{KeywordCommandHandler, KeywordCommandHandlers};
+pub use plugin::{Plugin, PluginInfo, PluginSwitch};
+pub use rate_limit::{RateLimit, SendThrottle};
+pub use schedule::{CatchUp, Cron, Job, JobHistory, ScheduledEvent, Trigger};
+pub(crate) use split_policy::SendTarget;
+pub use split_policy::SplitPolicy;
+pub use store::{BotStore, GroupStore, Storage, Store, UserStore};
 
 type WebsocketStream = async_tungstenite::WebSocketStream<async_tungstenite::tokio::ConnectStream>;
 use async_tungstenite::tungstenite::Message as WsMessage;
@@ -0,0 +1,324 @@
+//! 插件：把一组指令、handler、数据和定时任务打包成一个模块，方便拆分代码以及在多个 bot 之间复用。
+//!
+//! 插件实现 [`Plugin`]，在 [`Plugin::register`] 中像平常一样注册指令等，然后通过 [`Bot::plugin`] 注册到 bot 上。
+//! 插件中：
+//! - 注册的指令、handler 和定时任务只在插件启用时生效，可以通过 [`PluginSwitch`] 在每个群中单独启用或者禁用；
+//! - 通过 [`Bot::bot_data`] 注册的数据只在插件中可见，不同的插件可以使用同一类型的配置；
+//! - 通过 [`App::middleware`](crate::App::middleware) 注册的中间件只对插件中的 handler 和指令生效。
+//!
+//! # Example
+//! ```no_run
+//! # use miraie::prelude::*;
+//! use miraie::bot::Plugin;
+//!
+//! struct Greeting {
+//!     text: String,
+//! }
+//!
+//! impl Plugin for Greeting {
+//!     fn name(&self) -> &str {
+//!         "问候"
+//!     }
+//!
+//!     fn description(&self) -> &str {
+//!         "跟大家打招呼"
+//!     }
+//!
+//!     fn register(&self, bot: Bot) -> Bot {
+//!         let bot = bot.bot_data(Data::new(self.text.clone()));
+//!         bot.clone()
+//!             .command("你好", |_: GroupMessage, text: Data<String>| async move {
+//!                 text.to_string()
+//!             });
+//!         bot
+//!     }
+//! }
+//!
+//! # tokio_test::block_on(async {
+//! # let (bot, _) = Bot::new("127.0.0.1", "secret", QQ(123456)).await.unwrap();
+//! let bot = bot.plugin(Greeting { text: "你好呀".to_string() });
+//! // 在群 10000 中禁用插件
+//! bot.plugin_switch("问候").unwrap().disable_in(QQ(10000));
+//! # });
+//! ```
+use parking_lot::RwLock;
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+use super::QQ;
+use crate::{
+    messages::Message,
+    msg_framework::{Extensions, HandlerHandle, Middleware, Middlewares, Next, Outcome, Request},
+    App, Bot,
+};
+
+/// 插件，见[模块文档](self)
+pub trait Plugin: Send + Sync + 'static {
+    /// 插件的名字，同一个 bot 中不能重复
+    fn name(&self) -> &str;
+
+    /// 插件的说明
+    fn description(&self) -> &str {
+        ""
+    }
+
+    /// 在 `bot` 上注册插件的指令、handler、数据等，返回值会被忽略
+    fn register(&self, bot: Bot) -> Bot;
+}
+
+/// 插件的信息
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PluginInfo {
+    pub name: String,
+    pub description: String,
+    /// 插件中注册的指令，即指令的第一个关键词或者模式
+    pub commands: Vec<String>,
+}
+
+/// 插件的开关，群中单独的设置优先于全局的设置，默认全局启用
+#[derive(Debug, Clone)]
+pub struct PluginSwitch(Arc<SwitchState>);
+
+#[derive(Debug)]
+struct SwitchState {
+    enabled: AtomicBool,
+    groups: RwLock<HashMap<QQ, bool>>,
+}
+
+impl PluginSwitch {
+    fn new() -> Self {
+        Self(Arc::new(SwitchState {
+            enabled: AtomicBool::new(true),
+            groups: RwLock::default(),
+        }))
+    }
+
+    /// 全局启用插件
+    pub fn enable(&self) {
+        self.0.enabled.store(true, Ordering::Relaxed);
+    }
+
+    /// 全局禁用插件，没有单独设置的群、私聊以及定时任务都不再生效
+    pub fn disable(&self) {
+        self.0.enabled.store(false, Ordering::Relaxed);
+    }
+
+    /// 在群 `group` 中启用插件
+    pub fn enable_in(&self, group: QQ) {
+        self.0.groups.write().insert(group, true);
+    }
+
+    /// 在群 `group` 中禁用插件
+    pub fn disable_in(&self, group: QQ) {
+        self.0.groups.write().insert(group, false);
+    }
+
+    /// 清除群 `group` 单独的设置，之后跟随全局的设置
+    pub fn reset_in(&self, group: QQ) {
+        self.0.groups.write().remove(&group);
+    }
+
+    /// 插件是否全局启用
+    pub fn is_enabled(&self) -> bool {
+        self.0.enabled.load(Ordering::Relaxed)
+    }
+
+    /// 插件在群 `group` 中是否启用
+    pub fn is_enabled_in(&self, group: QQ) -> bool {
+        let group = self.0.groups.read().get(&group).copied();
+        group.unwrap_or_else(|| self.is_enabled())
+    }
+
+    /// 插件是否处理这条消息，群消息和临时会话按照群的设置
+    pub(crate) fn allows(&self, message: &Message) -> bool {
+        match message {
+            Message::Group(msg) => self.is_enabled_in(msg.sender.group.id),
+            Message::Temp(msg) => self.is_enabled_in(msg.sender.group.id),
+            _ => self.is_enabled(),
+        }
+    }
+}
+
+/// 插件注册时的状态，插件中的 bot 通过它使用插件自己的中间件和数据
+pub(crate) struct PluginScope {
+    pub name: String,
+    pub description: String,
+    /// 插件的中间件，外层是全局中间件
+    pub middlewares: Middlewares<Bot>,
+    /// 插件注册的数据，优先于全局的数据
+    pub extensions: RwLock<Extensions>,
+    pub switch: PluginSwitch,
+}
+
+/// 注册的所有插件，按照注册的顺序排列
+#[derive(Clone, Default)]
+pub(crate) struct Plugins(Arc<RwLock<Vec<Arc<PluginScope>>>>);
+
+/// 插件的最外层中间件，插件禁用时拦截请求
+struct PluginGate(PluginSwitch);
+
+#[async_trait]
+impl Middleware<Bot> for PluginGate {
+    async fn handle(&self, request: Request<Bot>, next: Next<'_, Bot>) -> Outcome {
+        match self.0.allows(&request.message) {
+            true => next.run(request).await,
+            false => Outcome::Skipped,
+        }
+    }
+}
+
+impl Bot {
+    /// 注册一个插件，见[模块文档](super::plugin)
+    ///
+    /// # Panics
+    /// 同名的插件已经注册时 panic。
+    pub fn plugin(self, plugin: impl Plugin) -> Self {
+        let scope = {
+            let mut plugins = self.plugins.0.write();
+            let name = plugin.name();
+            assert!(
+                plugins.iter().all(|scope| scope.name != name),
+                "插件 `{}` 已经注册",
+                name
+            );
+            let switch = PluginSwitch::new();
+            let middlewares = self.middlewares().child();
+            middlewares.push(PluginGate(switch.clone()));
+            let scope = Arc::new(PluginScope {
+                name: name.to_string(),
+                description: plugin.description().to_string(),
+                middlewares,
+                extensions: RwLock::new(Extensions::new()),
+                switch,
+            });
+            plugins.push(scope.clone());
+            scope
+        };
+        plugin.register(self.scoped(&scope));
+        self
+    }
+
+    /// 所有插件的信息，按照注册的顺序排列
+    pub fn plugins(&self) -> Vec<PluginInfo> {
+        let registry = self.kw_command_handlers.0.read();
+        self.plugins
+            .0
+            .read()
+            .iter()
+            .map(|scope| PluginInfo {
+                name: scope.name.clone(),
+                description: scope.description.clone(),
+                commands: registry.plugin_commands(scope),
+            })
+            .collect()
+    }
+
+    /// 插件 `name` 的开关，没有这个插件时返回 `None`
+    pub fn plugin_switch(&self, name: &str) -> Option<PluginSwitch> {
+        let plugins = self.plugins.0.read();
+        let scope = plugins.iter().find(|scope| scope.name == name)?;
+        Some(scope.switch.clone())
+    }
+}
+
+/// 注册 handler 之后可以继续链式注册插件
+impl HandlerHandle<Bot> {
+    /// 继续注册插件，见 [`Bot::plugin`]
+    pub fn plugin(self, plugin: impl Plugin) -> Bot {
+        self.into_app().plugin(plugin)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        messages::GroupMessage,
+        test_utils::{group_message, mock_bot},
+        Data,
+    };
+    use parking_lot::Mutex;
+
+    type Records = Arc<Mutex<Vec<(&'static str, String)>>>;
+
+    struct Echo(Records);
+
+    struct Tag(&'static str);
+
+    impl Plugin for Echo {
+        fn name(&self) -> &str {
+            "回声"
+        }
+
+        fn register(&self, bot: Bot) -> Bot {
+            let (commands, handlers) = (self.0.clone(), self.0.clone());
+            bot.bot_data(Data::new(Tag("插件")))
+                .command("回声", move |msg: GroupMessage, tag: Data<Tag>| {
+                    let commands = commands.clone();
+                    async move {
+                        let text = format!("{}{}", tag.0, msg.message.plain_text());
+                        commands.lock().push(("command", text));
+                    }
+                })
+                .handler(move |msg: GroupMessage, tag: Data<Tag>| {
+                    let handlers = handlers.clone();
+                    async move {
+                        let text = format!("{}{}", tag.0, msg.message.plain_text());
+                        handlers.lock().push(("handler", text));
+                    }
+                })
+                .into_app()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_plugin() {
+        let records = Records::default();
+        let (bot, _) = mock_bot(|_, _| None);
+        let bot = bot.plugin(Echo(records.clone()));
+        // 插件的数据只在插件中可见
+        assert!(bot.data::<Data<Tag>>().is_none());
+        assert_eq!(
+            bot.plugins(),
+            vec![PluginInfo {
+                name: "回声".to_string(),
+                description: String::new(),
+                commands: vec!["回声".to_string()],
+            }]
+        );
+
+        let switch = bot.plugin_switch("回声").unwrap();
+        let send = |group, text: &str| {
+            let message = Message::Group(group_message(group, 10, text));
+            bot.event_bus().send(message).unwrap();
+        };
+        send(100, "回声1");
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        switch.disable_in(QQ(200));
+        send(100, "回声2");
+        send(200, "回声3");
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        switch.disable();
+        switch.enable_in(QQ(200));
+        send(100, "回声4");
+        send(200, "回声5");
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+
+        let mut records = records.lock().clone();
+        records.sort();
+        let mut expected: Vec<_> = ["1", "2", "5"]
+            .into_iter()
+            .flat_map(|n| {
+                let text = format!("插件回声{}", n);
+                [("command", text.clone()), ("handler", text)]
+            })
+            .collect();
+        expected.sort();
+        assert_eq!(records, expected);
+    }
+}
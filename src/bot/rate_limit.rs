@@ -0,0 +1,385 @@
+//! 指令的频率限制和冷却，以及发送消息的全局限速。
+//!
+//! [`per_user`]、[`per_group`]、[`per_command`] 创建一个 [`RateLimit`]，
+//! 通过 [`Command::cooldown`](super::Command::cooldown) 注册到指令上。
+//! 冷却中的消息不会交给指令处理，默认会回复【冷却中，还剩 N 秒】，
+//! 不满足指令守卫（如权限不足）的消息不会收到这个回复。
+//!
+//! 频率限制使用令牌桶实现：每个 `period` 恢复一次使用次数，最多积攒 `burst` 次，
+//! 默认 `burst` 为 1，即固定的冷却时间。
+//!
+//! # Example
+//! ```no_run
+//! # use miraie::prelude::*;
+//! use miraie::bot::rate_limit::*;
+//! use std::time::Duration;
+//! # tokio_test::block_on(async {
+//! # let (bot, _) = Bot::new("127.0.0.1", "secret", QQ(123456)).await.unwrap();
+//! bot.command("抽卡", |_: GroupMessage| async { "..." })
+//!     // 每个人每分钟可以抽一次
+//!     .cooldown(per_user(Duration::from_secs(60)))
+//!     .command("天气", |_: Message| async { "..." })
+//!     // 每个群每 10 秒一次，最多连续使用 3 次，冷却中不回复
+//!     .cooldown(per_group(Duration::from_secs(10)).burst(3).silent());
+//! # });
+//! ```
+use parking_lot::Mutex;
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use super::QQ;
+use crate::{
+    messages::Message,
+    msg_framework::{Middleware, Next, Outcome, Request, Return},
+    Bot,
+};
+
+/// 超过这个数量时清理已经恢复满的令牌桶
+const MAX_IDLE_BUCKETS: usize = 1024;
+
+/// 频率限制，见[模块文档](self)
+pub struct RateLimit {
+    scope: Scope,
+    period: Duration,
+    burst: u32,
+    /// 冷却中回复的消息，`{}` 会被替换为剩余的秒数
+    reply: Option<String>,
+    buckets: Mutex<HashMap<Key, Bucket>>,
+}
+
+/// 按照什么区分使用者
+#[derive(Debug, Clone, Copy)]
+enum Scope {
+    User,
+    Group,
+    Command,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Key {
+    User(QQ),
+    Group(QQ),
+    Command,
+}
+
+/// 令牌桶
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// 每个发送者（QQ 号）单独计算冷却，包括私聊和所有群里的消息
+pub fn per_user(period: Duration) -> RateLimit {
+    RateLimit::new(Scope::User, period)
+}
+
+/// 每个群单独计算冷却，私聊按照每个用户单独计算
+pub fn per_group(period: Duration) -> RateLimit {
+    RateLimit::new(Scope::Group, period)
+}
+
+/// 所有人共享同一个冷却
+pub fn per_command(period: Duration) -> RateLimit {
+    RateLimit::new(Scope::Command, period)
+}
+
+impl RateLimit {
+    fn new(scope: Scope, period: Duration) -> Self {
+        assert!(!period.is_zero(), "冷却时间必须大于 0");
+        Self {
+            scope,
+            period,
+            burst: 1,
+            reply: Some("冷却中，还剩 {} 秒".to_string()),
+            buckets: Mutex::default(),
+        }
+    }
+
+    /// 最多可以连续使用 `burst` 次，之后每个 `period` 恢复一次
+    pub fn burst(mut self, burst: u32) -> Self {
+        assert!(burst > 0, "burst 必须大于 0");
+        self.burst = burst;
+        self
+    }
+
+    /// 冷却中回复的消息，`{}` 会被替换为剩余的秒数
+    pub fn reply(mut self, template: impl Into<String>) -> Self {
+        self.reply = Some(template.into());
+        self
+    }
+
+    /// 冷却中不回复，直接忽略消息
+    pub fn silent(mut self) -> Self {
+        self.reply = None;
+        self
+    }
+
+    fn key(&self, message: &Message) -> Option<Key> {
+        let (group, user) = match message {
+            Message::Group(msg) => (Some(msg.sender.group.id), msg.sender.id),
+            Message::Temp(msg) => (None, msg.sender.id),
+            Message::Friend(msg) => (None, msg.sender.id),
+            Message::Stranger(msg) => (None, msg.sender.id),
+            Message::Event(_) | Message::Scheduled(_) => return None,
+        };
+        Some(match (self.scope, group) {
+            (Scope::User, _) | (Scope::Group, None) => Key::User(user),
+            (Scope::Group, Some(group)) => Key::Group(group),
+            (Scope::Command, _) => Key::Command,
+        })
+    }
+
+    /// 使用一次，冷却中时返回剩余的时间
+    fn acquire(&self, key: Key, now: Instant) -> Result<(), Duration> {
+        let burst = f64::from(self.burst);
+        let mut buckets = self.buckets.lock();
+        if buckets.len() > MAX_IDLE_BUCKETS {
+            let period = self.period;
+            buckets.retain(|_, bucket| bucket.refill(now, period, burst) < burst);
+        }
+        let bucket = buckets.entry(key).or_insert(Bucket {
+            tokens: burst,
+            updated: now,
+        });
+        let tokens = bucket.refill(now, self.period, burst);
+        if tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            let remaining = self.period.as_secs_f64() * (1.0 - tokens);
+            Err(Duration::from_millis((remaining * 1000.0).round() as u64))
+        }
+    }
+}
+
+impl RateLimit {
+    /// 退还一次使用次数
+    fn refund(&self, key: Key) {
+        let burst = f64::from(self.burst);
+        if let Some(bucket) = self.buckets.lock().get_mut(&key) {
+            bucket.tokens = (bucket.tokens + 1.0).min(burst);
+        }
+    }
+}
+
+impl Bucket {
+    /// 按照经过的时间恢复令牌，返回当前的令牌数
+    fn refill(&mut self, now: Instant, period: Duration, burst: f64) -> f64 {
+        let elapsed = now.saturating_duration_since(self.updated);
+        self.tokens = (self.tokens + elapsed.as_secs_f64() / period.as_secs_f64()).min(burst);
+        self.updated = now;
+        self.tokens
+    }
+}
+
+impl RateLimit {
+    /// 限制请求的频率，冷却中并且 `would_run` 返回 `true` 时才回复
+    pub(crate) async fn limit(
+        &self,
+        request: Request<Bot>,
+        next: Next<'_, Bot>,
+        would_run: impl FnOnce(&Request<Bot>) -> bool,
+    ) -> Outcome {
+        let key = match self.key(&request.message) {
+            Some(key) => key,
+            None => return next.run(request).await,
+        };
+        match self.acquire(key, Instant::now()) {
+            Ok(()) => {
+                let outcome = next.run(request).await;
+                // 没有执行指令（如被内层的守卫拦截）时不消耗次数
+                if outcome == Outcome::Skipped {
+                    self.refund(key);
+                }
+                outcome
+            }
+            Err(remaining) => {
+                if let Some(template) = self.reply.as_ref().filter(|_| would_run(&request)) {
+                    let seconds = (remaining.as_millis() as u64).div_ceil(1000);
+                    let text = template.replace("{}", &seconds.to_string());
+                    text.on_return(request).await;
+                }
+                Outcome::Done
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl Middleware<Bot> for RateLimit {
+    async fn handle(&self, request: Request<Bot>, next: Next<'_, Bot>) -> Outcome {
+        self.limit(request, next, |_| true).await
+    }
+}
+
+/// 发送消息的全局限速，两条消息之间至少间隔 `interval`，发送过快的消息会排队等待。
+///
+/// 通过 [`Bot::send_throttle`] 注册后，回复消息以及
+/// [`Bot::send_group_message`]、[`Bot::send_friend_message`] 都会被限速，
+/// 被 [`SplitPolicy`](super::SplitPolicy) 拆分的消息每一条都单独计算。
+///
+/// # Example
+/// ```no_run
+/// # use miraie::prelude::*;
+/// use miraie::bot::rate_limit::SendThrottle;
+/// use std::time::Duration;
+/// # tokio_test::block_on(async {
+/// # let (bot, _) = Bot::new("127.0.0.1", "secret", QQ(123456)).await.unwrap();
+/// let bot = bot.send_throttle(SendThrottle::new(Duration::from_millis(500)));
+/// # });
+/// ```
+#[derive(Debug, Clone)]
+pub struct SendThrottle {
+    interval: Duration,
+    /// 下一条消息最早可以发送的时间
+    next: Arc<tokio::sync::Mutex<Option<tokio::time::Instant>>>,
+}
+
+impl SendThrottle {
+    /// 相邻两条消息至少间隔 `interval` 发送
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            next: Arc::default(),
+        }
+    }
+
+    /// 等到可以发送下一条消息
+    pub(crate) async fn wait(&self) {
+        let mut next = self.next.lock().await;
+        if let Some(next) = *next {
+            tokio::time::sleep_until(next).await;
+        }
+        *next = Some(tokio::time::Instant::now() + self.interval);
+    }
+}
+
+impl Bot {
+    /// 注册发送消息的全局限速，见 [`SendThrottle`]。
+    ///
+    /// 限速对所有发送的消息生效，在插件中注册也是一样，之后注册的会替换之前的。
+    pub fn send_throttle(self, throttle: SendThrottle) -> Self {
+        *self.send_throttle.write() = Some(throttle);
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        bot::Plugin,
+        messages::{group::Permission, GroupMessage},
+        test_utils::{friend_message, group_message, mock_bot},
+        App,
+    };
+
+    #[test]
+    fn test_rate_limit() {
+        let limit = per_user(Duration::from_secs(10)).burst(2);
+        let now = Instant::now();
+        let user = Key::User(QQ(1));
+        assert_eq!(limit.acquire(user, now), Ok(()));
+        assert_eq!(limit.acquire(user, now), Ok(()));
+        assert_eq!(limit.acquire(user, now), Err(Duration::from_secs(10)));
+        assert_eq!(limit.acquire(Key::User(QQ(2)), now), Ok(()));
+
+        let later = now + Duration::from_secs(4);
+        assert_eq!(limit.acquire(user, later), Err(Duration::from_secs(6)));
+        let later = now + Duration::from_secs(10);
+        assert_eq!(limit.acquire(user, later), Ok(()));
+        assert!(limit.acquire(user, later).is_err());
+        // 最多积攒 burst 次
+        let later = now + Duration::from_secs(100);
+        assert_eq!(limit.acquire(user, later), Ok(()));
+        assert_eq!(limit.acquire(user, later), Ok(()));
+        assert!(limit.acquire(user, later).is_err());
+    }
+
+    /// 收到消息，等待处理完
+    async fn receive(bot: &Bot, message: Message) {
+        bot.event_bus().send(message).unwrap();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+
+    #[tokio::test]
+    async fn test_cooldown() {
+        let (bot, sent) = mock_bot(|_, _| None);
+        bot.clone()
+            .command("抽卡", |_: GroupMessage| async { "抽到了" })
+            .cooldown(per_user(Duration::from_secs(60)))
+            .command("踢", |_: GroupMessage| async { "踢了" })
+            .cooldown(per_user(Duration::from_secs(60)))
+            .permission(Permission::Administrator)
+            .command("禁言", |_: GroupMessage| async { "禁言了" })
+            .cooldown(per_group(Duration::from_secs(60)))
+            .permission(Permission::Administrator);
+        let group = |text| Message::Group(group_message(100, 10, text));
+        let admin = |text| {
+            let mut message = group_message(100, 20, text);
+            message.sender.permission = Permission::Administrator;
+            Message::Group(message)
+        };
+
+        // 指令不处理的消息不计算冷却，也不回复
+        receive(&bot, Message::Friend(friend_message(10, "抽卡"))).await;
+        assert!(sent.take().is_empty());
+        receive(&bot, group("抽卡")).await;
+        assert_eq!(sent.take(), vec!["抽到了"]);
+        receive(&bot, group("抽卡")).await;
+        assert_eq!(sent.take(), vec!["冷却中，还剩 60 秒"]);
+
+        // 被守卫拦截的消息不消耗次数
+        receive(&bot, group("踢")).await;
+        receive(&bot, group("踢")).await;
+        assert!(sent.take().is_empty());
+        receive(&bot, admin("踢")).await;
+        assert_eq!(sent.take(), vec!["踢了"]);
+
+        // 冷却中只回复可以使用指令的人
+        receive(&bot, admin("禁言")).await;
+        assert_eq!(sent.take(), vec!["禁言了"]);
+        receive(&bot, group("禁言")).await;
+        assert!(sent.take().is_empty());
+        receive(&bot, admin("禁言")).await;
+        assert_eq!(sent.take(), vec!["冷却中，还剩 60 秒"]);
+    }
+
+    struct Throttled;
+
+    impl Plugin for Throttled {
+        fn name(&self) -> &str {
+            "限速"
+        }
+
+        fn register(&self, bot: Bot) -> Bot {
+            bot.send_throttle(SendThrottle::new(Duration::from_millis(20)))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_send_throttle_in_plugin() {
+        let (bot, _) = mock_bot(|_, _| None);
+        let bot = bot.plugin(Throttled);
+        // 插件中注册的限速也是全局的
+        let start = std::time::Instant::now();
+        for _ in 0..3 {
+            bot.send_group_message(QQ(100), "hi").await.unwrap();
+        }
+        assert!(start.elapsed() >= Duration::from_millis(40));
+    }
+
+    #[tokio::test]
+    async fn test_send_throttle() {
+        let throttle = SendThrottle::new(Duration::from_millis(20));
+        let start = std::time::Instant::now();
+        for _ in 0..3 {
+            throttle.wait().await;
+        }
+        assert!(start.elapsed() >= Duration::from_millis(40));
+    }
+}
@@ -1,7 +1,7 @@
 use std::fmt::{Debug, Display};
 
 use crate::messages::MessageChain;
-use crate::msg_framework::{Request, Return};
+use crate::msg_framework::{Outcome, Request, Return};
 use crate::prelude::{Bot, Conversation};
 
 #[async_trait]
@@ -47,6 +47,13 @@ where
             }
         }
     }
+
+    fn outcome(&self) -> Outcome {
+        match self {
+            Ok(_) => Outcome::Done,
+            Err(e) => Outcome::Failed(e.to_string()),
+        }
+    }
 }
 
 #[async_trait]
@@ -82,4 +89,11 @@ where
             }
         };
     }
+
+    fn outcome(&self) -> Outcome {
+        match self {
+            Ok(_) => Outcome::Done,
+            Err(e) => Outcome::Failed(e.to_string()),
+        }
+    }
 }
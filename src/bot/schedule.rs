@@ -0,0 +1,647 @@
+//! 定时任务：按照 cron 表达式或者固定的间隔执行任务。
+//!
+//! 通过 [`Bot::schedule`] 注册，任务和 handler 一样通过 [`FromRequest`] 提取参数，
+//! 可以提取 [`Bot`]、[`Data`](super::Data) 以及触发任务的 [`ScheduledEvent`]。
+//! 任务同样经过全局中间件以及并发限制，出错时也会交给 [`Bot::on_error`] 注册的错误处理。
+//!
+//! 返回的 [`HandlerHandle`] 可以用来暂停或者取消任务，跟 mirai 的连接关闭时所有任务都会停止。
+//!
+//! # Example
+//! ```no_run
+//! # use miraie::prelude::*;
+//! use chrono::FixedOffset;
+//! use miraie::bot::{CatchUp, Cron, Job, JobHistory};
+//! use std::time::Duration;
+//! # tokio_test::block_on(async {
+//! # let (bot, _) = Bot::new("127.0.0.1", "secret", QQ(123456)).await.unwrap();
+//! let beijing = FixedOffset::east_opt(8 * 3600).unwrap();
+//! bot.bot_data(JobHistory::file("jobs.json")?)
+//!     // 每天早上 8 点（本地时间），无效的 cron 表达式在解析时返回错误
+//!     .schedule(Cron::parse("0 8 * * *")?, |bot: Bot| async move {
+//!         bot.send_group_message(QQ(1234), "早上好").await.map(|_| ())
+//!     })
+//!     // 工作日北京时间 18 点，错过时在启动后补发一次
+//!     .schedule(
+//!         Job::new(Cron::parse("0 18 * * 1-5")?.timezone(beijing))
+//!             .name("日报")
+//!             .catch_up(CatchUp::Once),
+//!         |bot: Bot| async move {
+//!             bot.send_group_message(QQ(1234), "下班了").await.map(|_| ())
+//!         },
+//!     )
+//!     // 每小时一次
+//!     .schedule(Duration::from_secs(3600), || async { println!("tick") });
+//! # Result::<(), miraie::Error>::Ok(()) });
+//! ```
+use chrono::{
+    DateTime, Datelike, Duration as ChronoDuration, Local, NaiveDate, NaiveDateTime, TimeZone,
+    Timelike, Utc,
+};
+use futures::Future;
+use parking_lot::Mutex;
+use std::{collections::HashMap, path::PathBuf, str::FromStr, sync::Arc, time::Duration};
+use tokio::task::JoinHandle;
+
+use crate::{
+    messages::Message,
+    msg_framework::{
+        endpoint, run_limited, run_middlewares, Endpoint, FromRequest, Func, HandlerHandle,
+        HandlerState, Outcome, Request, Return,
+    },
+    App, Bot, Error, Result,
+};
+
+/// 启动时最多补执行的次数
+const MAX_CATCH_UP: usize = 100;
+
+/// 定时任务触发，由 [`Bot::schedule`] 产生，作为 [`Message::Scheduled`] 交给任务，不会从 mirai 收到
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScheduledEvent {
+    /// 任务的名字，见 [`Job::name`]
+    pub name: Option<String>,
+    /// 任务计划执行的时间
+    pub scheduled_at: DateTime<Utc>,
+    /// 是否是启动时补执行的错过的任务，见 [`CatchUp`]
+    pub catch_up: bool,
+}
+
+impl FromRequest<Bot> for ScheduledEvent {
+    fn from_request(request: &Request<Bot>) -> Option<Self> {
+        match &request.message {
+            Message::Scheduled(event) => Some(event.clone()),
+            _ => None,
+        }
+    }
+
+    fn may_extract(message: &Message) -> bool {
+        matches!(message, Message::Scheduled(_))
+    }
+}
+
+/// 定时任务的触发时间
+pub trait Trigger: Send + Sync + 'static {
+    /// `after` 之后（不包括 `after`）下一次执行的时间，不会再执行时返回 `None`
+    fn next_after(&self, after: DateTime<Utc>) -> Option<DateTime<Utc>>;
+}
+
+/// 固定的间隔，第一次在注册之后一个间隔执行
+impl Trigger for Duration {
+    fn next_after(&self, after: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        after.checked_add_signed(ChronoDuration::from_std(*self).ok()?)
+    }
+}
+
+/// cron 表达式，格式为 `分 时 日 月 星期`，默认使用本地时区。
+///
+/// 每个字段可以是 `*`、数字、范围 `a-b`，以及用 `,` 分隔的列表，都可以加上步长 `/n`，
+/// 如 `*/15`、`9-18/3`、`1,15`。星期中 0 和 7 都表示周日。
+/// 日和星期都不是 `*` 时，满足其中一个即可，跟常见的 cron 实现一致。
+///
+/// 也可以使用 `@yearly`、`@monthly`、`@weekly`、`@daily`、`@hourly`。
+#[derive(Debug, Clone)]
+pub struct Cron<Tz: TimeZone = Local> {
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    /// 日是否被限制，即不以 `*` 开头
+    days_restricted: bool,
+    /// 星期是否被限制，即不以 `*` 开头
+    weekdays_restricted: bool,
+    timezone: Tz,
+}
+
+impl Cron {
+    /// 解析 cron 表达式，格式错误时返回 [`Error::Format`]
+    pub fn parse(expr: &str) -> Result<Self> {
+        let expr = match expr.trim() {
+            "@yearly" | "@annually" => "0 0 1 1 *",
+            "@monthly" => "0 0 1 * *",
+            "@weekly" => "0 0 * * 0",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@hourly" => "0 * * * *",
+            expr => expr,
+        };
+        let fields: Vec<&str> = expr.split_whitespace().collect();
+        let [minutes, hours, days, months, weekdays] = fields[..] else {
+            return Err(Error::format(format!(
+                "cron 表达式需要 5 个字段，实际为 {} 个：{}",
+                fields.len(),
+                expr
+            )));
+        };
+        let mut weekday_bits = parse_field(weekdays, 0, 7)?;
+        // 7 也表示周日
+        if weekday_bits & (1 << 7) != 0 {
+            weekday_bits = (weekday_bits | 1) & !(1 << 7);
+        }
+        Ok(Self {
+            minutes: parse_field(minutes, 0, 59)?,
+            hours: parse_field(hours, 0, 23)?,
+            days: parse_field(days, 1, 31)?,
+            months: parse_field(months, 1, 12)?,
+            weekdays: weekday_bits,
+            days_restricted: !days.starts_with('*'),
+            weekdays_restricted: !weekdays.starts_with('*'),
+            timezone: Local,
+        })
+    }
+}
+
+impl FromStr for Cron {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Self::parse(s)
+    }
+}
+
+impl<Tz: TimeZone> Cron<Tz> {
+    /// 按照时区 `timezone` 计算执行时间
+    pub fn timezone<Tz2: TimeZone>(self, timezone: Tz2) -> Cron<Tz2> {
+        Cron {
+            minutes: self.minutes,
+            hours: self.hours,
+            days: self.days,
+            months: self.months,
+            weekdays: self.weekdays,
+            days_restricted: self.days_restricted,
+            weekdays_restricted: self.weekdays_restricted,
+            timezone,
+        }
+    }
+
+    fn day_matches(&self, date: NaiveDate) -> bool {
+        let day = contains(self.days, date.day());
+        let weekday = contains(self.weekdays, date.weekday().num_days_from_sunday());
+        match (self.days_restricted, self.weekdays_restricted) {
+            (true, true) => day || weekday,
+            _ => day && weekday,
+        }
+    }
+}
+
+impl<Tz> Trigger for Cron<Tz>
+where
+    Tz: TimeZone + Send + Sync + 'static,
+    Tz::Offset: Send + Sync,
+{
+    fn next_after(&self, after: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let local = after.with_timezone(&self.timezone).naive_local();
+        let mut time = at(local.date(), local.hour(), local.minute())? + ChronoDuration::minutes(1);
+        // 像 `0 0 30 2 *` 这样永远不会执行的表达式，最多向后找 5 年
+        let end = time + ChronoDuration::days(366 * 5);
+        while time < end {
+            if !contains(self.months, time.month()) {
+                let (year, month) = match time.month() {
+                    12 => (time.year() + 1, 1),
+                    month => (time.year(), month + 1),
+                };
+                time = at(NaiveDate::from_ymd_opt(year, month, 1)?, 0, 0)?;
+            } else if !self.day_matches(time.date()) {
+                time = at(time.date().succ_opt()?, 0, 0)?;
+            } else if !contains(self.hours, time.hour()) {
+                time = at(time.date(), time.hour(), 0)? + ChronoDuration::hours(1);
+            } else if !contains(self.minutes, time.minute()) {
+                time += ChronoDuration::minutes(1);
+            } else {
+                // 夏令时跳过的时间不存在，重复的时间取第一次
+                if let Some(next) = self.timezone.from_local_datetime(&time).earliest() {
+                    let next = next.with_timezone(&Utc);
+                    if next > after {
+                        return Some(next);
+                    }
+                }
+                time += ChronoDuration::minutes(1);
+            }
+        }
+        None
+    }
+}
+
+fn at(date: NaiveDate, hour: u32, minute: u32) -> Option<NaiveDateTime> {
+    date.and_hms_opt(hour, minute, 0)
+}
+
+fn contains(bits: u64, value: u32) -> bool {
+    bits & (1 << value) != 0
+}
+
+/// 解析 cron 表达式的一个字段，返回取值的集合
+fn parse_field(field: &str, min: u32, max: u32) -> Result<u64> {
+    let invalid = || Error::format(format!("无效的 cron 字段：{}", field));
+    let number = |s: &str| s.parse::<u32>().map_err(|_| invalid());
+    let mut bits = 0;
+    for item in field.split(',') {
+        let (range, step) = match item.split_once('/') {
+            Some((range, step)) => (range, Some(number(step)?)),
+            None => (item, None),
+        };
+        let (start, end) = match range.split_once('-') {
+            _ if range == "*" => (min, max),
+            Some((start, end)) => (number(start)?, number(end)?),
+            // `a/n` 表示从 a 开始直到最大值
+            None if step.is_some() => (number(range)?, max),
+            None => (number(range)?, number(range)?),
+        };
+        if step == Some(0) || start < min || end > max || start > end {
+            return Err(invalid());
+        }
+        for value in (start..=end).step_by(step.unwrap_or(1) as usize) {
+            bits |= 1 << value;
+        }
+    }
+    Ok(bits)
+}
+
+/// 启动时如何处理停机期间错过的任务，需要注册 [`JobHistory`] 并且设置 [`Job::name`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CatchUp {
+    /// 不补执行错过的任务
+    #[default]
+    Skip,
+    /// 有错过的任务时立即补执行一次
+    Once,
+    /// 按顺序补执行每一次错过的任务，最多 100 次
+    All,
+}
+
+/// 定时任务的设置
+///
+/// 可以直接从 [`Cron`] 以及 [`Duration`] 转换而来，cron 表达式通过 `TryFrom` 转换。
+pub struct Job {
+    trigger: Box<dyn Trigger>,
+    name: Option<String>,
+    catch_up: CatchUp,
+}
+
+impl Job {
+    /// 在 `trigger` 的时间执行的任务，没有名字，错过的任务不补执行
+    pub fn new(trigger: impl Trigger) -> Self {
+        Self {
+            trigger: Box::new(trigger),
+            name: None,
+            catch_up: CatchUp::Skip,
+        }
+    }
+
+    /// 任务的名字，[`JobHistory`] 按照名字记录每个任务最后执行的时间
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    /// 启动时如何处理错过的任务，默认不补执行
+    pub fn catch_up(mut self, policy: CatchUp) -> Self {
+        self.catch_up = policy;
+        self
+    }
+
+    /// 上次执行之后到 `now` 之间错过的执行时间
+    fn missed(&self, last: DateTime<Utc>, now: DateTime<Utc>) -> Vec<DateTime<Utc>> {
+        let mut missed = vec![];
+        let mut after = last;
+        while let Some(next) = self.trigger.next_after(after) {
+            if next > now || missed.len() >= MAX_CATCH_UP {
+                break;
+            }
+            missed.push(next);
+            after = next;
+        }
+        missed
+    }
+}
+
+/// cron 表达式无效时返回 [`Error::Format`]
+impl TryFrom<&str> for Job {
+    type Error = Error;
+
+    fn try_from(expr: &str) -> Result<Self> {
+        Cron::parse(expr).map(Job::new)
+    }
+}
+
+impl From<Duration> for Job {
+    fn from(interval: Duration) -> Self {
+        Job::new(interval)
+    }
+}
+
+impl<Tz> From<Cron<Tz>> for Job
+where
+    Tz: TimeZone + Send + Sync + 'static,
+    Tz::Offset: Send + Sync,
+{
+    fn from(cron: Cron<Tz>) -> Self {
+        Job::new(cron)
+    }
+}
+
+/// 记录定时任务最后执行的时间，用来在启动时找出错过的任务，见 [`CatchUp`]。
+///
+/// 通过 `bot.bot_data(..)` 注册，需要在注册任务之前注册，只会记录设置了名字的任务。
+#[derive(Debug, Clone, Default)]
+pub struct JobHistory {
+    path: Option<PathBuf>,
+    runs: Arc<Mutex<HashMap<String, DateTime<Utc>>>>,
+    /// 同时只有一次写入，避免写入同一个临时文件，也保证后记录的时间不会被先记录的覆盖
+    saving: Arc<tokio::sync::Mutex<()>>,
+}
+
+impl JobHistory {
+    /// 只保存在内存中，重启后丢失
+    pub fn memory() -> Self {
+        Self::default()
+    }
+
+    /// 保存在 JSON 文件中，文件不存在时会在第一次记录时创建
+    pub fn file(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        let runs = match std::fs::read(&path) {
+            Ok(content) => serde_json::from_slice(&content)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Self {
+            path: Some(path),
+            runs: Arc::new(Mutex::new(runs)),
+            saving: Arc::default(),
+        })
+    }
+
+    /// 任务 `name` 最后一次执行的时间
+    pub fn last_run(&self, name: &str) -> Option<DateTime<Utc>> {
+        self.runs.lock().get(name).copied()
+    }
+
+    /// 记录任务 `name` 在 `at` 执行，保存失败时只记录日志
+    pub async fn record(&self, name: &str, at: DateTime<Utc>) {
+        self.runs.lock().insert(name.to_string(), at);
+        if let Some(path) = &self.path {
+            if let Err(e) = self.save(path).await {
+                warn!("failed to save job history to {}: {}", path.display(), e);
+            }
+        }
+    }
+
+    /// 在阻塞线程中先写入临时文件再替换，写入一半时中断不会损坏原来的文件
+    async fn save(&self, path: &std::path::Path) -> Result<()> {
+        let _saving = self.saving.lock().await;
+        let content = serde_json::to_vec_pretty(&*self.runs.lock())?;
+        let path = path.to_path_buf();
+        let write = move || {
+            let mut tmp = path.clone().into_os_string();
+            tmp.push(".tmp");
+            std::fs::write(&tmp, content)?;
+            std::fs::rename(&tmp, &path)
+        };
+        tokio::task::spawn_blocking(write)
+            .await
+            .unwrap_or_else(|e| Err(std::io::Error::other(e)))?;
+        Ok(())
+    }
+}
+
+impl Bot {
+    /// 注册一个定时任务，`job` 可以是 [`Cron`]、[`Duration`] 或者 [`Job`]，
+    /// cron 表达式先用 [`Cron::parse`] 或者 `Job::try_from` 解析，见[模块文档](super::schedule)。
+    ///
+    /// 返回的句柄可以用来暂停或者取消任务，暂停期间到时间的任务会被跳过。
+    pub fn schedule<F, I, Fut>(self, job: impl Into<Job>, f: F) -> HandlerHandle<Bot>
+    where
+        F: Func<I, Fut>,
+        I: FromRequest<Bot> + Send + 'static,
+        Fut: Future + Send + 'static,
+        Fut::Output: Return<Bot>,
+    {
+        let job = job.into();
+        let handle = HandlerHandle::new(self.clone());
+        let state = handle.state();
+        let endpoint = endpoint(f);
+        let bot = self;
+        let task = async move {
+            let history = bot.data::<JobHistory>();
+            let run = |scheduled_at, catch_up| {
+                let event = ScheduledEvent {
+                    name: job.name.clone(),
+                    scheduled_at,
+                    catch_up,
+                };
+                run_job(&bot, &state, &endpoint, history.clone(), event)
+            };
+
+            let now = Utc::now();
+            let last = match (&history, &job.name) {
+                (Some(history), Some(name)) => history.last_run(name),
+                _ => None,
+            };
+            let missed = last.map(|last| job.missed(last, now)).unwrap_or_default();
+            match job.catch_up {
+                CatchUp::Skip => {}
+                CatchUp::Once => {
+                    if let Some(&scheduled_at) = missed.last() {
+                        run(scheduled_at, true);
+                    }
+                }
+                CatchUp::All => {
+                    for scheduled_at in missed {
+                        let _ = run(scheduled_at, true).await;
+                    }
+                }
+            }
+
+            let mut after = now;
+            while let Some(next) = job.trigger.next_after(after) {
+                let delay = (next - Utc::now()).to_std().unwrap_or_default();
+                tokio::select! {
+                    _ = tokio::time::sleep(delay) => {}
+                    _ = bot.closed() => break,
+                }
+                // 醒来得太晚时（如系统休眠）从现在开始计算，不连续执行错过的任务
+                after = next.max(Utc::now());
+                if !state.is_paused() {
+                    run(next, false);
+                }
+            }
+            debug!("scheduled job {:?} stopped", job.name);
+        };
+        let task = tokio::spawn(task);
+        handle.on_unregister(move || task.abort());
+        handle
+    }
+}
+
+/// 记录执行的时间，然后在全局中间件和并发限制下执行一次任务
+fn run_job(
+    bot: &Bot,
+    state: &HandlerState,
+    endpoint: &Arc<Endpoint<Bot>>,
+    history: Option<JobHistory>,
+    event: ScheduledEvent,
+) -> JoinHandle<Outcome> {
+    let record = (history, event.name.clone(), event.scheduled_at);
+    let request = Request::new(bot.clone(), Message::Scheduled(event));
+    let middlewares = bot.middlewares().snapshot();
+    let limiters = [state.limiter.clone(), bot.scheduler().limiter()];
+    let endpoint = endpoint.clone();
+    tokio::spawn(async move {
+        if let (Some(history), Some(name), at) = record {
+            history.record(&name, at).await;
+        }
+        let run = run_middlewares(&middlewares, &*endpoint, request);
+        run_limited(&limiters, run).await
+    })
+}
+
+/// 注册 handler 之后可以继续链式注册定时任务
+impl HandlerHandle<Bot> {
+    /// 继续注册定时任务，见 [`Bot::schedule`]
+    pub fn schedule<F, I, Fut>(self, job: impl Into<Job>, f: F) -> HandlerHandle<Bot>
+    where
+        F: Func<I, Fut>,
+        I: FromRequest<Bot> + Send + 'static,
+        Fut: Future + Send + 'static,
+        Fut::Output: Return<Bot>,
+    {
+        self.into_app().schedule(job, f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bot::QQ;
+    use chrono::FixedOffset;
+
+    fn utc(s: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc)
+    }
+
+    fn next(expr: &str, after: &str) -> Option<DateTime<Utc>> {
+        Cron::parse(expr)
+            .unwrap()
+            .timezone(Utc)
+            .next_after(utc(after))
+    }
+
+    #[test]
+    fn test_cron() {
+        let after = "2021-06-30T08:30:00Z";
+        assert_eq!(next("* * * * *", after), Some(utc("2021-06-30T08:31:00Z")));
+        assert_eq!(next("30 8 * * *", after), Some(utc("2021-07-01T08:30:00Z")));
+        assert_eq!(
+            next("*/20 9-18/3 * * *", after),
+            Some(utc("2021-06-30T09:00:00Z"))
+        );
+        assert_eq!(next("0 0 1 1 *", after), Some(utc("2022-01-01T00:00:00Z")));
+        assert_eq!(next("@monthly", after), Some(utc("2021-07-01T00:00:00Z")));
+        // 2021-06-30 是周三，7 表示周日
+        assert_eq!(next("0 12 * * 7", after), Some(utc("2021-07-04T12:00:00Z")));
+        assert_eq!(
+            next("0 12 * * 1-5", after),
+            Some(utc("2021-06-30T12:00:00Z"))
+        );
+        // 日和星期都被限制时满足一个即可
+        assert_eq!(next("0 0 15 * 5", after), Some(utc("2021-07-02T00:00:00Z")));
+        assert_eq!(next("0 0 29 2 *", after), Some(utc("2024-02-29T00:00:00Z")));
+        assert_eq!(next("0 0 30 2 *", after), None);
+
+        // 北京时间 9 点是 UTC 1 点
+        let beijing = FixedOffset::east_opt(8 * 3600).unwrap();
+        let cron = Cron::parse("0 9 * * *").unwrap().timezone(beijing);
+        assert_eq!(
+            cron.next_after(utc(after)),
+            Some(utc("2021-07-01T01:00:00Z"))
+        );
+
+        for expr in [
+            "* * * *",
+            "60 * * * *",
+            "* * 0 * *",
+            "*/0 * * * *",
+            "5-1 * * * *",
+            "a * * * *",
+        ] {
+            assert!(Cron::parse(expr).is_err(), "{}", expr);
+            assert!(Job::try_from(expr).is_err(), "{}", expr);
+        }
+        assert!(Job::try_from("@daily").is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_job_history_file() {
+        let path = std::env::temp_dir().join(format!("miraie-jobs-{}.json", std::process::id()));
+        let history = JobHistory::file(&path).unwrap();
+        let at = utc("2021-06-30T08:30:00Z");
+        futures::future::join_all((0..10).map(|i| {
+            history.record(
+                if i % 2 == 0 { "早报" } else { "晚报" },
+                at + ChronoDuration::minutes(i),
+            )
+        }))
+        .await;
+
+        // 重新打开文件，记录的是每个任务最后的时间
+        let history = JobHistory::file(&path).unwrap();
+        assert_eq!(history.last_run("早报"), Some(utc("2021-06-30T08:38:00Z")));
+        assert_eq!(history.last_run("晚报"), Some(utc("2021-06-30T08:39:00Z")));
+        let mut tmp = path.clone().into_os_string();
+        tmp.push(".tmp");
+        assert!(!std::path::Path::new(&tmp).exists());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_missed() {
+        let job = Job::new(Duration::from_secs(60));
+        let missed = job.missed(utc("2021-06-30T08:30:00Z"), utc("2021-06-30T08:33:30Z"));
+        assert_eq!(
+            missed,
+            vec![
+                utc("2021-06-30T08:31:00Z"),
+                utc("2021-06-30T08:32:00Z"),
+                utc("2021-06-30T08:33:00Z"),
+            ]
+        );
+        let missed = job.missed(utc("2021-06-30T08:30:00Z"), utc("2021-07-30T08:30:00Z"));
+        assert_eq!(missed.len(), MAX_CATCH_UP);
+    }
+
+    #[tokio::test]
+    async fn test_schedule() {
+        let runs = Arc::new(Mutex::new(Vec::new()));
+        let history = JobHistory::memory();
+        history
+            .record("job", Utc::now() - ChronoDuration::milliseconds(250))
+            .await;
+
+        let sink = runs.clone();
+        let handle = Bot::mock(QQ(1)).bot_data(history.clone()).schedule(
+            Job::new(Duration::from_millis(100))
+                .name("job")
+                .catch_up(CatchUp::All),
+            move |event: ScheduledEvent| {
+                let sink = sink.clone();
+                async move { sink.lock().push(event.catch_up) }
+            },
+        );
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(*runs.lock(), vec![true, true]);
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(*runs.lock(), vec![true, true, false]);
+        assert!(history.last_run("job").unwrap() > Utc::now() - ChronoDuration::seconds(1));
+
+        handle.pause();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(runs.lock().len(), 3);
+        handle.resume();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(runs.lock().len(), 4);
+
+        handle.unregister();
+        tokio::time::sleep(Duration::from_millis(150)).await;
+        assert_eq!(runs.lock().len(), 4);
+    }
+}
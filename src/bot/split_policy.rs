@@ -0,0 +1,188 @@
+use super::QQ;
+use crate::{
+    api::{self, common::SendMessageResponse},
+    messages::{ForwardNode, MessageBlock, MessageChain},
+    Bot, Result,
+};
+use chrono::Utc;
+
+/// 自动拆分过长消息的策略，默认不开启，通过 `bot.bot_data(SplitPolicy::new(..))` 注册后，
+/// 回复消息以及 [`Bot::send_group_message`]、[`Bot::send_friend_message`] 都会按照策略拆分。
+///
+/// 拆分的规则见 [`MessageChain::split_for_sending`]，拆分后的消息会按顺序逐条发送。
+///
+/// # Example
+/// ```no_run
+/// # use miraie::prelude::*;
+/// use miraie::bot::SplitPolicy;
+/// # tokio_test::block_on(async {
+/// # let (bot, _) = Bot::new("127.0.0.1", "secret", QQ(123456)).await.unwrap();
+/// // 每条消息最多 1000 字，拆分后超过 5 条时改为发送合并转发的聊天记录
+/// let bot = bot.bot_data(SplitPolicy::new(1000).forward_after(5));
+/// # });
+/// ```
+#[derive(Debug, Clone)]
+pub struct SplitPolicy {
+    max_len: usize,
+    forward_after: Option<usize>,
+    sender_name: String,
+}
+
+impl SplitPolicy {
+    /// 每条消息最多 `max_len` 个字符
+    pub fn new(max_len: usize) -> Self {
+        assert!(max_len > 0, "max_len 必须大于 0");
+        Self {
+            max_len,
+            forward_after: None,
+            sender_name: String::new(),
+        }
+    }
+
+    /// 拆分后超过 `max_parts` 条时，不再逐条发送，而是合并成一条转发的聊天记录
+    pub fn forward_after(mut self, max_parts: usize) -> Self {
+        self.forward_after = Some(max_parts);
+        self
+    }
+
+    /// 聊天记录中显示的发送者名字，默认为空
+    pub fn sender_name(mut self, name: impl Into<String>) -> Self {
+        self.sender_name = name.into();
+        self
+    }
+
+    /// 按照策略拆分消息，返回需要依次发送的消息
+    fn apply(&self, message: MessageChain, sender: QQ) -> Vec<MessageChain> {
+        let parts = message.split_for_sending(self.max_len);
+        match self.forward_after {
+            _ if parts.len() <= 1 => vec![message],
+            Some(max_parts) if parts.len() > max_parts => {
+                let time = Utc::now();
+                let nodes = parts
+                    .into_iter()
+                    .map(|message| ForwardNode {
+                        sender_id: sender,
+                        time,
+                        sender_name: self.sender_name.clone(),
+                        message,
+                        message_id: None,
+                    })
+                    .collect();
+                vec![MessageBlock::Forward { nodes }.into()]
+            }
+            _ => parts,
+        }
+    }
+}
+
+/// 消息发送的目标
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum SendTarget {
+    Group(QQ),
+    Friend(QQ),
+}
+
+impl Bot {
+    /// 发送群消息，如果注册了 [`SplitPolicy`]，过长的消息会被拆分
+    pub async fn send_group_message(
+        &self,
+        group: QQ,
+        message: impl Into<MessageChain>,
+    ) -> Result<SendMessageResponse> {
+        self.send_message(SendTarget::Group(group), None, message.into())
+            .await
+    }
+
+    /// 发送好友消息，如果注册了 [`SplitPolicy`]，过长的消息会被拆分
+    pub async fn send_friend_message(
+        &self,
+        friend: QQ,
+        message: impl Into<MessageChain>,
+    ) -> Result<SendMessageResponse> {
+        self.send_message(SendTarget::Friend(friend), None, message.into())
+            .await
+    }
+
+    /// 按照 [`SplitPolicy`] 发送消息，注册了 [`SendThrottle`](super::SendThrottle) 时每一条都会被限速，拆分成多条时只有第一条会引用 `quote`，返回第一条消息的结果
+    pub(crate) async fn send_message(
+        &self,
+        target: SendTarget,
+        quote: Option<i64>,
+        message: MessageChain,
+    ) -> Result<SendMessageResponse> {
+        let policy = self.data::<SplitPolicy>();
+        let parts = match policy {
+            Some(policy) => policy.apply(message, self.qq()),
+            None => vec![message],
+        };
+
+        let throttle = self.send_throttle.read().clone();
+
+        let mut first = None;
+        for (i, message) in parts.into_iter().enumerate() {
+            let quote = if i == 0 { quote } else { None };
+            if let Some(throttle) = &throttle {
+                throttle.wait().await;
+            }
+            let response = match target {
+                SendTarget::Group(target) => {
+                    self.request(api::send_group_message::Request {
+                        target,
+                        quote,
+                        message,
+                    })
+                    .await?
+                }
+                SendTarget::Friend(target) => {
+                    self.request(api::send_friend_message::Request {
+                        target,
+                        quote,
+                        message,
+                    })
+                    .await?
+                }
+            };
+            first.get_or_insert(SendMessageResponse {
+                target: Some(target),
+                ..response
+            });
+        }
+        Ok(first.expect("按照策略拆分后至少有一条消息"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_policy() {
+        let message = MessageChain::new().text("一。二。三。");
+
+        let policy = SplitPolicy::new(100);
+        assert_eq!(policy.apply(message.clone(), QQ(1)), vec![message.clone()]);
+
+        let policy = SplitPolicy::new(2);
+        assert_eq!(
+            policy.apply(message.clone(), QQ(1)),
+            vec![
+                MessageChain::new().text("一。"),
+                MessageChain::new().text("二。"),
+                MessageChain::new().text("三。"),
+            ]
+        );
+
+        let policy = SplitPolicy::new(2).forward_after(2).sender_name("bot");
+        let parts = policy.apply(message, QQ(1));
+        assert_eq!(parts.len(), 1);
+        match &parts[0].0[..] {
+            [MessageBlock::Forward { nodes }] => {
+                assert_eq!(nodes.len(), 3);
+                assert_eq!(nodes[0].sender_id, QQ(1));
+                assert_eq!(nodes[0].sender_name, "bot");
+                assert_eq!(nodes[2].message, MessageChain::new().text("三。"));
+            }
+            _ => panic!("expect forward message, got {:?}", parts),
+        }
+    }
+}
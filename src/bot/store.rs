@@ -0,0 +1,508 @@
+//! 持久化的键值存储，用来保存群设置、计数、用户绑定之类的少量状态。
+//!
+//! [`Store`] 是存储的后端，内置了保存到 JSON 文件的 [`JsonFileStore`] 以及只在内存中的 [`MemoryStore`]，
+//! 也可以为数据库等实现 [`Store`]。通过 `bot.bot_data(Storage::new(..))` 注册后，
+//! handler 中可以使用 [`GroupStore`]、[`UserStore`]、[`BotStore`] 提取当前群、当前用户以及整个 bot 的存储。
+//!
+//! 存储按照命名空间隔离，插件中提取的存储还会按照插件隔离，不同的插件使用同样的键不会冲突。
+//! 值使用 JSON 保存，可以是任何实现了 `Serialize` 和 `Deserialize` 的类型。
+//!
+//! # Example
+//! ```no_run
+//! # use miraie::prelude::*;
+//! use miraie::bot::store::{GroupStore, JsonFileStore, Storage, UserStore};
+//! # tokio_test::block_on(async {
+//! # let (bot, _) = Bot::new("127.0.0.1", "secret", QQ(123456)).await.unwrap();
+//! bot.bot_data(Storage::new(JsonFileStore::open("data.json")?))
+//!     .command("签到", |store: UserStore| async move {
+//!         let days = store.update("签到", |days: Option<u32>| days.unwrap_or(0) + 1).await?;
+//!         Result::<_, miraie::Error>::Ok(format!("已经签到 {} 天", days))
+//!     })
+//!     .command("欢迎语", |store: GroupStore, Args(Rest(text)): Args<Rest<String>>| async move {
+//!         store.set("欢迎语", &text).await?;
+//!         Result::<_, miraie::Error>::Ok("设置成功")
+//!     });
+//! # Result::<(), miraie::Error>::Ok(()) });
+//! ```
+use parking_lot::{Mutex, RwLock};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+use std::{
+    collections::HashMap,
+    ops::Deref,
+    path::PathBuf,
+    sync::{Arc, Weak},
+};
+
+use crate::{
+    messages::Message,
+    msg_framework::{FromRequest, Request},
+    Bot, Result,
+};
+
+/// 存储的后端，按照命名空间保存键值对
+#[async_trait]
+pub trait Store: Send + Sync + 'static {
+    async fn get(&self, namespace: &str, key: &str) -> Result<Option<Value>>;
+
+    async fn set(&self, namespace: &str, key: &str, value: Value) -> Result<()>;
+
+    /// 删除键，返回原来的值
+    async fn remove(&self, namespace: &str, key: &str) -> Result<Option<Value>>;
+
+    /// 命名空间中所有的键
+    async fn keys(&self, namespace: &str) -> Result<Vec<String>>;
+}
+
+type Namespaces = HashMap<String, HashMap<String, Value>>;
+
+/// 只保存在内存中的存储，重启后丢失，适合测试
+#[derive(Debug, Default)]
+pub struct MemoryStore(RwLock<Namespaces>);
+
+impl MemoryStore {
+    /// 空的存储
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl Store for MemoryStore {
+    async fn get(&self, namespace: &str, key: &str) -> Result<Option<Value>> {
+        let namespaces = self.0.read();
+        Ok(namespaces
+            .get(namespace)
+            .and_then(|ns| ns.get(key))
+            .cloned())
+    }
+
+    async fn set(&self, namespace: &str, key: &str, value: Value) -> Result<()> {
+        let mut namespaces = self.0.write();
+        let ns = namespaces.entry(namespace.to_string()).or_default();
+        ns.insert(key.to_string(), value);
+        Ok(())
+    }
+
+    async fn remove(&self, namespace: &str, key: &str) -> Result<Option<Value>> {
+        let mut namespaces = self.0.write();
+        Ok(namespaces.get_mut(namespace).and_then(|ns| ns.remove(key)))
+    }
+
+    async fn keys(&self, namespace: &str) -> Result<Vec<String>> {
+        let namespaces = self.0.read();
+        let keys = namespaces
+            .get(namespace)
+            .map(|ns| ns.keys().cloned().collect());
+        Ok(keys.unwrap_or_default())
+    }
+}
+
+/// 保存在一个 JSON 文件中的存储，所有数据都在内存中，每次修改后写入整个文件
+#[derive(Debug)]
+pub struct JsonFileStore {
+    path: PathBuf,
+    data: MemoryStore,
+    /// 同时只有一次写入，避免写入同一个临时文件，也保证后修改的数据不会被先修改的覆盖
+    saving: tokio::sync::Mutex<()>,
+}
+
+impl JsonFileStore {
+    /// 打开 JSON 文件，文件不存在时会在第一次修改时创建
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        let namespaces = match std::fs::read(&path) {
+            Ok(content) => serde_json::from_slice(&content)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Namespaces::new(),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Self {
+            path,
+            data: MemoryStore(RwLock::new(namespaces)),
+            saving: tokio::sync::Mutex::new(()),
+        })
+    }
+
+    /// 在阻塞线程中先写入临时文件再替换，写入一半时中断不会损坏原来的文件
+    async fn save(&self) -> Result<()> {
+        let _saving = self.saving.lock().await;
+        let content = serde_json::to_vec_pretty(&*self.data.0.read())?;
+        let path = self.path.clone();
+        let write = move || {
+            let mut tmp = path.clone().into_os_string();
+            tmp.push(".tmp");
+            std::fs::write(&tmp, content)?;
+            std::fs::rename(&tmp, &path)
+        };
+        tokio::task::spawn_blocking(write)
+            .await
+            .unwrap_or_else(|e| Err(std::io::Error::other(e)))?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Store for JsonFileStore {
+    async fn get(&self, namespace: &str, key: &str) -> Result<Option<Value>> {
+        self.data.get(namespace, key).await
+    }
+
+    async fn set(&self, namespace: &str, key: &str, value: Value) -> Result<()> {
+        self.data.set(namespace, key, value).await?;
+        self.save().await
+    }
+
+    async fn remove(&self, namespace: &str, key: &str) -> Result<Option<Value>> {
+        let value = self.data.remove(namespace, key).await?;
+        if value.is_some() {
+            self.save().await?;
+        }
+        Ok(value)
+    }
+
+    async fn keys(&self, namespace: &str) -> Result<Vec<String>> {
+        self.data.keys(namespace).await
+    }
+}
+
+/// 注册到 bot 上的存储，见[模块文档](self)
+#[derive(Clone)]
+pub struct Storage {
+    store: Arc<dyn Store>,
+    /// 每个键的锁，保证同一个键的 [`Namespace::update`]、`set`、`remove` 依次执行，
+    /// 没有在使用的锁会在之后加锁时清理
+    key_locks: Arc<Mutex<KeyLocks>>,
+}
+
+type KeyLock = tokio::sync::Mutex<()>;
+
+/// 命名空间以及键对应的锁
+type KeyLocks = HashMap<(String, String), Weak<KeyLock>>;
+
+impl Storage {
+    /// 把数据保存在 `store` 中的存储
+    pub fn new(store: impl Store) -> Self {
+        Self {
+            store: Arc::new(store),
+            key_locks: Arc::default(),
+        }
+    }
+
+    /// 锁住命名空间 `namespace` 中的 `key`，返回的锁被丢弃时释放
+    async fn lock(&self, namespace: &str, key: &str) -> tokio::sync::OwnedMutexGuard<()> {
+        let lock = {
+            let mut locks = self.key_locks.lock();
+            let id = (namespace.to_string(), key.to_string());
+            match locks.get(&id).and_then(Weak::upgrade) {
+                Some(lock) => lock,
+                None => {
+                    locks.retain(|_, lock| lock.strong_count() > 0);
+                    let lock = Arc::new(KeyLock::new(()));
+                    locks.insert(id, Arc::downgrade(&lock));
+                    lock
+                }
+            }
+        };
+        lock.lock_owned().await
+    }
+
+    /// 命名空间 `name` 中的存储
+    pub fn namespace(&self, name: impl Into<String>) -> Namespace {
+        Namespace {
+            storage: self.clone(),
+            name: name.into(),
+        }
+    }
+
+    /// 整个 bot 共享的存储
+    pub fn global(&self) -> Namespace {
+        self.namespace("global")
+    }
+
+    /// 群 `group` 的存储
+    pub fn group(&self, group: super::QQ) -> Namespace {
+        self.namespace(format!("group:{}", group))
+    }
+
+    /// 用户 `user` 的存储，私聊和所有群里共享
+    pub fn user(&self, user: super::QQ) -> Namespace {
+        self.namespace(format!("user:{}", user))
+    }
+}
+
+/// 一个命名空间中的存储
+#[derive(Clone)]
+pub struct Namespace {
+    storage: Storage,
+    name: String,
+}
+
+impl Namespace {
+    /// 命名空间的名字
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// 读取 `key` 的值，不存在时返回 `None`，格式不对时返回 [`Error::Json`](crate::Error::Json)
+    pub async fn get<T: DeserializeOwned>(&self, key: &str) -> Result<Option<T>> {
+        match self.storage.store.get(&self.name, key).await? {
+            Some(value) => Ok(Some(serde_json::from_value(value)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// 保存 `key` 的值，会等待同一个键上正在执行的 [`Namespace::update`] 完成
+    pub async fn set<T: Serialize + ?Sized>(&self, key: &str, value: &T) -> Result<()> {
+        let value = serde_json::to_value(value)?;
+        let _lock = self.storage.lock(&self.name, key).await;
+        self.storage.store.set(&self.name, key, value).await
+    }
+
+    /// 删除 `key`，返回是否存在，会等待同一个键上正在执行的 [`Namespace::update`] 完成
+    pub async fn remove(&self, key: &str) -> Result<bool> {
+        let _lock = self.storage.lock(&self.name, key).await;
+        let value = self.storage.store.remove(&self.name, key).await?;
+        Ok(value.is_some())
+    }
+
+    /// 命名空间中所有的键
+    pub async fn keys(&self) -> Result<Vec<String>> {
+        self.storage.store.keys(&self.name).await
+    }
+
+    /// 读取 `key` 的值，用 `f` 计算新的值并保存，返回新的值。
+    ///
+    /// 同一个 [`Storage`] 中同一个键的 `update`、`set`、`remove` 依次执行，
+    /// 读取和保存之间不会被修改，可以用来实现计数之类的功能。
+    /// 锁只在同一个 [`Storage`] 中有效，其他进程对存储后端的修改不受影响。
+    pub async fn update<T, F>(&self, key: &str, f: F) -> Result<T>
+    where
+        T: Serialize + DeserializeOwned,
+        F: FnOnce(Option<T>) -> T,
+    {
+        let _lock = self.storage.lock(&self.name, key).await;
+        let value = f(self.get(key).await?);
+        let json = serde_json::to_value(&value)?;
+        self.storage.store.set(&self.name, key, json).await?;
+        Ok(value)
+    }
+}
+
+/// 请求中命名空间 `name` 的存储，插件中的请求使用插件自己的命名空间
+fn scoped(request: &Request<Bot>, name: String) -> Option<Namespace> {
+    let storage = request.app.data::<Storage>()?;
+    let name = match request.app.plugin_name() {
+        Some(plugin) => format!("plugin:{}/{}", plugin, name),
+        None => name,
+    };
+    Some(storage.namespace(name))
+}
+
+macro_rules! scoped_store {
+    ($(#[$doc:meta])* $name:ident) => {
+        $(#[$doc])*
+        #[derive(Clone)]
+        pub struct $name(pub Namespace);
+
+        impl Deref for $name {
+            type Target = Namespace;
+
+            fn deref(&self) -> &Namespace {
+                &self.0
+            }
+        }
+    };
+}
+
+scoped_store! {
+    /// 当前群的存储，只能从群消息和临时会话中提取，需要注册 [`Storage`]
+    GroupStore
+}
+
+scoped_store! {
+    /// 消息发送者的存储，私聊和所有群里共享，需要注册 [`Storage`]
+    UserStore
+}
+
+scoped_store! {
+    /// 整个 bot（在插件中为整个插件）共享的存储，需要注册 [`Storage`]
+    BotStore
+}
+
+impl FromRequest<Bot> for GroupStore {
+    fn from_request(request: &Request<Bot>) -> Option<Self> {
+        let group = match &request.message {
+            Message::Group(msg) => msg.sender.group.id,
+            Message::Temp(msg) => msg.sender.group.id,
+            _ => return None,
+        };
+        scoped(request, format!("group:{}", group)).map(GroupStore)
+    }
+
+    fn may_extract(message: &Message) -> bool {
+        matches!(message, Message::Group(_) | Message::Temp(_))
+    }
+}
+
+impl FromRequest<Bot> for UserStore {
+    fn from_request(request: &Request<Bot>) -> Option<Self> {
+        let user = match &request.message {
+            Message::Friend(msg) => msg.sender.id,
+            Message::Group(msg) => msg.sender.id,
+            Message::Temp(msg) => msg.sender.id,
+            Message::Stranger(msg) => msg.sender.id,
+            Message::Event(_) | Message::Scheduled(_) => return None,
+        };
+        scoped(request, format!("user:{}", user)).map(UserStore)
+    }
+
+    fn may_extract(message: &Message) -> bool {
+        !matches!(message, Message::Event(_) | Message::Scheduled(_))
+    }
+}
+
+impl FromRequest<Bot> for BotStore {
+    fn from_request(request: &Request<Bot>) -> Option<Self> {
+        scoped(request, "global".to_string()).map(BotStore)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        bot::{Plugin, QQ},
+        test_utils::friend_message,
+    };
+
+    #[tokio::test]
+    async fn test_json_file_store() {
+        let path = std::env::temp_dir().join(format!("miraie-store-{}.json", std::process::id()));
+        let storage = Storage::new(JsonFileStore::open(&path).unwrap());
+        let group = storage.group(QQ(100));
+        group.set("欢迎语", "你好").await.unwrap();
+        assert_eq!(
+            group.get::<String>("欢迎语").await.unwrap().unwrap(),
+            "你好"
+        );
+        assert_eq!(
+            storage
+                .group(QQ(200))
+                .get::<String>("欢迎语")
+                .await
+                .unwrap(),
+            None
+        );
+        assert!(group.get::<u32>("欢迎语").await.is_err());
+
+        let counter = storage.global();
+        let tasks = (0..10).map(|_| {
+            let counter = counter.clone();
+            tokio::spawn(async move {
+                counter
+                    .update("count", |n: Option<u32>| n.unwrap_or(0) + 1)
+                    .await
+            })
+        });
+        for task in futures::future::join_all(tasks).await {
+            task.unwrap().unwrap();
+        }
+
+        // 重新打开文件
+        let storage = Storage::new(JsonFileStore::open(&path).unwrap());
+        assert_eq!(
+            storage.global().get::<u32>("count").await.unwrap(),
+            Some(10)
+        );
+        let group = storage.group(QQ(100));
+        assert_eq!(group.keys().await.unwrap(), vec!["欢迎语"]);
+        assert!(group.remove("欢迎语").await.unwrap());
+        assert!(!group.remove("欢迎语").await.unwrap());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    /// 读取很慢的存储
+    struct SlowStore(MemoryStore);
+
+    #[async_trait]
+    impl Store for SlowStore {
+        async fn get(&self, namespace: &str, key: &str) -> Result<Option<Value>> {
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+            self.0.get(namespace, key).await
+        }
+
+        async fn set(&self, namespace: &str, key: &str, value: Value) -> Result<()> {
+            self.0.set(namespace, key, value).await
+        }
+
+        async fn remove(&self, namespace: &str, key: &str) -> Result<Option<Value>> {
+            self.0.remove(namespace, key).await
+        }
+
+        async fn keys(&self, namespace: &str) -> Result<Vec<String>> {
+            self.0.keys(namespace).await
+        }
+    }
+
+    #[tokio::test]
+    async fn test_set_waits_for_update() {
+        let store = Storage::new(SlowStore(MemoryStore::new())).global();
+        let counter = store.clone();
+        let update = tokio::spawn(async move {
+            counter
+                .update("count", |n: Option<u32>| n.unwrap_or(0) + 1)
+                .await
+        });
+        tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+        // update 读取之后、保存之前的 set 不会被覆盖
+        store.set("count", &100).await.unwrap();
+        assert_eq!(update.await.unwrap().unwrap(), 1);
+        assert_eq!(store.get::<u32>("count").await.unwrap(), Some(100));
+        // 用完的锁在之后加锁时清理
+        store.set("other", &1).await.unwrap();
+        assert_eq!(store.storage.key_locks.lock().len(), 1);
+    }
+
+    struct Capture(Arc<Mutex<Option<Bot>>>);
+
+    impl Plugin for Capture {
+        fn name(&self) -> &str {
+            "插件"
+        }
+
+        fn register(&self, bot: Bot) -> Bot {
+            *self.0.lock() = Some(bot.clone());
+            bot
+        }
+    }
+
+    #[tokio::test]
+    async fn test_scoped_store() {
+        let slot = Arc::default();
+        let bot = Bot::mock(QQ(1))
+            .bot_data(Storage::new(MemoryStore::new()))
+            .plugin(Capture(Arc::clone(&slot)));
+        let plugin_bot = slot.lock().take().unwrap();
+
+        let message = Message::Friend(friend_message(10, ""));
+        let request = Request::new(bot.clone(), message.clone());
+        assert!(GroupStore::from_request(&request).is_none());
+        assert_eq!(UserStore::from_request(&request).unwrap().name(), "user:10");
+        assert_eq!(BotStore::from_request(&request).unwrap().name(), "global");
+
+        let request = Request::new(plugin_bot, message);
+        let store = UserStore::from_request(&request).unwrap();
+        assert_eq!(store.name(), "plugin:插件/user:10");
+
+        let request = Request::new(
+            Bot::mock(QQ(1)),
+            Message::Scheduled(crate::bot::ScheduledEvent {
+                name: None,
+                scheduled_at: chrono::Utc::now(),
+                catch_up: false,
+            }),
+        );
+        // 没有注册 Storage
+        assert!(BotStore::from_request(&request).is_none());
+    }
+}
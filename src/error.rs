@@ -3,18 +3,23 @@ use thiserror::Error;
 
 #[derive(Debug, Error)]
 pub enum Error {
+    /// websocket 的错误体积较大，装箱后 `Result` 不会因此变大
     #[error("Websocket error: {0}")]
-    Websocket(#[from] async_tungstenite::tungstenite::Error),
+    Websocket(Box<async_tungstenite::tungstenite::Error>),
 
+    /// 序列化或者反序列化 JSON 失败
     #[error("Json error: {0}")]
     Json(#[from] serde_json::Error),
 
+    /// 数据的格式不正确，如无法解析的消息 XML、cron 表达式等
     #[error("format error: {}", .reason)]
     Format { reason: String },
 
+    /// 和 mirai 的连接已经断开
     #[error("The connection to mirai bot is closed.")]
     ConnectionClosed,
 
+    /// mirai 没有在规定时间内回复请求
     #[error("Request to mirai bot has timeout.")]
     RequestTimeout,
 
@@ -22,11 +27,29 @@ pub enum Error {
     #[error("Response timeout.")]
     ResponseTimeout,
 
+    /// 用户取消了会话，见 [`Session`](crate::messages::Session)
+    #[error("Session cancelled.")]
+    SessionCancelled,
+
+    /// 用户在会话中输入错误的次数过多
+    #[error("Too many invalid responses in session.")]
+    TooManyRetries,
+
+    /// mirai 返回了表示失败的状态码
     #[error("Request error: code = {}, msg = {}", .code, msg)]
     Request { code: i32, msg: String },
+
+    /// 下载图片、语音、文件等失败
+    #[error("Download error: {0}")]
+    Download(String),
+
+    /// 读写文件等 IO 操作失败
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
 }
 
 impl Error {
+    /// 以 `reason` 为原因的 [`Error::Format`]
     pub fn format(reason: impl Into<String>) -> Self {
         Self::Format {
             reason: reason.into(),
@@ -34,4 +57,17 @@ impl Error {
     }
 }
 
+/// 不会失败的转换，如 `TryFrom` 的默认实现
+impl From<std::convert::Infallible> for Error {
+    fn from(e: std::convert::Infallible) -> Self {
+        match e {}
+    }
+}
+
+impl From<async_tungstenite::tungstenite::Error> for Error {
+    fn from(e: async_tungstenite::tungstenite::Error) -> Self {
+        Self::Websocket(Box::new(e))
+    }
+}
+
 pub type Result<T, E = Error> = std::result::Result<T, E>;
@@ -13,6 +13,8 @@ pub mod bot;
 pub mod error;
 pub mod messages;
 pub mod msg_framework;
+#[cfg(test)]
+mod test_utils;
 
 pub use api::Api;
 pub use bot::{Bot, Data};
@@ -24,11 +26,11 @@ pub mod prelude {
 
     pub use super::{
         api,
-        bot::QQ,
+        bot::{Arg, Args, Optional, Rest, QQ},
         messages::{
-            events, Conversation, Event, FriendMessage, GroupMessage, Message, MessageBlock,
-            MessageChain,
+            events, Conversation, Event, FriendMessage, GroupMessage, MediaSource, Message,
+            MessageBlock, MessageChain, Session,
         },
-        Api, App, Bot, Data,
+        message_chain, Api, App, Bot, Data,
     };
 }
@@ -0,0 +1,180 @@
+//! [`message_chain!`](crate::message_chain) 中使用的简写，每个函数都会生成一个 [`MessageBlock`]。
+//!
+//! 在宏里面可以直接使用这些函数，不需要额外导入。
+use super::{MessageBlock, PokeKind};
+use crate::bot::QQ;
+
+/// 文字，见 [`MessageBlock::text`]
+pub fn text(text: impl Into<String>) -> MessageBlock {
+    MessageBlock::text(text)
+}
+
+/// at 某人，见 [`MessageBlock::at`]
+pub fn at(qq: impl Into<QQ>) -> MessageBlock {
+    MessageBlock::at(qq.into())
+}
+
+/// at 全体成员
+pub fn at_all() -> MessageBlock {
+    MessageBlock::AtAll
+}
+
+/// QQ 表情，见 [`MessageBlock::face`]
+pub fn face(face_id: i32) -> MessageBlock {
+    MessageBlock::face(face_id)
+}
+
+/// 引用回复，见 [`MessageBlock::quote`]
+pub fn quote(id: i64) -> MessageBlock {
+    MessageBlock::quote(id)
+}
+
+/// 来自 url 的图片，见 [`MessageBlock::image_url`]
+pub fn img_url(url: impl Into<String>) -> MessageBlock {
+    MessageBlock::image_url(url)
+}
+
+/// 本地图片，见 [`MessageBlock::image_path`]
+pub fn img_path(path: impl AsRef<str>) -> MessageBlock {
+    MessageBlock::image_path(path)
+}
+
+/// 通过 imageId 发送图片，见 [`MessageBlock::image_id`]
+pub fn img_id(image_id: impl Into<String>) -> MessageBlock {
+    MessageBlock::image_id(image_id)
+}
+
+/// 直接发送图片的内容，见 [`MessageBlock::image_bytes`]
+pub fn img_bytes(bytes: impl AsRef<[u8]>) -> MessageBlock {
+    MessageBlock::image_bytes(bytes)
+}
+
+/// 来自 url 的闪照，见 [`MessageBlock::flash_image_url`]
+pub fn flash_url(url: impl Into<String>) -> MessageBlock {
+    MessageBlock::flash_image_url(url)
+}
+
+/// 来自 url 的语音，见 [`MessageBlock::voice_url`]
+pub fn voice_url(url: impl Into<String>) -> MessageBlock {
+    MessageBlock::voice_url(url)
+}
+
+/// 本地语音，见 [`MessageBlock::voice_path`]
+pub fn voice_path(path: impl AsRef<str>) -> MessageBlock {
+    MessageBlock::voice_path(path)
+}
+
+/// 骰子
+pub fn dice(value: u32) -> MessageBlock {
+    MessageBlock::dice(value)
+}
+
+/// 戳一戳
+pub fn poke(kind: PokeKind) -> MessageBlock {
+    MessageBlock::poke(kind)
+}
+
+/// 快速构建 [`MessageChain`](crate::messages::MessageChain)。
+///
+/// 每一项可以是：
+/// - 字面量，相邻的字面量会合并成一个文字消息块；
+/// - [`blocks`](crate::messages::blocks) 中的简写，如 `at(qq)`、`face(14)`、`img_url("...")`、`quote(id)`；
+/// - 任何实现了 `Into<MessageBlock>` 的表达式，如 `String`、[`MessageBlock`]。
+///
+/// # Example
+/// ```
+/// use miraie::prelude::*;
+///
+/// let qq = QQ(12345);
+/// let name = String::from("小明");
+/// let chain = message_chain![
+///     quote(42),
+///     "你好，", "世界",
+///     at(qq),
+///     face(14),
+///     "\n",
+///     name,
+///     img_url("http://example.com/a.jpg"),
+///     MessageBlock::dice(6),
+/// ];
+/// assert_eq!(
+///     chain,
+///     MessageChain(vec![
+///         MessageBlock::quote(42),
+///         MessageBlock::text("你好，世界"),
+///         MessageBlock::at(qq),
+///         MessageBlock::face(14),
+///         MessageBlock::text("\n"),
+///         MessageBlock::text("小明"),
+///         MessageBlock::image_url("http://example.com/a.jpg"),
+///         MessageBlock::dice(6),
+///     ])
+/// );
+///
+/// assert_eq!(message_chain![], MessageChain::new());
+/// ```
+///
+/// 简写只在宏里可用，不会污染外部的命名空间，即使导入了 prelude：
+/// ```compile_fail
+/// use miraie::prelude::*;
+///
+/// let chain = message_chain![at(QQ(1))];
+/// let block: MessageBlock = at(QQ(1));
+/// ```
+#[macro_export]
+macro_rules! message_chain {
+    () => {
+        $crate::messages::MessageChain::new()
+    };
+    // 字面量先攒起来，遇到别的消息块或者结束时再合并
+    (@push $chain:ident [$($lit:literal)*] $next:literal $(, $($rest:tt)*)?) => {
+        $crate::message_chain!(@push $chain [$($lit)* $next] $($($rest)*)?)
+    };
+    (@push $chain:ident [$($lit:literal)*] $next:expr $(, $($rest:tt)*)?) => {
+        $crate::message_chain!(@flush $chain [$($lit)*]);
+        $chain.0.push($crate::messages::MessageBlock::from($next));
+        $crate::message_chain!(@push $chain [] $($($rest)*)?)
+    };
+    (@push $chain:ident [$($lit:literal)*]) => {
+        $crate::message_chain!(@flush $chain [$($lit)*])
+    };
+
+    (@flush $chain:ident []) => {};
+    (@flush $chain:ident [$($lit:literal)+]) => {
+        $chain.0.push($crate::messages::MessageBlock::text(concat!($($lit),+)))
+    };
+
+    ($($items:tt)+) => {{
+        #[allow(unused_imports)]
+        use $crate::messages::blocks::*;
+        let mut chain = $crate::messages::MessageChain::new();
+        $crate::message_chain!(@push chain [] $($items)+);
+        chain
+    }};
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        bot::QQ,
+        messages::{MessageBlock, MessageChain},
+    };
+
+    #[test]
+    fn test_message_chain_macro() {
+        assert_eq!(message_chain!["a"], MessageChain::new().text("a"));
+        assert_eq!(
+            message_chain!["a", 1, "b",],
+            MessageChain::new().text("a1b")
+        );
+        assert_eq!(
+            message_chain![at(QQ(1)), "a".to_string(), "b", at_all()],
+            MessageChain(vec![
+                MessageBlock::at(QQ(1)),
+                MessageBlock::text("a"),
+                MessageBlock::text("b"),
+                MessageBlock::AtAll,
+            ])
+        );
+    }
+}
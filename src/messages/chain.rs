@@ -1,4 +1,6 @@
+use super::MediaSource;
 use crate::bot::QQ;
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
 use chrono::{DateTime, Utc};
 use std::{
     env,
@@ -63,62 +65,48 @@ pub enum MessageBlock {
     Text { text: String },
 
     /// 图片消息
-    ///
-    /// 三个参数任选其一，出现多个参数时，按照imageId > url > path > base64的优先级
     Image {
-        /// 图片的imageId，群图片与好友图片格式不同。不为空时将忽略url属性
-        /// 群图片格式   "{01E9451B-70ED-EAE3-B37C-101F1EEBF5B5}.mirai"
-        /// 好友图片格式 "/f8f1ab55-bf8e-4236-b55e-955848d7069f"
-        #[serde(rename = "imageId")]
-        image_id: String,
-        /// 图片的URL，发送时可作网络图片的链接；接收时为腾讯图片服务器的链接，可用于图片下载
-        url: String,
-        // /// 图片的路径，发送本地图片，相对路径于 plugins/MiraiAPIHTTP/images
-        // path: Option<String>,
-        /// 图片的 Base64 编码
-        base64: Option<String>,
+        /// 图片的来源，出现多个时按照imageId > url > path > base64的优先级
+        #[serde(flatten, with = "super::media::image::source")]
+        source: MediaSource,
+        /// 接收时为腾讯图片服务器的链接，可用于图片下载；发送时忽略
+        #[serde(flatten, with = "super::media::image::url")]
+        url: Option<String>,
     },
 
     /// 闪照
     ///
-    /// 三个参数任选其一，出现多个参数时，按照imageId > url > path > base64的优先级
-    FlushImage {
-        /// 图片的imageId，群图片与好友图片格式不同。不为空时将忽略url属性
-        /// 群图片格式   "{01E9451B-70ED-EAE3-B37C-101F1EEBF5B5}.mirai"
-        /// 好友图片格式 "/f8f1ab55-bf8e-4236-b55e-955848d7069f"
-        #[serde(rename = "imageId")]
-        image_id: String,
-        /// 图片的URL，发送时可作网络图片的链接；接收时为腾讯图片服务器的链接，可用于图片下载
-        url: String,
-        // /// 图片的路径，发送本地图片，相对路径于 plugins/MiraiAPIHTTP/images
-        // path: Option<String>,
-        /// 图片的 Base64 编码
-        base64: Option<String>,
+    /// 旧版本曾错误地使用 `FlushImage` 作为类型标签，反序列化时仍然兼容。
+    #[serde(alias = "FlushImage")]
+    FlashImage {
+        /// 图片的来源，出现多个时按照imageId > url > path > base64的优先级
+        #[serde(flatten, with = "super::media::image::source")]
+        source: MediaSource,
+        /// 接收时为腾讯图片服务器的链接，可用于图片下载；发送时忽略
+        #[serde(flatten, with = "super::media::image::url")]
+        url: Option<String>,
     },
 
     /// 音频消息
-    ///
-    /// 三个参数任选其一，出现多个参数时，按照voiceId > url > path > base64的优先级
     Voice {
-        /// 语音的voiceId，不为空时将忽略url属性
-        #[serde(rename = "voiceId")]
-        voice_id: Option<String>,
-        /// 语音的URL，发送时可作网络语音的链接；接收时为腾讯语音服务器的链接，可用于语音下载
+        /// 语音的来源，出现多个时按照voiceId > url > path > base64的优先级
+        #[serde(flatten, with = "super::media::voice::source")]
+        source: MediaSource,
+        /// 接收时为腾讯语音服务器的链接，可用于语音下载；发送时忽略
+        #[serde(flatten, with = "super::media::voice::url")]
         url: Option<String>,
-        // /// 语音的路径，发送本地语音，相对路径于 plugins/MiraiAPIHTTP/voices
-        // path: Option<String>,
-        /// 语音的 Base64 编码
-        base64: Option<String>,
     },
 
     /// XML
     Xml { xml: String },
-    // /// 转发消息，暂时不支持
-    // #[serde(rename = "ForwardMessage")]
-    // ForwardMessage {
-    //     #[serde(rename = "nodeList")]
-    //     nodes: Vec<ForwardMessageNode>,
-    // }
+
+    /// 合并转发的聊天记录
+    Forward {
+        /// 聊天记录中的每一条消息
+        #[serde(rename = "nodeList")]
+        nodes: Vec<ForwardNode>,
+    },
+
     /// 文件消息
     File {
         /// 文件识别id
@@ -128,6 +116,133 @@ pub enum MessageBlock {
         /// 文件大小
         size: usize,
     },
+
+    /// 骰子
+    Dice {
+        /// 点数
+        value: u32,
+    },
+
+    /// 戳一戳
+    Poke {
+        /// 戳一戳的类型
+        name: PokeKind,
+    },
+
+    /// 商城表情
+    MarketFace {
+        /// 商城表情唯一标识
+        id: i32,
+        /// 表情显示名称
+        name: String,
+    },
+
+    /// 音乐分享
+    MusicShare {
+        /// 音乐应用类型
+        kind: MusicKind,
+        /// 消息卡片标题
+        title: String,
+        /// 消息卡片内容
+        summary: String,
+        /// 点击卡片跳转网页 URL
+        #[serde(rename = "jumpUrl")]
+        jump_url: String,
+        /// 消息卡片图片 URL
+        #[serde(rename = "pictureUrl")]
+        picture_url: String,
+        /// 音乐文件 URL
+        #[serde(rename = "musicUrl")]
+        music_url: String,
+        /// 在消息列表显示
+        brief: String,
+    },
+
+    /// 小程序等 JSON 卡片消息
+    App {
+        /// 内容，通常是一段 JSON
+        content: String,
+    },
+
+    /// mirai 码，发送时会由 mirai 解析成对应的消息
+    MiraiCode {
+        /// mirai 码，如 `[mirai:at:123]`
+        code: String,
+    },
+
+    /// 短视频
+    ShortVideo {
+        /// 视频的来源，出现多个时按照videoId > url > path > base64的优先级
+        #[serde(flatten, with = "super::media::video::source")]
+        source: MediaSource,
+        /// 接收时为视频的下载链接，即 mirai 的 `videoUrl`；发送时忽略
+        #[serde(flatten, with = "super::media::video::url")]
+        url: Option<String>,
+        /// 视频文件的 md5
+        #[serde(rename = "fileMd5", default)]
+        file_md5: String,
+        /// 视频文件大小
+        #[serde(rename = "fileSize", default)]
+        file_size: u64,
+        /// 视频文件格式，如 mp4
+        #[serde(rename = "fileFormat", default)]
+        file_format: String,
+        /// 视频文件名
+        #[serde(default)]
+        filename: String,
+    },
+}
+
+/// 合并转发中的一条消息
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+pub struct ForwardNode {
+    /// 发送者的QQ号
+    #[serde(rename = "senderId")]
+    pub sender_id: QQ,
+    /// 发送时间
+    #[serde(with = "chrono::serde::ts_seconds")]
+    pub time: DateTime<Utc>,
+    /// 显示的发送者名字
+    #[serde(rename = "senderName")]
+    pub sender_name: String,
+    /// 消息内容
+    #[serde(rename = "messageChain", default)]
+    pub message: MessageChain,
+    /// 引用已有消息的messageId，此时忽略消息内容
+    #[serde(rename = "messageId", skip_serializing_if = "Option::is_none", default)]
+    pub message_id: Option<i64>,
+}
+
+/// 戳一戳的类型
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq, Eq)]
+pub enum PokeKind {
+    /// 戳一戳
+    Poke,
+    /// 比心
+    ShowLove,
+    /// 点赞
+    Like,
+    /// 心碎
+    Heartbroken,
+    /// 666
+    SixSixSix,
+    /// 放大招
+    FangDaZhao,
+}
+
+/// 音乐分享的应用类型
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq, Eq)]
+pub enum MusicKind {
+    /// 网易云音乐
+    NeteaseCloudMusic,
+    /// QQ 音乐
+    QQMusic,
+    /// 咪咕音乐
+    MiguMusic,
+    /// 酷狗音乐
+    KugouMusic,
+    /// 酷我音乐
+    KuwoMusic,
 }
 
 impl fmt::Display for MessageBlock {
@@ -150,10 +265,24 @@ impl fmt::Display for MessageBlock {
             MessageBlock::Face { name, .. } => write!(f, "[{}]", name),
             MessageBlock::Text { text } => f.write_str(text),
             MessageBlock::Image { .. } => f.write_str("[图片]"),
-            MessageBlock::FlushImage { .. } => f.write_str("[闪照]"),
+            MessageBlock::FlashImage { .. } => f.write_str("[闪照]"),
             MessageBlock::Voice { .. } => f.write_str("[语音消息]"),
             MessageBlock::Xml { .. } => f.write_str("[XML消息]"),
             MessageBlock::File { .. } => f.write_str("[文件消息]"),
+            MessageBlock::Forward { .. } => f.write_str("[聊天记录]"),
+            MessageBlock::Dice { value } => write!(f, "[骰子:{}]", value),
+            MessageBlock::Poke { .. } => f.write_str("[戳一戳]"),
+            MessageBlock::MarketFace { name, .. } => {
+                if name.starts_with('[') {
+                    f.write_str(name)
+                } else {
+                    write!(f, "[{}]", name)
+                }
+            }
+            MessageBlock::MusicShare { title, .. } => write!(f, "[分享]{}", title),
+            MessageBlock::App { .. } => f.write_str("[小程序]"),
+            MessageBlock::MiraiCode { code } => f.write_str(code),
+            MessageBlock::ShortVideo { .. } => f.write_str("[视频]"),
         }
     }
 }
@@ -168,17 +297,36 @@ impl MessageBlock {
     pub fn at_all() -> Self {
         Self::AtAll
     }
+    /// 引用回复某条消息，发送时只需要原消息的 messageId
+    pub fn quote(id: i64) -> Self {
+        Self::Quote {
+            id,
+            group_id: QQ(0),
+            sender_id: QQ(0),
+            target_id: QQ(0),
+            origin: MessageChain::new(),
+        }
+    }
     pub fn text(text: impl Into<String>) -> Self {
         Self::Text { text: text.into() }
     }
 
-    pub fn image_url(url: impl Into<String>) -> Self {
-        Self::Image {
-            image_id: String::new(),
-            url: url.into(),
-            base64: None,
+    /// QQ 表情，`face_id` 见 mirai 的表情编号
+    pub fn face(face_id: i32) -> Self {
+        Self::Face {
+            face_id,
+            name: String::new(),
         }
     }
+
+    /// 图片，来源见 [`MediaSource`]
+    pub fn image(source: MediaSource) -> Self {
+        Self::Image { source, url: None }
+    }
+
+    pub fn image_url(url: impl Into<String>) -> Self {
+        Self::image(MediaSource::Url(url.into()))
+    }
     /// 图片的路径，发送本地图片，相对路径于 env:MIRAIE_RESOURCE_ROOT/images
     pub fn image_path(path: impl AsRef<str>) -> Self {
         Self::image_url(format!(
@@ -188,12 +336,43 @@ impl MessageBlock {
         ))
     }
 
+    /// 通过 imageId 发送图片，可以用来转发收到的图片
+    pub fn image_id(image_id: impl Into<String>) -> Self {
+        Self::image(MediaSource::Id(image_id.into()))
+    }
+
+    /// 直接发送图片的内容，会被编码成 base64
+    pub fn image_bytes(bytes: impl AsRef<[u8]>) -> Self {
+        Self::image(MediaSource::Base64(BASE64.encode(bytes)))
+    }
+
+    /// 闪照，来源见 [`MediaSource`]
+    pub fn flash_image(source: MediaSource) -> Self {
+        Self::FlashImage { source, url: None }
+    }
+
+    /// 来自 url 的闪照
+    pub fn flash_image_url(url: impl Into<String>) -> Self {
+        Self::flash_image(MediaSource::Url(url.into()))
+    }
+
+    /// 通过 imageId 发送闪照
+    pub fn flash_image_id(image_id: impl Into<String>) -> Self {
+        Self::flash_image(MediaSource::Id(image_id.into()))
+    }
+
+    /// 直接发送闪照的内容，会被编码成 base64
+    pub fn flash_image_bytes(bytes: impl AsRef<[u8]>) -> Self {
+        Self::flash_image(MediaSource::Base64(BASE64.encode(bytes)))
+    }
+
+    /// 语音，来源见 [`MediaSource`]
+    pub fn voice(source: MediaSource) -> Self {
+        Self::Voice { source, url: None }
+    }
+
     pub fn voice_url(url: impl Into<String>) -> Self {
-        Self::Voice {
-            voice_id: None,
-            url: Some(url.into()),
-            base64: None,
-        }
+        Self::voice(MediaSource::Url(url.into()))
     }
     /// 语音的路径，发送本地语音，相对路径于 env:MIRAIE_RESOURCE_ROOT/voices
     pub fn voice_path(path: impl AsRef<str>) -> Self {
@@ -203,6 +382,94 @@ impl MessageBlock {
             path.as_ref()
         ))
     }
+
+    /// 骰子，点数为 1 到 6
+    pub fn dice(value: u32) -> Self {
+        Self::Dice { value }
+    }
+
+    /// 戳一戳
+    pub fn poke(kind: PokeKind) -> Self {
+        Self::Poke { name: kind }
+    }
+
+    /// 商城表情
+    pub fn market_face(id: i32, name: impl Into<String>) -> Self {
+        Self::MarketFace {
+            id,
+            name: name.into(),
+        }
+    }
+
+    /// 音乐分享，`brief` 默认为 `[分享]{title}`
+    pub fn music_share(
+        kind: MusicKind,
+        title: impl Into<String>,
+        summary: impl Into<String>,
+        jump_url: impl Into<String>,
+        picture_url: impl Into<String>,
+        music_url: impl Into<String>,
+    ) -> Self {
+        let title = title.into();
+        Self::MusicShare {
+            kind,
+            brief: format!("[分享]{}", title),
+            title,
+            summary: summary.into(),
+            jump_url: jump_url.into(),
+            picture_url: picture_url.into(),
+            music_url: music_url.into(),
+        }
+    }
+
+    /// JSON 卡片，`content` 为卡片的 JSON
+    pub fn app(content: impl Into<String>) -> Self {
+        Self::App {
+            content: content.into(),
+        }
+    }
+
+    /// mirai 码，由 mirai 解析成消息
+    pub fn mirai_code(code: impl Into<String>) -> Self {
+        Self::MiraiCode { code: code.into() }
+    }
+
+    /// 通过 videoId 发送短视频
+    pub fn short_video(video_id: impl Into<String>) -> Self {
+        Self::ShortVideo {
+            source: MediaSource::Id(video_id.into()),
+            url: None,
+            file_md5: String::new(),
+            file_size: 0,
+            file_format: String::new(),
+            filename: String::new(),
+        }
+    }
+
+    /// 图片、闪照、语音、短视频的来源
+    pub fn media_source(&self) -> Option<&MediaSource> {
+        match self {
+            MessageBlock::Image { source, .. }
+            | MessageBlock::FlashImage { source, .. }
+            | MessageBlock::Voice { source, .. }
+            | MessageBlock::ShortVideo { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+
+    /// 图片、闪照、语音、短视频的下载链接，可能是接收到的链接，也可能是发送时指定的链接
+    pub fn media_url(&self) -> Option<&str> {
+        match self {
+            MessageBlock::Image { source, url }
+            | MessageBlock::FlashImage { source, url }
+            | MessageBlock::Voice { source, url }
+            | MessageBlock::ShortVideo { source, url, .. } => match source {
+                MediaSource::Url(url) => Some(url),
+                _ => url.as_deref(),
+            },
+            _ => None,
+        }
+    }
 }
 
 /// 一条接受或者发送的消息，可能由一个或几个 [`MessageBlock`] 构成。
@@ -285,6 +552,36 @@ impl MessageChain {
         self
     }
 
+    /// 在消息里增加一张图片，其来自 imageId
+    pub fn image_id(mut self, image_id: impl Into<String>) -> Self {
+        self.0.push(MessageBlock::image_id(image_id));
+        self
+    }
+
+    /// 在消息里增加一张图片，直接发送图片的内容
+    pub fn image_bytes(mut self, bytes: impl AsRef<[u8]>) -> Self {
+        self.0.push(MessageBlock::image_bytes(bytes));
+        self
+    }
+
+    /// 在消息里增加一张闪照，其来自 url
+    pub fn flash_image_url(mut self, url: impl Into<String>) -> Self {
+        self.0.push(MessageBlock::flash_image_url(url));
+        self
+    }
+
+    /// 在消息里增加一张闪照，其来自 imageId
+    pub fn flash_image_id(mut self, image_id: impl Into<String>) -> Self {
+        self.0.push(MessageBlock::flash_image_id(image_id));
+        self
+    }
+
+    /// 在消息里增加一张闪照，直接发送图片的内容
+    pub fn flash_image_bytes(mut self, bytes: impl AsRef<[u8]>) -> Self {
+        self.0.push(MessageBlock::flash_image_bytes(bytes));
+        self
+    }
+
     /// 在消息里增加一段语音
     pub fn voice_url(mut self, url: impl Into<String>) -> Self {
         self.0.push(MessageBlock::voice_url(url));
@@ -299,6 +596,63 @@ impl MessageChain {
         self
     }
 
+    /// 在消息里增加一个骰子
+    pub fn dice(mut self, value: u32) -> Self {
+        self.0.push(MessageBlock::dice(value));
+        self
+    }
+
+    /// 在消息里增加一个戳一戳
+    pub fn poke(mut self, kind: PokeKind) -> Self {
+        self.0.push(MessageBlock::poke(kind));
+        self
+    }
+
+    /// 在消息里增加一个商城表情
+    pub fn market_face(mut self, id: i32, name: impl Into<String>) -> Self {
+        self.0.push(MessageBlock::market_face(id, name));
+        self
+    }
+
+    /// 在消息里增加一个音乐分享，参数见 [`MessageBlock::music_share`]
+    pub fn music_share(
+        mut self,
+        kind: MusicKind,
+        title: impl Into<String>,
+        summary: impl Into<String>,
+        jump_url: impl Into<String>,
+        picture_url: impl Into<String>,
+        music_url: impl Into<String>,
+    ) -> Self {
+        self.0.push(MessageBlock::music_share(
+            kind,
+            title,
+            summary,
+            jump_url,
+            picture_url,
+            music_url,
+        ));
+        self
+    }
+
+    /// 在消息里增加一个 JSON 卡片
+    pub fn app(mut self, content: impl Into<String>) -> Self {
+        self.0.push(MessageBlock::app(content));
+        self
+    }
+
+    /// 在消息里增加一段 mirai 码
+    pub fn mirai_code(mut self, code: impl Into<String>) -> Self {
+        self.0.push(MessageBlock::mirai_code(code));
+        self
+    }
+
+    /// 在消息里增加一个短视频
+    pub fn short_video(mut self, video_id: impl Into<String>) -> Self {
+        self.0.push(MessageBlock::short_video(video_id));
+        self
+    }
+
     /// 获取消息的 message id，可以用于稍后回复
     pub fn message_id(&self) -> Option<i64> {
         if self.0.is_empty() {
@@ -323,6 +677,18 @@ impl MessageChain {
     }
 }
 
+impl From<String> for MessageBlock {
+    fn from(s: String) -> Self {
+        MessageBlock::text(s)
+    }
+}
+
+impl From<&str> for MessageBlock {
+    fn from(s: &str) -> Self {
+        MessageBlock::text(s)
+    }
+}
+
 impl From<MessageBlock> for MessageChain {
     fn from(block: MessageBlock) -> Self {
         Self(vec![block])
@@ -343,8 +709,6 @@ impl From<&str> for MessageChain {
 
 #[cfg(test)]
 mod tests {
-    use chrono::NaiveDateTime;
-
     use super::*;
     #[test]
     fn test_message_block_source() {
@@ -357,7 +721,7 @@ mod tests {
             serde_json::from_str::<MessageBlock>(s).unwrap(),
             MessageBlock::Source {
                 id: 123,
-                time: DateTime::from_utc(NaiveDateTime::from_timestamp(123, 0), Utc)
+                time: DateTime::from_timestamp(123, 0).unwrap()
             }
         );
     }
@@ -377,4 +741,357 @@ mod tests {
             }
         );
     }
+
+    #[test]
+    fn test_message_block_forward() {
+        let s = r#"{
+            "type": "Forward",
+            "nodeList": [
+                {
+                    "senderId": 123,
+                    "time": 0,
+                    "senderName": "某人",
+                    "messageChain": [{ "type": "Plain", "text": "hello" }]
+                },
+                {
+                    "senderId": 456,
+                    "time": 1,
+                    "senderName": "",
+                    "messageId": 789
+                }
+            ]
+        }"#;
+        assert_eq!(
+            serde_json::from_str::<MessageBlock>(s).unwrap(),
+            MessageBlock::Forward {
+                nodes: vec![
+                    ForwardNode {
+                        sender_id: QQ(123),
+                        time: DateTime::from_timestamp(0, 0).unwrap(),
+                        sender_name: "某人".to_string(),
+                        message: MessageChain::new().text("hello"),
+                        message_id: None,
+                    },
+                    ForwardNode {
+                        sender_id: QQ(456),
+                        time: DateTime::from_timestamp(1, 0).unwrap(),
+                        sender_name: String::new(),
+                        message: MessageChain::new(),
+                        message_id: Some(789),
+                    },
+                ]
+            }
+        );
+    }
+
+    #[test]
+    fn test_message_block_dice() {
+        let s = r#"{
+            "type": "Dice",
+            "value": 1
+        }"#;
+        assert_eq!(
+            serde_json::from_str::<MessageBlock>(s).unwrap(),
+            MessageBlock::Dice { value: 1 }
+        );
+    }
+
+    #[test]
+    fn test_message_block_poke() {
+        let s = r#"{
+            "type": "Poke",
+            "name": "SixSixSix"
+        }"#;
+        assert_eq!(
+            serde_json::from_str::<MessageBlock>(s).unwrap(),
+            MessageBlock::Poke {
+                name: PokeKind::SixSixSix
+            }
+        );
+    }
+
+    #[test]
+    fn test_message_block_market_face() {
+        let s = r#"{
+            "type": "MarketFace",
+            "id": 123,
+            "name": "商城表情"
+        }"#;
+        assert_eq!(
+            serde_json::from_str::<MessageBlock>(s).unwrap(),
+            MessageBlock::MarketFace {
+                id: 123,
+                name: "商城表情".to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn test_message_block_music_share() {
+        let s = r#"{
+            "type": "MusicShare",
+            "kind": "NeteaseCloudMusic",
+            "title": "相见恨晚",
+            "summary": "彭佳慧",
+            "jumpUrl": "https://y.music.163.com/m/song/280761/",
+            "pictureUrl": "http://p4.music.126.net/GpsgjHB_9XgtrBVXt8XX4A==/93458488360396.jpg",
+            "musicUrl": "http://music.163.com/song/media/outer/url?id=280761&userid=52707933",
+            "brief": "[分享]相见恨晚"
+        }"#;
+        let block = serde_json::from_str::<MessageBlock>(s).unwrap();
+        assert_eq!(
+            block,
+            MessageBlock::music_share(
+                MusicKind::NeteaseCloudMusic,
+                "相见恨晚",
+                "彭佳慧",
+                "https://y.music.163.com/m/song/280761/",
+                "http://p4.music.126.net/GpsgjHB_9XgtrBVXt8XX4A==/93458488360396.jpg",
+                "http://music.163.com/song/media/outer/url?id=280761&userid=52707933",
+            )
+        );
+        assert_eq!(block.to_string(), "[分享]相见恨晚");
+    }
+
+    #[test]
+    fn test_message_block_app() {
+        let s = r#"{
+            "type": "App",
+            "content": "{\"app\":\"com.tencent.miniapp\"}"
+        }"#;
+        assert_eq!(
+            serde_json::from_str::<MessageBlock>(s).unwrap(),
+            MessageBlock::app(r#"{"app":"com.tencent.miniapp"}"#)
+        );
+    }
+
+    #[test]
+    fn test_message_block_mirai_code() {
+        let s = r#"{
+            "type": "MiraiCode",
+            "code": "hello[mirai:at:1234567]"
+        }"#;
+        assert_eq!(
+            serde_json::from_str::<MessageBlock>(s).unwrap(),
+            MessageBlock::mirai_code("hello[mirai:at:1234567]")
+        );
+    }
+
+    #[test]
+    fn test_message_block_short_video() {
+        let s = r#"{
+            "type": "ShortVideo",
+            "videoId": "video-id",
+            "fileMd5": "d41d8cd98f00b204e9800998ecf8427e",
+            "fileSize": 1024,
+            "fileFormat": "mp4",
+            "filename": "video.mp4"
+        }"#;
+        assert_eq!(
+            serde_json::from_str::<MessageBlock>(s).unwrap(),
+            MessageBlock::ShortVideo {
+                source: MediaSource::Id("video-id".to_string()),
+                url: None,
+                file_md5: "d41d8cd98f00b204e9800998ecf8427e".to_string(),
+                file_size: 1024,
+                file_format: "mp4".to_string(),
+                filename: "video.mp4".to_string(),
+            }
+        );
+        // 发送时只需要 videoId
+        let value = serde_json::to_value(MessageBlock::short_video("video-id")).unwrap();
+        assert_eq!(value["type"], "ShortVideo");
+        assert_eq!(value["videoId"], "video-id");
+    }
+
+    macro_rules! fixture {
+        ($name:literal) => {
+            include_str!(concat!("../../tests/fixtures/", $name))
+        };
+    }
+
+    #[test]
+    fn test_message_block_image_fixtures() {
+        let block: MessageBlock = serde_json::from_str(fixture!("image_group.json")).unwrap();
+        assert_eq!(
+            block,
+            MessageBlock::Image {
+                source: MediaSource::Id("{01E9451B-70ED-EAE3-B37C-101F1EEBF5B5}.mirai".to_string()),
+                url: Some("http://gchat.qpic.cn/gchatpic_new/0/0-0-01E9451B70EDEAE3B37C101F1EEBF5B5/0?term=2".to_string()),
+            }
+        );
+
+        let block: MessageBlock = serde_json::from_str(fixture!("image_friend.json")).unwrap();
+        assert_eq!(
+            block,
+            MessageBlock::Image {
+                source: MediaSource::Id("/f8f1ab55-bf8e-4236-b55e-955848d7069f".to_string()),
+                url: Some("http://c2cpicdw.qpic.cn/offpic_new/0//f8f1ab55-bf8e-4236-b55e-955848d7069f/0?term=2".to_string()),
+            }
+        );
+    }
+
+    #[test]
+    fn test_message_block_flash_image_fixtures() {
+        let s = fixture!("flash_image.json");
+        let block: MessageBlock = serde_json::from_str(s).unwrap();
+        let expected = MessageBlock::FlashImage {
+            source: MediaSource::Id("{A7CBB529-43A2-127C-E426-59D29BAA8515}.mirai".to_string()),
+            url: Some(
+                "http://gchat.qpic.cn/gchatpic_new/0/0-0-A7CBB52943A2127CE42659D29BAA8515/0?term=2"
+                    .to_string(),
+            ),
+        };
+        assert_eq!(block, expected);
+        assert_eq!(block.to_string(), "[闪照]");
+
+        // 序列化时使用正确的类型标签，只发送 imageId
+        let value = serde_json::to_value(&block).unwrap();
+        assert_eq!(
+            value,
+            serde_json::json!({
+                "type": "FlashImage",
+                "imageId": "{A7CBB529-43A2-127C-E426-59D29BAA8515}.mirai",
+            })
+        );
+
+        // 兼容旧的 `FlushImage` 标签
+        let legacy = s.replace("\"FlashImage\"", "\"FlushImage\"");
+        assert_eq!(
+            serde_json::from_str::<MessageBlock>(&legacy).unwrap(),
+            expected
+        );
+    }
+
+    #[test]
+    fn test_message_block_short_video_fixture() {
+        let s = fixture!("short_video.json");
+        let url = "https://multimedia.nt.qq.com.cn/download?appid=1415&fileid=CgoxMjM0NTY3ODkw&rkey=CAQSKAB6JWENi5LM";
+        let expected = MessageBlock::ShortVideo {
+            source: MediaSource::Id("c8a6f2b1e4d3470f9b5e2a1d6c7f8e90".to_string()),
+            url: Some(url.to_string()),
+            file_md5: "3a5f2c9e8b1d4f6a7c0e2b4d6f8a1c3e".to_string(),
+            file_size: 2097152,
+            file_format: "mp4".to_string(),
+            filename: "c8a6f2b1e4d3470f9b5e2a1d6c7f8e90.mp4".to_string(),
+        };
+        let block: MessageBlock = serde_json::from_str(s).unwrap();
+        assert_eq!(block, expected);
+        assert_eq!(block.media_url(), Some(url));
+
+        // 兼容用 `url` 表示下载链接
+        let legacy = s.replace("\"videoUrl\"", "\"url\"");
+        assert_eq!(
+            serde_json::from_str::<MessageBlock>(&legacy).unwrap(),
+            expected
+        );
+    }
+
+    #[test]
+    fn test_message_with_image_fixtures() {
+        let msg: crate::messages::Message =
+            serde_json::from_str(fixture!("group_message_images.json")).unwrap();
+        let msg = match msg {
+            crate::messages::Message::Group(msg) => msg,
+            _ => panic!("should be group message"),
+        };
+        assert_eq!(msg.message.message_id(), Some(31224));
+        assert!(matches!(msg.message.0[2], MessageBlock::Image { .. }));
+        assert!(matches!(msg.message.0[3], MessageBlock::FlashImage { .. }));
+        assert_eq!(msg.message.to_string(), "看图 [图片] [闪照]");
+    }
+
+    #[test]
+    fn test_flash_image_builders() {
+        let chain = MessageChain::new()
+            .flash_image_url("http://a.com/a.jpg")
+            .flash_image_id("{A7CBB529-43A2-127C-E426-59D29BAA8515}.mirai")
+            .flash_image_bytes(b"hello");
+        let value = serde_json::to_value(&chain).unwrap();
+        for block in value.as_array().unwrap() {
+            assert_eq!(block["type"], "FlashImage");
+        }
+        assert_eq!(value[0]["url"], "http://a.com/a.jpg");
+        assert_eq!(
+            value[1]["imageId"],
+            "{A7CBB529-43A2-127C-E426-59D29BAA8515}.mirai"
+        );
+        assert_eq!(value[2]["base64"], "aGVsbG8=");
+    }
+
+    #[test]
+    fn test_media_source_serialize() {
+        let cases = [
+            (
+                MessageBlock::image_id("{01E9451B-70ED-EAE3-B37C-101F1EEBF5B5}.mirai"),
+                serde_json::json!({ "type": "Image", "imageId": "{01E9451B-70ED-EAE3-B37C-101F1EEBF5B5}.mirai" }),
+            ),
+            (
+                MessageBlock::image_url("http://a.com/a.jpg"),
+                serde_json::json!({ "type": "Image", "url": "http://a.com/a.jpg" }),
+            ),
+            (
+                MessageBlock::image(MediaSource::Path("a.jpg".to_string())),
+                serde_json::json!({ "type": "Image", "path": "a.jpg" }),
+            ),
+            (
+                MessageBlock::image_bytes("hello"),
+                serde_json::json!({ "type": "Image", "base64": "aGVsbG8=" }),
+            ),
+            (
+                MessageBlock::voice(MediaSource::Id("voice-id".to_string())),
+                serde_json::json!({ "type": "Voice", "voiceId": "voice-id" }),
+            ),
+            (
+                MessageBlock::short_video("video-id"),
+                serde_json::json!({
+                    "type": "ShortVideo",
+                    "videoId": "video-id",
+                    "fileMd5": "",
+                    "fileSize": 0,
+                    "fileFormat": "",
+                    "filename": "",
+                }),
+            ),
+        ];
+        for (block, expected) in cases {
+            let value = serde_json::to_value(&block).unwrap();
+            assert_eq!(value, expected);
+            assert_eq!(
+                serde_json::from_value::<MessageBlock>(value).unwrap(),
+                block
+            );
+        }
+    }
+
+    #[test]
+    fn test_media_source_deserialize() {
+        // mirai 发出的各种形状
+        let cases = [
+            (
+                r#"{"type": "Voice", "voiceId": "id", "url": "http://a.com/a.amr", "path": null, "base64": null, "length": 3}"#,
+                MessageBlock::Voice {
+                    source: MediaSource::Id("id".to_string()),
+                    url: Some("http://a.com/a.amr".to_string()),
+                },
+            ),
+            (
+                r#"{"type": "Voice", "voiceId": null, "url": "http://a.com/a.amr"}"#,
+                MessageBlock::voice_url("http://a.com/a.amr"),
+            ),
+            // 旧版本发送的空 imageId
+            (
+                r#"{"type": "Image", "imageId": "", "url": "http://a.com/a.jpg", "base64": null}"#,
+                MessageBlock::image_url("http://a.com/a.jpg"),
+            ),
+            (
+                r#"{"type": "Image", "path": "a.jpg"}"#,
+                MessageBlock::image(MediaSource::Path("a.jpg".to_string())),
+            ),
+        ];
+        for (s, expected) in cases {
+            assert_eq!(serde_json::from_str::<MessageBlock>(s).unwrap(), expected);
+        }
+        assert!(serde_json::from_str::<MessageBlock>(r#"{"type": "Image", "url": null}"#).is_err());
+    }
 }
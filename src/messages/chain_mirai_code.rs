@@ -0,0 +1,578 @@
+//! mirai 码的序列化与解析，见
+//! <https://github.com/mamoe/mirai/blob/dev/docs/Messages.md#mirai-码>
+use super::{ForwardNode, MediaSource, MessageBlock, MessageChain, MusicKind, PokeKind};
+use crate::{bot::QQ, Error, Result};
+use chrono::DateTime;
+use std::{fmt::Write, str::FromStr};
+
+impl MessageChain {
+    /// 把消息序列化为 mirai 码，如 `hello[mirai:at:123]`。
+    ///
+    /// 跟 [`Display`](std::fmt::Display) 不同，转换是无损的，可以通过 [`MessageChain::from_mirai_code`] 还原。
+    /// mirai 本身支持的消息使用 mirai 的格式，其余的（如 `source`、`quote`、`voice`）使用 miraie 自己的格式。
+    ///
+    /// 注意 [`MessageBlock::MiraiCode`] 会被原样输出，再次解析时会变成对应的消息块。
+    ///
+    /// # Example
+    /// ```
+    /// # use miraie::prelude::*;
+    /// let chain = MessageChain::new().text("[你好]").at(QQ(123));
+    /// assert_eq!(chain.to_mirai_code(), r"\[你好\][mirai:at:123]");
+    /// ```
+    pub fn to_mirai_code(&self) -> String {
+        let mut s = String::new();
+        for block in self.0.iter() {
+            block.write_mirai_code(&mut s);
+        }
+        s
+    }
+
+    /// 从 mirai 码解析出 [`MessageChain`]，格式错误时返回 [`Error::Format`]。
+    ///
+    /// # Example
+    /// ```
+    /// # use miraie::prelude::*;
+    /// let chain = MessageChain::from_mirai_code("[mirai:at:123] 你好[mirai:face:14]").unwrap();
+    /// assert_eq!(chain.0[0], MessageBlock::at(QQ(123)));
+    /// assert_eq!(chain.0[1], MessageBlock::text(" 你好"));
+    /// ```
+    pub fn from_mirai_code(code: &str) -> Result<Self> {
+        let mut blocks = vec![];
+        let mut text = String::new();
+        let mut rest = code;
+        while !rest.is_empty() {
+            if let Some(after) = rest.strip_prefix("[mirai:") {
+                let end = find_unescaped(after, ']')
+                    .ok_or_else(|| Error::format(format!("mirai 码没有结束：{}", rest)))?;
+                if !text.is_empty() {
+                    blocks.push(MessageBlock::text(std::mem::take(&mut text)));
+                }
+                blocks.push(parse_code(&after[..end])?);
+                rest = &after[end + 1..];
+                continue;
+            }
+            let mut chars = rest.chars();
+            match chars.next() {
+                Some('\\') => match chars.next() {
+                    Some(c) => text.push(unescape_char(c)),
+                    None => text.push('\\'),
+                },
+                Some(c) => text.push(c),
+                None => unreachable!(),
+            }
+            rest = chars.as_str();
+        }
+        if !text.is_empty() {
+            blocks.push(MessageBlock::text(text));
+        }
+        Ok(Self(blocks))
+    }
+}
+
+impl MessageBlock {
+    fn write_mirai_code(&self, s: &mut String) {
+        match self {
+            MessageBlock::Source { id, time } => write_code(
+                s,
+                "source",
+                &[&id.to_string(), &time.timestamp().to_string()],
+            ),
+            MessageBlock::Quote {
+                id,
+                group_id,
+                sender_id,
+                target_id,
+                origin,
+            } => write_code(
+                s,
+                "quote",
+                &[
+                    &id.to_string(),
+                    &group_id.to_string(),
+                    &sender_id.to_string(),
+                    &target_id.to_string(),
+                    &origin.to_mirai_code(),
+                ],
+            ),
+            MessageBlock::At { target, display } => {
+                write_code(s, "at", &[&target.to_string(), display])
+            }
+            MessageBlock::AtAll => write_code(s, "atall", &[]),
+            MessageBlock::Face { face_id, name } => {
+                write_code(s, "face", &[&face_id.to_string(), name])
+            }
+            MessageBlock::Text { text } => s.push_str(&escape(text)),
+            MessageBlock::Image { source, url } => write_media(s, "image", source, url),
+            MessageBlock::FlashImage { source, url } => write_media(s, "flash", source, url),
+            MessageBlock::Voice { source, url } => write_media(s, "voice", source, url),
+            MessageBlock::Xml { xml } => write_code(s, "service", &["60", xml]),
+            MessageBlock::File { id, name, size } => {
+                write_code(s, "file", &[id, "0", name, &size.to_string()])
+            }
+            MessageBlock::Forward { nodes } => {
+                let args = nodes
+                    .iter()
+                    .flat_map(|node| {
+                        [
+                            node.sender_id.to_string(),
+                            node.time.timestamp().to_string(),
+                            node.sender_name.clone(),
+                            node.message_id.map(|id| id.to_string()).unwrap_or_default(),
+                            node.message.to_mirai_code(),
+                        ]
+                    })
+                    .collect::<Vec<_>>();
+                let args = args.iter().map(String::as_str).collect::<Vec<_>>();
+                write_code(s, "forward", &args)
+            }
+            MessageBlock::Dice { value } => write_code(s, "dice", &[&value.to_string()]),
+            MessageBlock::Poke { name } => {
+                let (poke_name, poke_type) = poke_to_code(*name);
+                write_code(s, "poke", &[poke_name, &poke_type.to_string(), "-1"])
+            }
+            MessageBlock::MarketFace { id, name } => {
+                write_code(s, "marketface", &[&id.to_string(), name])
+            }
+            MessageBlock::MusicShare {
+                kind,
+                title,
+                summary,
+                jump_url,
+                picture_url,
+                music_url,
+                brief,
+            } => write_code(
+                s,
+                "musicshare",
+                &[
+                    music_kind_name(*kind),
+                    title,
+                    summary,
+                    jump_url,
+                    picture_url,
+                    music_url,
+                    brief,
+                ],
+            ),
+            MessageBlock::App { content } => write_code(s, "app", &[content]),
+            MessageBlock::MiraiCode { code } => s.push_str(code),
+            MessageBlock::ShortVideo {
+                source,
+                url,
+                file_md5,
+                file_size,
+                file_format,
+                filename,
+            } => {
+                let mut args = media_args(source, url);
+                args.extend([
+                    file_md5.clone(),
+                    file_size.to_string(),
+                    file_format.clone(),
+                    filename.clone(),
+                ]);
+                let args = args.iter().map(String::as_str).collect::<Vec<_>>();
+                write_code(s, "shortvideo", &args)
+            }
+        }
+    }
+}
+
+/// 媒体的参数为 `id,url,path,base64`，其中 url 在来源为 id 时是接收到的下载链接
+fn media_args(source: &MediaSource, url: &Option<String>) -> Vec<String> {
+    let mut args = vec![String::new(); 4];
+    let i = match source {
+        MediaSource::Id(_) => {
+            args[1] = url.clone().unwrap_or_default();
+            0
+        }
+        MediaSource::Url(_) => 1,
+        MediaSource::Path(_) => 2,
+        MediaSource::Base64(_) => 3,
+    };
+    args[i] = source.as_str().to_string();
+    args
+}
+
+fn write_media(s: &mut String, kind: &str, source: &MediaSource, url: &Option<String>) {
+    let args = media_args(source, url);
+    let args = args.iter().map(String::as_str).collect::<Vec<_>>();
+    write_code(s, kind, &args)
+}
+
+/// 从 `id,url,path,base64` 解析出媒体的来源和下载链接
+fn parse_media(kind: &str, args: &[String]) -> Result<(MediaSource, Option<String>)> {
+    let arg = |i: usize| args.get(i).filter(|s| !s.is_empty()).cloned();
+    let source = arg(0)
+        .map(MediaSource::Id)
+        .or_else(|| arg(1).map(MediaSource::Url))
+        .or_else(|| arg(2).map(MediaSource::Path))
+        .or_else(|| arg(3).map(MediaSource::Base64))
+        .ok_or_else(|| Error::format(format!("mirai 码 `{}` 缺少来源", kind)))?;
+    let url = match source {
+        MediaSource::Id(_) => arg(1),
+        _ => None,
+    };
+    Ok((source, url))
+}
+
+/// 写入 `[mirai:kind:arg1,arg2]`，末尾的空参数会被省略
+fn write_code(s: &mut String, kind: &str, args: &[&str]) {
+    let len = args
+        .iter()
+        .rposition(|arg| !arg.is_empty())
+        .map_or(0, |i| i + 1);
+    write!(s, "[mirai:{}", kind).unwrap();
+    for (i, arg) in args[..len].iter().enumerate() {
+        s.push(if i == 0 { ':' } else { ',' });
+        s.push_str(&escape(arg));
+    }
+    s.push(']');
+}
+
+fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '\\' | '[' | ']' | ':' | ',' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            '\n' => escaped.push_str(r"\n"),
+            '\r' => escaped.push_str(r"\r"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn unescape_char(c: char) -> char {
+    match c {
+        'n' => '\n',
+        'r' => '\r',
+        c => c,
+    }
+}
+
+/// 找到第一个没有被转义的 `target`
+fn find_unescaped(s: &str, target: char) -> Option<usize> {
+    let mut escaped = false;
+    for (i, c) in s.char_indices() {
+        if escaped {
+            escaped = false;
+        } else if c == '\\' {
+            escaped = true;
+        } else if c == target {
+            return Some(i);
+        }
+    }
+    None
+}
+
+/// 按照没有被转义的 `,` 切分参数，并去除转义
+fn split_args(s: &str) -> Vec<String> {
+    let mut args = vec![String::new()];
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some(c) => args.last_mut().unwrap().push(unescape_char(c)),
+                None => args.last_mut().unwrap().push('\\'),
+            },
+            ',' => args.push(String::new()),
+            c => args.last_mut().unwrap().push(c),
+        }
+    }
+    args
+}
+
+/// 解析 `[mirai:` 与 `]` 之间的内容
+fn parse_code(code: &str) -> Result<MessageBlock> {
+    let (kind, args) = match find_unescaped(code, ':') {
+        Some(i) => (&code[..i], split_args(&code[i + 1..])),
+        None => (code, vec![]),
+    };
+    let arg = |i: usize| args.get(i).map(String::as_str).unwrap_or_default();
+
+    let block = match kind {
+        "source" => MessageBlock::Source {
+            id: parse_num(kind, arg(0))?,
+            time: DateTime::from_timestamp(parse_num(kind, arg(1))?, 0)
+                .ok_or_else(|| Error::format("mirai 码 `source` 的时间无效"))?,
+        },
+        "quote" => MessageBlock::Quote {
+            id: parse_num(kind, arg(0))?,
+            group_id: parse_num(kind, arg(1)).map(QQ)?,
+            sender_id: parse_num(kind, arg(2)).map(QQ)?,
+            target_id: parse_num(kind, arg(3)).map(QQ)?,
+            origin: MessageChain::from_mirai_code(arg(4))?,
+        },
+        "at" => MessageBlock::At {
+            target: parse_num(kind, arg(0)).map(QQ)?,
+            display: arg(1).to_string(),
+        },
+        "atall" => MessageBlock::AtAll,
+        "face" => MessageBlock::Face {
+            face_id: parse_num(kind, arg(0))?,
+            name: arg(1).to_string(),
+        },
+        "image" => {
+            let (source, url) = parse_media(kind, &args)?;
+            MessageBlock::Image { source, url }
+        }
+        "flash" => {
+            let (source, url) = parse_media(kind, &args)?;
+            MessageBlock::FlashImage { source, url }
+        }
+        "voice" => {
+            let (source, url) = parse_media(kind, &args)?;
+            MessageBlock::Voice { source, url }
+        }
+        "service" => MessageBlock::Xml {
+            xml: arg(1).to_string(),
+        },
+        // mirai 的格式为 id,internalId,name,size
+        "file" if args.len() == 4 => MessageBlock::File {
+            id: arg(0).to_string(),
+            name: arg(2).to_string(),
+            size: parse_num(kind, arg(3))?,
+        },
+        "file" => MessageBlock::File {
+            id: arg(0).to_string(),
+            name: arg(1).to_string(),
+            size: parse_num(kind, arg(2))?,
+        },
+        "forward" => MessageBlock::Forward {
+            nodes: (0..args.len().div_ceil(5))
+                .map(|i| {
+                    let arg = |j: usize| arg(i * 5 + j);
+                    Ok(ForwardNode {
+                        sender_id: parse_num(kind, arg(0)).map(QQ)?,
+                        time: DateTime::from_timestamp(parse_num(kind, arg(1))?, 0)
+                            .ok_or_else(|| Error::format("mirai 码 `forward` 的时间无效"))?,
+                        sender_name: arg(2).to_string(),
+                        message_id: match arg(3) {
+                            "" => None,
+                            id => Some(parse_num(kind, id)?),
+                        },
+                        message: MessageChain::from_mirai_code(arg(4))?,
+                    })
+                })
+                .collect::<Result<_>>()?,
+        },
+        "dice" => MessageBlock::Dice {
+            value: parse_num(kind, arg(0))?,
+        },
+        "poke" => MessageBlock::Poke {
+            name: poke_from_code(arg(0), arg(1))
+                .ok_or_else(|| Error::format(format!("未知的戳一戳类型：{}", arg(0))))?,
+        },
+        "marketface" => MessageBlock::MarketFace {
+            id: parse_num(kind, arg(0))?,
+            name: arg(1).to_string(),
+        },
+        "musicshare" => MessageBlock::MusicShare {
+            kind: music_kind_from_name(arg(0))
+                .ok_or_else(|| Error::format(format!("未知的音乐分享类型：{}", arg(0))))?,
+            title: arg(1).to_string(),
+            summary: arg(2).to_string(),
+            jump_url: arg(3).to_string(),
+            picture_url: arg(4).to_string(),
+            music_url: arg(5).to_string(),
+            brief: arg(6).to_string(),
+        },
+        "app" => MessageBlock::App {
+            content: arg(0).to_string(),
+        },
+        "shortvideo" => {
+            let (source, url) = parse_media(kind, &args)?;
+            MessageBlock::ShortVideo {
+                source,
+                url,
+                file_md5: arg(4).to_string(),
+                file_size: if arg(5).is_empty() {
+                    0
+                } else {
+                    parse_num(kind, arg(5))?
+                },
+                file_format: arg(6).to_string(),
+                filename: arg(7).to_string(),
+            }
+        }
+        _ => return Err(Error::format(format!("未知的 mirai 码类型：{}", kind))),
+    };
+    Ok(block)
+}
+
+fn parse_num<T: FromStr>(kind: &str, s: &str) -> Result<T> {
+    s.parse()
+        .map_err(|_| Error::format(format!("mirai 码 `{}` 的参数 `{}` 不是数字", kind, s)))
+}
+
+const POKES: [(PokeKind, &str, i32); 6] = [
+    (PokeKind::Poke, "戳一戳", 1),
+    (PokeKind::ShowLove, "比心", 2),
+    (PokeKind::Like, "点赞", 3),
+    (PokeKind::Heartbroken, "心碎", 4),
+    (PokeKind::SixSixSix, "666", 5),
+    (PokeKind::FangDaZhao, "放大招", 6),
+];
+
+fn poke_to_code(kind: PokeKind) -> (&'static str, i32) {
+    let (_, name, poke_type) = POKES.iter().find(|(k, _, _)| *k == kind).unwrap();
+    (name, *poke_type)
+}
+
+fn poke_from_code(name: &str, poke_type: &str) -> Option<PokeKind> {
+    POKES
+        .iter()
+        .find(|(_, n, t)| *n == name || t.to_string() == poke_type)
+        .map(|(kind, _, _)| *kind)
+}
+
+const MUSIC_KINDS: [(MusicKind, &str); 5] = [
+    (MusicKind::NeteaseCloudMusic, "NeteaseCloudMusic"),
+    (MusicKind::QQMusic, "QQMusic"),
+    (MusicKind::MiguMusic, "MiguMusic"),
+    (MusicKind::KugouMusic, "KugouMusic"),
+    (MusicKind::KuwoMusic, "KuwoMusic"),
+];
+
+fn music_kind_name(kind: MusicKind) -> &'static str {
+    MUSIC_KINDS.iter().find(|(k, _)| *k == kind).unwrap().1
+}
+
+fn music_kind_from_name(name: &str) -> Option<MusicKind> {
+    MUSIC_KINDS
+        .iter()
+        .find(|(_, n)| *n == name)
+        .map(|(kind, _)| *kind)
+}
+
+#[test]
+fn test_mirai_code_escape() {
+    let chain = MessageChain::new().text("a[b]c:d,e\\f\ng");
+    let code = chain.to_mirai_code();
+    assert_eq!(code, r"a\[b\]c\:d\,e\\f\ng");
+    assert_eq!(MessageChain::from_mirai_code(&code).unwrap(), chain);
+
+    // 不是 mirai 码的方括号会被当做文字
+    assert_eq!(
+        MessageChain::from_mirai_code("[图片]").unwrap(),
+        MessageChain::new().text("[图片]")
+    );
+}
+
+#[test]
+fn test_parse_mirai_code() {
+    let chain =
+        MessageChain::from_mirai_code("hi [mirai:at:123][mirai:atall][mirai:face:14] bye").unwrap();
+    assert_eq!(
+        chain,
+        MessageChain(vec![
+            MessageBlock::text("hi "),
+            MessageBlock::at(QQ(123)),
+            MessageBlock::AtAll,
+            MessageBlock::Face {
+                face_id: 14,
+                name: String::new()
+            },
+            MessageBlock::text(" bye"),
+        ])
+    );
+
+    let chain = MessageChain::from_mirai_code(
+        "[mirai:image:{01E9451B-70ED-EAE3-B37C-101F1EEBF5B5}.mirai][mirai:poke:戳一戳,1,-1]",
+    )
+    .unwrap();
+    assert_eq!(
+        chain,
+        MessageChain(vec![
+            MessageBlock::image_id("{01E9451B-70ED-EAE3-B37C-101F1EEBF5B5}.mirai"),
+            MessageBlock::poke(PokeKind::Poke),
+        ])
+    );
+
+    assert!(MessageChain::from_mirai_code("[mirai:at:123").is_err());
+    assert!(MessageChain::from_mirai_code("[mirai:at:abc]").is_err());
+    assert!(MessageChain::from_mirai_code("[mirai:unknown:1]").is_err());
+}
+
+#[test]
+fn test_mirai_code_round_trip() {
+    let origin = MessageChain::new().text("原消息, 带逗号").at(QQ(1));
+    let chain = MessageChain(vec![
+        MessageBlock::Source {
+            id: 123,
+            time: DateTime::from_timestamp(1_600_000_000, 0).unwrap(),
+        },
+        MessageBlock::Quote {
+            id: 100,
+            group_id: QQ(2),
+            sender_id: QQ(3),
+            target_id: QQ(4),
+            origin,
+        },
+        MessageBlock::At {
+            target: QQ(5),
+            display: "@某人".to_string(),
+        },
+        MessageBlock::AtAll,
+        MessageBlock::Face {
+            face_id: 14,
+            name: "微笑".to_string(),
+        },
+        MessageBlock::text("text: [1]"),
+        MessageBlock::Image {
+            source: MediaSource::Id("/f8f1ab55-bf8e-4236-b55e-955848d7069f".to_string()),
+            url: Some("http://example.com/a.jpg?a=1,b=2".to_string()),
+        },
+        MessageBlock::image_url("http://example.com/b.jpg"),
+        MessageBlock::image(MediaSource::Path("c.jpg".to_string())),
+        MessageBlock::flash_image_bytes("hello"),
+        MessageBlock::voice_url("http://example.com/a.silk"),
+        MessageBlock::Xml {
+            xml: "<?xml version='1.0'?><msg/>".to_string(),
+        },
+        MessageBlock::File {
+            id: "/file-id".to_string(),
+            name: "a.txt".to_string(),
+            size: 10,
+        },
+        MessageBlock::Forward {
+            nodes: vec![
+                ForwardNode {
+                    sender_id: QQ(6),
+                    time: DateTime::from_timestamp(1_600_000_000, 0).unwrap(),
+                    sender_name: "某人".to_string(),
+                    message: MessageChain::new()
+                        .text("第一条, ")
+                        .image_url("http://a.com/a.jpg"),
+                    message_id: None,
+                },
+                ForwardNode {
+                    sender_id: QQ(7),
+                    time: DateTime::from_timestamp(1_600_000_001, 0).unwrap(),
+                    sender_name: String::new(),
+                    message: MessageChain::new(),
+                    message_id: Some(8),
+                },
+            ],
+        },
+        MessageBlock::dice(6),
+        MessageBlock::poke(PokeKind::FangDaZhao),
+        MessageBlock::market_face(1, "[吃瓜]"),
+        MessageBlock::music_share(
+            MusicKind::QQMusic,
+            "title",
+            "summary",
+            "http://a.com/jump",
+            "http://a.com/pic.jpg",
+            "http://a.com/music.mp3",
+        ),
+        MessageBlock::app(r#"{"app":"com.tencent.miniapp","view":"a"}"#),
+        MessageBlock::short_video("video-id"),
+    ]);
+    let code = chain.to_mirai_code();
+    assert_eq!(MessageChain::from_mirai_code(&code).unwrap(), chain);
+}
@@ -0,0 +1,271 @@
+//! 查询和处理消息内容的辅助方法
+use super::{MessageBlock, MessageChain};
+use crate::bot::QQ;
+use chrono::{DateTime, Utc};
+
+impl MessageChain {
+    /// 不包括 Source 的消息块
+    fn content(&self) -> impl Iterator<Item = &MessageBlock> {
+        self.0
+            .iter()
+            .filter(|block| !matches!(block, MessageBlock::Source { .. }))
+    }
+
+    /// 消息块的数量，不计算 Source
+    pub fn len(&self) -> usize {
+        self.content().count()
+    }
+
+    /// 除了 Source 以外没有别的消息块
+    pub fn is_empty(&self) -> bool {
+        self.content().next().is_none()
+    }
+
+    /// 把所有文字消息块拼接起来，忽略其他的消息块
+    ///
+    /// # Example
+    /// ```
+    /// # use miraie::prelude::*;
+    /// let chain = MessageChain::new().text("你好").at(QQ(123)).text("世界");
+    /// assert_eq!(chain.plain_text(), "你好世界");
+    /// ```
+    pub fn plain_text(&self) -> String {
+        self.content()
+            .filter_map(|block| match block {
+                MessageBlock::Text { text } => Some(text.as_str()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// 消息中 at 的所有 QQ 号，不包括 at 全体成员
+    pub fn ats(&self) -> impl Iterator<Item = QQ> + '_ {
+        self.content().filter_map(|block| match block {
+            MessageBlock::At { target, .. } => Some(*target),
+            _ => None,
+        })
+    }
+
+    /// 消息是否 at 了 `qq`，通常用来判断是否 at 了机器人。at 全体成员不算。
+    pub fn mentions(&self, qq: QQ) -> bool {
+        self.ats().any(|target| target == qq)
+    }
+
+    /// 消息中的图片，包括闪照
+    pub fn images(&self) -> impl Iterator<Item = &MessageBlock> {
+        self.content().filter(|block| {
+            matches!(
+                block,
+                MessageBlock::Image { .. } | MessageBlock::FlashImage { .. }
+            )
+        })
+    }
+
+    /// 消息引用回复的消息块，见 [`MessageBlock::Quote`]
+    pub fn quote(&self) -> Option<&MessageBlock> {
+        self.content()
+            .find(|block| matches!(block, MessageBlock::Quote { .. }))
+    }
+
+    /// 引用回复的原消息的 message id
+    pub fn quote_id(&self) -> Option<i64> {
+        match self.quote()? {
+            MessageBlock::Quote { id, .. } => Some(*id),
+            _ => None,
+        }
+    }
+
+    /// 接收到消息的时间
+    pub fn source_time(&self) -> Option<DateTime<Utc>> {
+        self.0.iter().find_map(|block| match block {
+            MessageBlock::Source { time, .. } => Some(*time),
+            _ => None,
+        })
+    }
+
+    /// 去掉消息开头的文字 `prefix`，不以 `prefix` 开头时返回 `None`。
+    ///
+    /// `prefix` 可以跨越多个相邻的文字消息块，Source 和引用回复会被保留，不参与匹配。
+    ///
+    /// # Example
+    /// ```
+    /// # use miraie::prelude::*;
+    /// let chain = MessageChain::new().text("/ba").text("n ").at(QQ(123));
+    /// assert_eq!(
+    ///     chain.strip_prefix("/ban"),
+    ///     Some(MessageChain::new().text(" ").at(QQ(123)))
+    /// );
+    /// assert_eq!(chain.strip_prefix("/kick"), None);
+    /// ```
+    pub fn strip_prefix(&self, prefix: &str) -> Option<MessageChain> {
+        let mut rest = prefix;
+        let mut blocks = Vec::with_capacity(self.0.len());
+        for block in self.0.iter() {
+            if rest.is_empty() {
+                blocks.push(block.clone());
+                continue;
+            }
+            match block {
+                MessageBlock::Source { .. } | MessageBlock::Quote { .. } => {
+                    blocks.push(block.clone())
+                }
+                MessageBlock::Text { text } => {
+                    if let Some(remain) = text.strip_prefix(rest) {
+                        rest = "";
+                        if !remain.is_empty() {
+                            blocks.push(MessageBlock::text(remain));
+                        }
+                    } else if let Some(remain) = rest.strip_prefix(text.as_str()) {
+                        rest = remain;
+                    } else {
+                        return None;
+                    }
+                }
+                _ => return None,
+            }
+        }
+        rest.is_empty().then_some(MessageChain(blocks))
+    }
+
+    /// 把文字消息块按照空白字符拆分，其他的消息块（不包括 Source）保持原样，
+    /// 通常用来解析指令的参数。
+    ///
+    /// # Example
+    /// ```
+    /// # use miraie::prelude::*;
+    /// let chain = MessageChain::new().text(" ban ").at(QQ(123)).text("10 分钟");
+    /// assert_eq!(
+    ///     chain.split_whitespace(),
+    ///     vec![
+    ///         MessageBlock::text("ban"),
+    ///         MessageBlock::at(QQ(123)),
+    ///         MessageBlock::text("10"),
+    ///         MessageBlock::text("分钟"),
+    ///     ]
+    /// );
+    /// ```
+    pub fn split_whitespace(&self) -> Vec<MessageBlock> {
+        let mut blocks = Vec::new();
+        for block in self.content() {
+            match block {
+                MessageBlock::Text { text } => {
+                    blocks.extend(text.split_whitespace().map(MessageBlock::text))
+                }
+                _ => blocks.push(block.clone()),
+            }
+        }
+        blocks
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn received(chain: MessageChain) -> MessageChain {
+        let mut blocks = vec![MessageBlock::Source {
+            id: 1,
+            time: DateTime::from_timestamp(123, 0).unwrap(),
+        }];
+        blocks.extend(chain.0);
+        MessageChain(blocks)
+    }
+
+    #[test]
+    fn test_len() {
+        let chain = received(MessageChain::new());
+        assert!(chain.is_empty());
+        assert_eq!(chain.len(), 0);
+
+        let chain = received(MessageChain::new().text("a").at(QQ(1)));
+        assert!(!chain.is_empty());
+        assert_eq!(chain.len(), 2);
+    }
+
+    #[test]
+    fn test_query() {
+        let chain = received(
+            MessageChain::new()
+                .at(QQ(1))
+                .text("你好")
+                .image_id("a")
+                .at_all()
+                .flash_image_url("b")
+                .at(QQ(2))
+                .text("世界"),
+        );
+        assert_eq!(chain.plain_text(), "你好世界");
+        assert_eq!(chain.ats().collect::<Vec<_>>(), vec![QQ(1), QQ(2)]);
+        assert!(chain.mentions(QQ(2)));
+        assert!(!chain.mentions(QQ(3)));
+        assert_eq!(
+            chain.images().cloned().collect::<Vec<_>>(),
+            vec![
+                MessageBlock::image_id("a"),
+                MessageBlock::flash_image_url("b")
+            ]
+        );
+        assert_eq!(chain.quote(), None);
+        assert_eq!(
+            chain.source_time(),
+            Some(DateTime::from_timestamp(123, 0).unwrap())
+        );
+        assert_eq!(MessageChain::new().source_time(), None);
+
+        let mut blocks = chain.0.clone();
+        blocks.insert(1, MessageBlock::quote(42));
+        let quoted = MessageChain(blocks);
+        assert_eq!(quoted.quote(), Some(&MessageBlock::quote(42)));
+        assert_eq!(quoted.quote_id(), Some(42));
+        assert_eq!(chain.quote_id(), None);
+    }
+
+    #[test]
+    fn test_strip_prefix() {
+        let chain = received(MessageChain::new().text("/").text("ban 1").at(QQ(1)));
+        assert_eq!(
+            chain.strip_prefix("/ban"),
+            Some(received(MessageChain::new().text(" 1").at(QQ(1))))
+        );
+        assert_eq!(
+            chain.strip_prefix("/ban 1"),
+            Some(received(MessageChain::new().at(QQ(1))))
+        );
+        assert_eq!(chain.strip_prefix(""), Some(chain.clone()));
+        assert_eq!(chain.strip_prefix("/kick"), None);
+        // 前缀不能跨过非文字的消息块
+        assert_eq!(chain.strip_prefix("/ban 1 "), None);
+        assert_eq!(
+            MessageChain::new()
+                .at(QQ(1))
+                .text("/ban")
+                .strip_prefix("/ban"),
+            None
+        );
+
+        let mut blocks = chain.0.clone();
+        blocks.insert(1, MessageBlock::quote(42));
+        let stripped = MessageChain(blocks).strip_prefix("/ban").unwrap();
+        assert_eq!(stripped.quote(), Some(&MessageBlock::quote(42)));
+        assert_eq!(stripped.plain_text(), " 1");
+    }
+
+    #[test]
+    fn test_split_whitespace() {
+        let chain = received(MessageChain::new().text("  a\tb ").at(QQ(1)).text("c\nd"));
+        assert_eq!(
+            chain.split_whitespace(),
+            vec![
+                MessageBlock::text("a"),
+                MessageBlock::text("b"),
+                MessageBlock::at(QQ(1)),
+                MessageBlock::text("c"),
+                MessageBlock::text("d"),
+            ]
+        );
+        assert!(MessageChain::new()
+            .text("   ")
+            .split_whitespace()
+            .is_empty());
+    }
+}
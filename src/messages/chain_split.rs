@@ -0,0 +1,210 @@
+//! 把过长的消息拆分成多条发送
+use super::{MessageBlock, MessageChain};
+
+/// 优先在这些字符之后拆分，按照优先级排列
+const BOUNDARIES: &[&[char]] = &[
+    &['\n'],
+    &['。', '！', '？', '!', '?', '；', ';', '…'],
+    &['，', ',', ' '],
+];
+
+impl MessageChain {
+    /// 把消息拆分成若干条，每条的长度（按照显示的字符数计算）不超过 `max_len`。
+    ///
+    /// 文字会尽量在换行、句子结束的位置拆开，实在找不到才会在句子中间拆开；
+    /// 图片、at 等其他消息块不会被拆开，如果单个消息块就超过了 `max_len`，会单独作为一条消息。
+    /// Source 会被去掉，引用回复只会保留在第一条消息中。
+    ///
+    /// # Panics
+    /// `max_len` 为 0 时 panic。
+    ///
+    /// # Example
+    /// ```
+    /// # use miraie::prelude::*;
+    /// let chain = MessageChain::new().text("第一句。第二句。").at(QQ(1));
+    /// assert_eq!(
+    ///     chain.split_for_sending(6),
+    ///     vec![
+    ///         MessageChain::new().text("第一句。"),
+    ///         MessageChain::new().text("第二句。").at(QQ(1)),
+    ///     ]
+    /// );
+    /// ```
+    pub fn split_for_sending(&self, max_len: usize) -> Vec<MessageChain> {
+        assert!(max_len > 0, "max_len 必须大于 0");
+        let mut splitter = Splitter {
+            max_len,
+            parts: Vec::new(),
+            current: Vec::new(),
+            current_len: 0,
+        };
+        for block in self.0.iter() {
+            match block {
+                MessageBlock::Source { .. } => {}
+                MessageBlock::Quote { .. } => {
+                    if splitter.parts.is_empty() {
+                        splitter.current.push(block.clone())
+                    }
+                }
+                MessageBlock::Text { text } => splitter.push_text(text),
+                _ => splitter.push_block(block.clone()),
+            }
+        }
+        splitter.flush();
+        splitter.parts
+    }
+}
+
+struct Splitter {
+    max_len: usize,
+    parts: Vec<MessageChain>,
+    current: Vec<MessageBlock>,
+    current_len: usize,
+}
+
+impl Splitter {
+    fn flush(&mut self) {
+        let has_content = self
+            .current
+            .iter()
+            .any(|block| !matches!(block, MessageBlock::Quote { .. }));
+        if has_content {
+            self.parts
+                .push(MessageChain(std::mem::take(&mut self.current)));
+        }
+        self.current_len = 0;
+    }
+
+    fn push_block(&mut self, block: MessageBlock) {
+        let len = block.to_string().chars().count();
+        if self.current_len > 0 && self.current_len + len > self.max_len {
+            self.flush();
+        }
+        self.current_len += len;
+        self.current.push(block);
+    }
+
+    fn push_text(&mut self, mut text: &str) {
+        while !text.is_empty() {
+            let available = self.max_len - self.current_len.min(self.max_len);
+            // 剩下的文字放得下
+            let end = match text.char_indices().nth(available) {
+                None => {
+                    self.push_piece(text);
+                    return;
+                }
+                Some((end, _)) => end,
+            };
+            match find_boundary(&text[..end]) {
+                Some(i) => {
+                    self.push_piece(&text[..i]);
+                    text = &text[i..];
+                }
+                // 找不到合适的位置，如果当前消息已经有内容就把文字留到下一条，否则只能硬拆
+                None if self.current_len > 0 => {}
+                None => {
+                    self.push_piece(&text[..end]);
+                    text = &text[end..];
+                }
+            }
+            self.flush();
+            // 下一条消息不需要以换行开头
+            text = text.trim_start_matches(['\n', '\r']);
+        }
+    }
+
+    fn push_piece(&mut self, piece: &str) {
+        if piece.is_empty() {
+            return;
+        }
+        self.current_len += piece.chars().count();
+        match self.current.last_mut() {
+            Some(MessageBlock::Text { text }) => text.push_str(piece),
+            _ => self.current.push(MessageBlock::text(piece)),
+        }
+    }
+}
+
+/// 在 `text` 中找到最靠后的拆分位置，返回拆分后前半部分的长度
+fn find_boundary(text: &str) -> Option<usize> {
+    BOUNDARIES.iter().find_map(|chars| {
+        text.rfind(*chars)
+            .map(|i| i + text[i..].chars().next().unwrap().len_utf8())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bot::QQ;
+
+    #[test]
+    fn test_split_short_message() {
+        let chain = MessageChain::new().text("hello").at(QQ(1));
+        assert_eq!(chain.split_for_sending(100), vec![chain.clone()]);
+        assert!(MessageChain::new().split_for_sending(100).is_empty());
+    }
+
+    #[test]
+    fn test_split_at_boundaries() {
+        let chain = MessageChain::new().text("第一行\n第二行，很长很长。第三句");
+        assert_eq!(
+            chain.split_for_sending(10),
+            vec![
+                MessageChain::new().text("第一行\n"),
+                MessageChain::new().text("第二行，很长很长。"),
+                MessageChain::new().text("第三句"),
+            ]
+        );
+
+        // 没有合适的拆分位置时硬拆
+        let chain = MessageChain::new().text("abcdefgh");
+        assert_eq!(
+            chain.split_for_sending(3),
+            vec![
+                MessageChain::new().text("abc"),
+                MessageChain::new().text("def"),
+                MessageChain::new().text("gh"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_split_keeps_blocks() {
+        let chain = MessageChain(vec![
+            MessageBlock::Source {
+                id: 1,
+                time: chrono::DateTime::from_timestamp(0, 0).unwrap(),
+            },
+            MessageBlock::quote(42),
+        ])
+        .image_url("http://a.com/a.jpg")
+        .text("一二三四五六")
+        .at(QQ(1))
+        .text("七八");
+        assert_eq!(
+            chain.split_for_sending(8),
+            vec![
+                MessageChain(vec![MessageBlock::quote(42)]).image_url("http://a.com/a.jpg"),
+                MessageChain::new().text("一二三四五六").at(QQ(1)),
+                MessageChain::new().text("七八"),
+            ]
+        );
+
+        // 过长的消息块单独发送
+        let chain = MessageChain::new()
+            .text("a")
+            .music_share(
+                crate::messages::MusicKind::QQMusic,
+                "很长的标题",
+                "",
+                "",
+                "",
+                "",
+            )
+            .text("b");
+        let parts = chain.split_for_sending(3);
+        assert_eq!(parts.len(), 3);
+        assert_eq!(parts[1].0.len(), 1);
+    }
+}
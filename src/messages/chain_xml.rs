@@ -1,108 +1,538 @@
-use super::{MessageBlock, MessageChain};
-
-use regex::{Regex, RegexBuilder};
+use super::{MediaSource, MessageBlock, MessageChain};
+use crate::{bot::QQ, Error, Result};
+use std::{borrow::Borrow, collections::HashMap, hash::Hash};
 
 impl MessageChain {
-    /// 从简单的 xml 解析出 [`MessageChain`]
+    /// 从简单的 xml 标记解析出 [`MessageChain`]，格式错误时返回 [`Error::Format`]。
     ///
     /// # 标签
-    /// 标签内容都是路径，且是相对于 env:MIRAIE_RESOURCE_ROOT/{voices|images} 的相对路径。
-    /// - `i`, `img`, `image`：图片
-    /// - `v`, `voice`：音频
+    /// - `<at qq="123"/>`：at 某人
+    /// - `<atall/>`：at 全体成员
+    /// - `<face id="14"/>` 或 `<face name="微笑"/>`：QQ 表情
+    /// - `<img url="..."/>`、`<img base64="..."/>`、`<img path="..."/>`、`<img id="..."/>`：图片，
+    ///   `i`、`image` 与 `img` 相同
+    /// - `<voice url="..."/>` 等：语音，参数同图片，`v` 与 `voice` 相同
+    /// - `<quote id="123"/>`：引用回复
+    /// - `<br/>`：换行
+    ///
+    /// 兼容旧的写法：`<img> a.jpg </img>`、`<v> a.silk </v>`，标签内容是相对于
+    /// env:MIRAIE_RESOURCE_ROOT/{voices|images} 的路径。`path` 属性也是这样的路径，
+    /// 没有设置 MIRAIE_RESOURCE_ROOT 时使用本地文件会返回 [`Error::Format`]。
+    ///
+    /// # 文本
+    /// 只有空白的文本（如标签之间的换行和缩进）会被忽略，文本两端含有换行的空白以及开头和结尾的空白会被去掉，
+    /// 其他的空格会保留，如 `你好 <at qq="1"/> 再见` 中 at 两边的空格。需要换行请使用 `<br/>`，
+    /// 需要保留被去掉的空白请使用 `&#32;`。
+    /// 支持 `&lt;`、`&gt;`、`&amp;`、`&quot;`、`&apos;` 与 `&#数字;` 形式的转义。
     ///
     /// # Example
     /// ```
     /// # use miraie::prelude::*;
     /// # std::env::set_var("MIRAIE_RESOURCE_ROOT", ".");
-    /// let chain = MessageChain::from_xml("下面是图片：<img> image.jpg </img>");
+    /// let chain = MessageChain::from_xml("下面是图片：<img> image.jpg </img>").unwrap();
+    /// let chain = MessageChain::from_xml(r#"<at qq="123"/> 你好 &lt;3<br/><face id="14"/>"#).unwrap();
+    /// assert!(MessageChain::from_xml("<img>没有结束标签").is_err());
+    /// ```
+    ///
+    pub fn from_xml(xml: &str) -> Result<Self> {
+        Parser::new(xml, None).parse()
+    }
+
+    /// 跟 [`MessageChain::from_xml`] 相同，但是会把文本和属性中的 `{name}` 替换为 `context` 中对应的值。
+    ///
+    /// 替换发生在解析之后，所以替换进去的值不会被当作标签解析。
+    /// 使用 `{{` 和 `}}` 表示 `{` 和 `}`，找不到对应的值时返回 [`Error::Format`]。
+    ///
+    /// # Example
     /// ```
+    /// # use miraie::prelude::*;
+    /// use std::collections::HashMap;
     ///
-    pub fn from_xml(xml: &str) -> Self {
-        lazy_static::lazy_static! {
-            static ref START_TAG_PATTERN: Regex = RegexBuilder::new(r"<(i|v|voice|img|image)>")
-                .case_insensitive(true)
-                .build()
-                .unwrap();
-        }
-        let mut blocks = vec![];
-
-        let mut rest = xml;
-        while let Some(cap) = START_TAG_PATTERN.captures(rest) {
-            let matches = cap.get(0).unwrap();
-            let (tag_start, tag_end) = (matches.start(), matches.end());
-            let tag = &cap[1];
-            // 处理这个标签之前的文本
-            if tag_start > 0 {
-                let head = rest[..tag_start].trim();
-                if !head.is_empty() {
-                    blocks.push(MessageBlock::text(head));
+    /// let mut context = HashMap::new();
+    /// context.insert("name", "<嘉然>".to_string());
+    /// context.insert("qq", "123".to_string());
+    /// let chain = MessageChain::from_xml_template(r#"<at qq="{qq}"/>{name}，你好"#, &context).unwrap();
+    /// assert_eq!(chain, MessageChain::new().at(QQ(123)).text("<嘉然>，你好"));
+    /// ```
+    pub fn from_xml_template<K, V>(xml: &str, context: &HashMap<K, V>) -> Result<Self>
+    where
+        K: Borrow<str> + Hash + Eq,
+        V: AsRef<str>,
+    {
+        let lookup = |name: &str| context.get(name).map(|v| v.as_ref().to_string());
+        Parser::new(xml, Some(&lookup)).parse()
+    }
+}
+
+type Lookup<'a> = &'a dyn Fn(&str) -> Option<String>;
+
+struct Parser<'a> {
+    src: &'a str,
+    pos: usize,
+    context: Option<Lookup<'a>>,
+    blocks: Vec<MessageBlock>,
+}
+
+/// 解析出的一个标签
+struct Tag<'a> {
+    name: String,
+    attrs: Vec<(&'a str, &'a str)>,
+    self_closing: bool,
+}
+
+impl<'a> Parser<'a> {
+    fn new(src: &'a str, context: Option<Lookup<'a>>) -> Self {
+        Self {
+            src,
+            pos: 0,
+            context,
+            blocks: vec![],
+        }
+    }
+
+    fn error(&self, reason: impl std::fmt::Display) -> Error {
+        Error::format(format!("xml 格式错误（位置 {}）：{}", self.pos, reason))
+    }
+
+    fn rest(&self) -> &'a str {
+        &self.src[self.pos..]
+    }
+
+    fn parse(mut self) -> Result<MessageChain> {
+        while !self.rest().is_empty() {
+            match self.rest().find('<') {
+                Some(0) => self.parse_tag()?,
+                Some(i) => {
+                    let text = &self.rest()[..i];
+                    self.push_text(text, self.pos == 0, false)?;
+                    self.pos += i;
+                }
+                None => {
+                    let text = self.rest();
+                    self.push_text(text, self.pos == 0, true)?;
+                    self.pos = self.src.len();
                 }
             }
+        }
+        Ok(MessageChain(self.blocks))
+    }
 
-            let after_tag = &rest[tag_end..];
+    /// 去掉只用于排版的空白后跟前一个文本合并，`first`、`last` 表示文本在开头、结尾
+    fn push_text(&mut self, raw: &str, first: bool, last: bool) -> Result<()> {
+        if raw.trim().is_empty() {
+            return Ok(());
+        }
+        let trimmed = raw.trim_start();
+        let raw = match first || raw[..raw.len() - trimmed.len()].contains('\n') {
+            true => trimmed,
+            false => raw,
+        };
+        let trimmed = raw.trim_end();
+        let raw = match last || raw[trimmed.len()..].contains('\n') {
+            true => trimmed,
+            false => raw,
+        };
+        let text = self.render(raw)?;
+        self.push_block(MessageBlock::text(text));
+        Ok(())
+    }
 
-            let end_pat = format!("</{}>", tag);
-            if let Some(end_tag_left) = after_tag.find(&end_pat) {
-                let body = after_tag[..end_tag_left].trim();
-                match tag {
-                    "v" | "voice" => blocks.push(MessageBlock::voice_path(body)),
-                    "i" | "img" | "image" => blocks.push(MessageBlock::image_path(body)),
-                    _ => unreachable!(),
-                }
-                rest = &after_tag[end_tag_left + end_pat.len()..];
+    fn push_block(&mut self, block: MessageBlock) {
+        if let (Some(MessageBlock::Text { text: last }), MessageBlock::Text { text }) =
+            (self.blocks.last_mut(), &block)
+        {
+            last.push_str(text);
+            return;
+        }
+        self.blocks.push(block);
+    }
+
+    /// 处理转义和占位符，替换进去的值不会再被转义
+    fn render(&self, raw: &str) -> Result<String> {
+        match self.context {
+            Some(context) => self.substitute(raw, context),
+            None => self.unescape(raw),
+        }
+    }
+
+    fn unescape(&self, raw: &str) -> Result<String> {
+        let mut s = String::with_capacity(raw.len());
+        let mut rest = raw;
+        while let Some(i) = rest.find('&') {
+            s.push_str(&rest[..i]);
+            rest = &rest[i..];
+            let end = rest
+                .find(';')
+                .ok_or_else(|| self.error(format!("转义没有结束：{}", rest)))?;
+            let entity = &rest[1..end];
+            let c = match entity {
+                "lt" => '<',
+                "gt" => '>',
+                "amp" => '&',
+                "quot" => '"',
+                "apos" => '\'',
+                _ => entity
+                    .strip_prefix("#x")
+                    .map(|hex| u32::from_str_radix(hex, 16))
+                    .or_else(|| entity.strip_prefix('#').map(|dec| dec.parse()))
+                    .and_then(|code| code.ok())
+                    .and_then(char::from_u32)
+                    .ok_or_else(|| self.error(format!("未知的转义：&{};", entity)))?,
+            };
+            s.push(c);
+            rest = &rest[end + 1..];
+        }
+        s.push_str(rest);
+        Ok(s)
+    }
+
+    fn substitute(&self, s: &str, context: Lookup) -> Result<String> {
+        let mut output = String::with_capacity(s.len());
+        let mut rest = s;
+        while let Some(i) = rest.find(['{', '}']) {
+            output.push_str(&self.unescape(&rest[..i])?);
+            rest = &rest[i..];
+            if rest.starts_with("{{") || rest.starts_with("}}") {
+                output.push_str(&rest[..1]);
+                rest = &rest[2..];
+            } else if rest.starts_with('}') {
+                return Err(self.error("多余的 `}`，请使用 `}}`"));
             } else {
-                // 没找到结束标签，剩下的全部处理成字符串
-                blocks.push(MessageBlock::text(rest));
-                // 清空
-                rest = &rest[rest.len()..];
+                let end = rest
+                    .find('}')
+                    .ok_or_else(|| self.error(format!("占位符没有结束：{}", rest)))?;
+                let name = rest[1..end].trim();
+                let value =
+                    context(name).ok_or_else(|| self.error(format!("找不到占位符 `{}`", name)))?;
+                output.push_str(&value);
+                rest = &rest[end + 1..];
             }
         }
-        // 处理剩下的文本
-        rest = rest.trim();
-        if !rest.is_empty() {
-            blocks.push(MessageBlock::text(rest));
+        output.push_str(&self.unescape(rest)?);
+        Ok(output)
+    }
+
+    fn parse_tag(&mut self) -> Result<()> {
+        let tag = self.read_tag()?;
+        let block = match tag.name.as_str() {
+            "br" => MessageBlock::text("\n"),
+            "at" => MessageBlock::at(QQ(self.parse_attr(&tag, "qq")?)),
+            "atall" => MessageBlock::at_all(),
+            "face" => match (self.attr(&tag, "id")?, self.attr(&tag, "name")?) {
+                (Some(id), _) => MessageBlock::Face {
+                    face_id: id
+                        .parse()
+                        .map_err(|_| self.error(format!("无效的表情 id：{}", id)))?,
+                    name: String::new(),
+                },
+                (None, Some(name)) => MessageBlock::Face { face_id: -1, name },
+                (None, None) => return Err(self.error("`face` 需要 `id` 或 `name` 属性")),
+            },
+            "quote" => MessageBlock::quote(self.parse_attr(&tag, "id")?),
+            "i" | "img" | "image" => self.media_block(&tag, MediaKind::Image)?,
+            "v" | "voice" => self.media_block(&tag, MediaKind::Voice)?,
+            _ => return Err(self.error(format!("未知的标签 `{}`", tag.name))),
+        };
+        self.push_block(block);
+        Ok(())
+    }
+
+    /// 读取 `<name attr="value">`、`<name/>`，不支持单独的结束标签
+    fn read_tag(&mut self) -> Result<Tag<'a>> {
+        let src = self.src;
+        let end = tag_end(self.rest()).ok_or_else(|| self.error("标签没有结束"))?;
+        let inner = &src[self.pos + 1..self.pos + end];
+        if inner.starts_with('/') {
+            return Err(self.error(format!("多余的结束标签 `<{}>`", inner)));
+        }
+        let (inner, self_closing) = match inner.strip_suffix('/') {
+            Some(inner) => (inner, true),
+            None => (inner, false),
+        };
+        let inner = inner.trim();
+        let name_end = inner.find(char::is_whitespace).unwrap_or(inner.len());
+        let name = inner[..name_end].to_lowercase();
+        if name.is_empty() {
+            return Err(self.error("标签名为空"));
+        }
+
+        let mut attrs = vec![];
+        let mut rest = inner[name_end..].trim_start();
+        while !rest.is_empty() {
+            let eq = rest
+                .find('=')
+                .ok_or_else(|| self.error(format!("`{}` 的属性格式错误", name)))?;
+            let key = rest[..eq].trim();
+            let value_part = rest[eq + 1..].trim_start();
+            let quote = value_part
+                .chars()
+                .next()
+                .filter(|c| *c == '"' || *c == '\'')
+                .ok_or_else(|| self.error(format!("属性 `{}` 的值需要引号", key)))?;
+            let value_end = value_part[1..]
+                .find(quote)
+                .ok_or_else(|| self.error(format!("属性 `{}` 的引号没有结束", key)))?;
+            attrs.push((key, &value_part[1..value_end + 1]));
+            rest = value_part[value_end + 2..].trim_start();
+        }
+
+        self.pos += end + 1;
+        Ok(Tag {
+            name,
+            attrs,
+            self_closing,
+        })
+    }
+
+    /// 读取标签的内容直到结束标签
+    fn read_body(&mut self, tag: &Tag) -> Result<&'a str> {
+        let src = self.src;
+        let rest = self.rest();
+        let lower = rest.to_ascii_lowercase();
+        let end_tag = format!("</{}>", tag.name);
+        let end = match lower.find(&end_tag) {
+            // 只转换 ASCII，不影响位置
+            Some(end) => end,
+            None => return Err(self.error(format!("`<{}>` 没有结束标签", tag.name))),
+        };
+        let body = &src[self.pos..self.pos + end];
+        self.pos += end + end_tag.len();
+        Ok(body)
+    }
+
+    fn attr(&self, tag: &Tag, key: &str) -> Result<Option<String>> {
+        tag.attrs
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(key))
+            .map(|(_, v)| self.render(v))
+            .transpose()
+    }
+
+    fn parse_attr<T: std::str::FromStr>(&self, tag: &Tag, key: &str) -> Result<T> {
+        let value = self
+            .attr(tag, key)?
+            .ok_or_else(|| self.error(format!("`{}` 需要 `{}` 属性", tag.name, key)))?;
+        value
+            .trim()
+            .parse()
+            .map_err(|_| self.error(format!("属性 `{}` 的值无效：{}", key, value)))
+    }
+
+    fn media_block(&mut self, tag: &Tag, kind: MediaKind) -> Result<MessageBlock> {
+        let body = if tag.self_closing {
+            String::new()
+        } else {
+            let body = self.read_body(tag)?;
+            self.render(body.trim())?
+        };
+        if let Some(url) = self.attr(tag, "url")? {
+            return Ok(kind.url(url));
+        }
+        if let Some(path) = self.attr(tag, "path")? {
+            return self.path_block(kind, path);
+        }
+        if let Some(id) = self.attr(tag, "id")? {
+            return Ok(kind.source(MediaSource::Id(id)));
+        }
+        if let Some(base64) = self.attr(tag, "base64")? {
+            return Ok(kind.source(MediaSource::Base64(base64)));
+        }
+        if !body.is_empty() {
+            return self.path_block(kind, body);
+        }
+        Err(self.error(format!(
+            "`{}` 需要 `url`、`path`、`id` 或 `base64` 属性",
+            tag.name
+        )))
+    }
+
+    /// 本地文件的路径相对于 env:MIRAIE_RESOURCE_ROOT，没有设置时返回错误
+    fn path_block(&self, kind: MediaKind, path: String) -> Result<MessageBlock> {
+        if std::env::var("MIRAIE_RESOURCE_ROOT").is_err() {
+            return Err(self.error(format!(
+                "使用本地文件 `{}` 需要设置环境变量 MIRAIE_RESOURCE_ROOT",
+                path
+            )));
+        }
+        Ok(kind.path(path))
+    }
+}
+
+/// 标签结尾的 `>` 的位置，跳过引号中的 `>`
+fn tag_end(s: &str) -> Option<usize> {
+    let mut quote = None;
+    for (i, c) in s.char_indices() {
+        match (quote, c) {
+            (None, '>') => return Some(i),
+            (None, '"' | '\'') => quote = Some(c),
+            (Some(q), c) if q == c => quote = None,
+            _ => {}
         }
+    }
+    None
+}
 
-        Self(blocks)
+#[derive(Clone, Copy)]
+enum MediaKind {
+    Image,
+    Voice,
+}
+
+impl MediaKind {
+    fn url(self, url: String) -> MessageBlock {
+        match self {
+            MediaKind::Image => MessageBlock::image_url(url),
+            MediaKind::Voice => MessageBlock::voice_url(url),
+        }
+    }
+    fn path(self, path: String) -> MessageBlock {
+        match self {
+            MediaKind::Image => MessageBlock::image_path(path),
+            MediaKind::Voice => MessageBlock::voice_path(path),
+        }
+    }
+    fn source(self, source: MediaSource) -> MessageBlock {
+        match self {
+            MediaKind::Image => MessageBlock::image(source),
+            MediaKind::Voice => MessageBlock::voice(source),
+        }
     }
 }
 
 #[test]
 fn test_parse_from_xml() {
+    // 没有设置资源目录时使用本地文件会返回错误而不是 panic
+    std::env::remove_var("MIRAIE_RESOURCE_ROOT");
+    for s in ["<v> filename.silk </v>", r#"<img path="image.jpg"/>"#] {
+        assert!(MessageChain::from_xml(s).is_err(), "{} should fail", s);
+    }
+
     std::env::set_var("MIRAIE_RESOURCE_ROOT", ".");
     let s = "hello, world";
-    assert_eq!(MessageChain::from_xml(s), MessageChain::new().text(s));
+    assert_eq!(
+        MessageChain::from_xml(s).unwrap(),
+        MessageChain::new().text(s)
+    );
 
     let s = "<v> filename.silk </v>";
     assert_eq!(
-        MessageChain::from_xml(s),
+        MessageChain::from_xml(s).unwrap(),
         MessageChain::new().voice_path("filename.silk")
     );
 
     let s = "prefix <v> filename.silk </v> postfix";
     assert_eq!(
-        MessageChain::from_xml(s),
+        MessageChain::from_xml(s).unwrap(),
         MessageChain::new()
-            .text("prefix")
+            .text("prefix ")
             .voice_path("filename.silk")
-            .text("postfix")
+            .text(" postfix")
     );
 
     let s = "prefix <v> filename.silk </v> <image> image.jpg </image> postfix   ";
     assert_eq!(
-        MessageChain::from_xml(s),
+        MessageChain::from_xml(s).unwrap(),
         MessageChain::new()
-            .text("prefix")
+            .text("prefix ")
             .voice_path("filename.silk")
             .image_path("image.jpg")
-            .text("postfix")
+            .text(" postfix")
     );
     let s = "prefix <v> 1.silk </v> <voice>2.silk</voice>      \n";
     assert_eq!(
-        MessageChain::from_xml(s),
+        MessageChain::from_xml(s).unwrap(),
         MessageChain::new()
-            .text("prefix")
+            .text("prefix ")
             .voice_path("1.silk")
             .voice_path("2.silk")
     );
 }
+
+#[test]
+fn test_parse_xml_tags() {
+    let s = r#"<quote id="100"/><at qq="123"/> 你好 <AtAll/><face id="14"/><br/>
+        <img url="http://a.com/a.jpg"/><img base64='aGVsbG8='/>&lt;3&#32;&amp;&#x41;"#;
+    assert_eq!(
+        MessageChain::from_xml(s).unwrap(),
+        MessageChain(vec![
+            MessageBlock::quote(100),
+            MessageBlock::at(QQ(123)),
+            MessageBlock::text(" 你好 "),
+            MessageBlock::AtAll,
+            MessageBlock::Face {
+                face_id: 14,
+                name: String::new()
+            },
+            MessageBlock::text("\n"),
+            MessageBlock::image_url("http://a.com/a.jpg"),
+            MessageBlock::image_bytes("hello"),
+            MessageBlock::text("<3 &A"),
+        ])
+    );
+    // 相邻的文本会合并
+    assert_eq!(
+        MessageChain::from_xml("第一行<br/>第二行").unwrap(),
+        MessageChain::new().text("第一行\n第二行")
+    );
+    // 用于排版的换行和缩进会被去掉，标签两边的空格会保留
+    let s = "  你好 <at qq=\"1\"/> 再见\n    <br/>\n    下一行  ";
+    assert_eq!(
+        MessageChain::from_xml(s).unwrap(),
+        MessageChain::new()
+            .text("你好 ")
+            .at(QQ(1))
+            .text(" 再见\n下一行")
+    );
+    // 引号中的 `>` 不会结束标签
+    assert_eq!(
+        MessageChain::from_xml(r#"<img url="http://a.com/?a>b"/>后面"#).unwrap(),
+        MessageChain::new()
+            .image_url("http://a.com/?a>b")
+            .text("后面")
+    );
+}
+
+#[test]
+fn test_parse_xml_errors() {
+    for s in [
+        "<img>a.jpg",
+        "<v>a.silk</img>",
+        "<unknown/>",
+        "<at/>",
+        r#"<at qq="abc"/>"#,
+        r#"<at qq=123/>"#,
+        "</at>",
+        "a <b",
+        "&unknown;",
+        "&lt",
+        "<img/>",
+    ] {
+        assert!(MessageChain::from_xml(s).is_err(), "{} should fail", s);
+    }
+}
+
+#[test]
+fn test_parse_xml_template() {
+    let mut context = HashMap::new();
+    context.insert("name", "<b>&".to_string());
+    context.insert("qq", "123".to_string());
+    context.insert("url", "http://a.com/a.jpg".to_string());
+
+    let s = r#"{name}，{{你好}}&#123; <at qq="{qq}"/><img url="{url}"/>"#;
+    assert_eq!(
+        MessageChain::from_xml_template(s, &context).unwrap(),
+        MessageChain::new()
+            .text("<b>&，{你好}{ ")
+            .at(QQ(123))
+            .image_url("http://a.com/a.jpg")
+    );
+    // 没有替换时大括号保持原样
+    assert_eq!(
+        MessageChain::from_xml("{name}").unwrap(),
+        MessageChain::new().text("{name}")
+    );
+
+    assert!(MessageChain::from_xml_template("{missing}", &context).is_err());
+    assert!(MessageChain::from_xml_template("{name", &context).is_err());
+    assert!(MessageChain::from_xml_template("name}", &context).is_err());
+}
@@ -0,0 +1,398 @@
+//! 下载收到的图片、语音、短视频和文件，需要开启 `download` feature。
+//!
+//! 默认使用 reqwest 进行下载，可以通过实现 [`HttpClient`] 替换，并使用
+//! `bot.bot_data(Downloader::new(client))` 注册到 [`Bot`] 中。
+use super::{GroupMessage, MediaSource, MessageBlock};
+use crate::{api, bot::QQ, Bot, Error, Result};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use bytes::Bytes;
+use std::{
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+/// 下载使用的 HTTP 客户端，可以替换成自己的实现，如在测试中进行 mock
+#[async_trait]
+pub trait HttpClient: Send + Sync + 'static {
+    /// 请求 `url` 并返回响应的内容
+    async fn get(&self, url: &str) -> Result<Bytes>;
+}
+
+/// 基于 reqwest 的默认 [`HttpClient`]
+#[derive(Debug, Clone, Default)]
+pub struct ReqwestClient(pub reqwest::Client);
+
+#[async_trait]
+impl HttpClient for ReqwestClient {
+    async fn get(&self, url: &str) -> Result<Bytes> {
+        let download = async {
+            self.0
+                .get(url)
+                .send()
+                .await?
+                .error_for_status()?
+                .bytes()
+                .await
+        };
+        download
+            .await
+            .map_err(|e| Error::Download(format!("下载 {} 失败：{}", url, e)))
+    }
+}
+
+/// 下载器，可以设置 HTTP 客户端和磁盘缓存
+///
+/// # Example
+/// ```no_run
+/// # use miraie::prelude::*;
+/// use miraie::messages::download::{Downloader, ReqwestClient};
+/// # tokio_test::block_on(async {
+/// # let (bot, _) = Bot::new("127.0.0.1", "secret", QQ(123456)).await.unwrap();
+/// // 以 imageId 为键缓存下载过的图片
+/// let bot = bot.bot_data(Downloader::new(ReqwestClient::default()).with_cache("cache/images"));
+///
+/// let bot = bot.handler(|msg: GroupMessage, bot: Bot| async move {
+///     for block in msg.message.0.iter() {
+///         if let MessageBlock::Image { .. } = block {
+///             let image = block.download(&bot).await?;
+///             log::info!("收到图片，大小 {} bytes", image.len());
+///         }
+///     }
+///     Result::<(), miraie::Error>::Ok(())
+/// });
+/// # });
+/// ```
+#[derive(Clone)]
+pub struct Downloader {
+    client: Arc<dyn HttpClient>,
+    cache_dir: Option<PathBuf>,
+}
+
+impl Default for Downloader {
+    fn default() -> Self {
+        Self::new(ReqwestClient::default())
+    }
+}
+
+impl Downloader {
+    /// 通过 `client` 下载，缓存默认关闭
+    pub fn new(client: impl HttpClient) -> Self {
+        Self {
+            client: Arc::new(client),
+            cache_dir: None,
+        }
+    }
+
+    /// 把通过 id 引用的媒体缓存在 `dir` 下，文件名由 id 生成
+    pub fn with_cache(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.cache_dir = Some(dir.into());
+        self
+    }
+
+    /// 下载图片、闪照、语音、短视频或者文件。
+    ///
+    /// base64 来源的媒体会直接解码，不会发起请求；本地路径的媒体无法下载。
+    ///
+    /// 文件消息中没有下载链接，需要通过 `file_info` 获取，`group` 是文件所在的群，
+    /// 不知道时文件会下载失败，其他的媒体不需要 `group`。
+    pub async fn download(
+        &self,
+        block: &MessageBlock,
+        bot: &Bot,
+        group: Option<QQ>,
+    ) -> Result<Bytes> {
+        if let MessageBlock::File { id, name, .. } = block {
+            let group =
+                group.ok_or_else(|| Error::Download(format!("不知道文件 {} 所在的群", name)))?;
+            let url = file_url(bot, id, group).await?;
+            return self.client.get(&url).await;
+        }
+        let source = block
+            .media_source()
+            .ok_or_else(|| Error::Download(format!("{} 不是可以下载的媒体", block)))?;
+        if let MediaSource::Base64(data) = source {
+            return BASE64
+                .decode(data)
+                .map(Bytes::from)
+                .map_err(|e| Error::Download(format!("base64 解码失败：{}", e)));
+        }
+
+        let cache_path = match source {
+            MediaSource::Id(id) => self.cache_path(id),
+            _ => None,
+        };
+        if let Some(path) = &cache_path {
+            if let Ok(data) = tokio::fs::read(path).await {
+                debug!("从缓存 {:?} 读取 {}", path, source.as_str());
+                return Ok(Bytes::from(data));
+            }
+        }
+
+        let url = block
+            .media_url()
+            .ok_or_else(|| Error::Download(format!("{} 没有下载链接", source.as_str())))?;
+        let data = self.client.get(url).await?;
+
+        if let Some(path) = &cache_path {
+            // 缓存只是优化，写入失败时仍然返回下载到的内容
+            if let Err(e) = write_cache(path, &data).await {
+                warn!("写入缓存 {:?} 失败：{}", path, e);
+            }
+        }
+        Ok(data)
+    }
+
+    fn cache_path(&self, id: &str) -> Option<PathBuf> {
+        let dir = self.cache_dir.as_ref()?;
+        let name: String = id
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || c == '-' || c == '.' {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+        Some(dir.join(name))
+    }
+}
+
+/// 通过 `file_info` 获取群文件的下载链接
+async fn file_url(bot: &Bot, id: &str, group: QQ) -> Result<String> {
+    let info = bot
+        .request(api::file_info::Request {
+            id: id.to_string(),
+            path: None,
+            target: group,
+            with_download_info: true,
+        })
+        .await?;
+    info.download_info
+        .map(|download_info| download_info.url)
+        .ok_or_else(|| Error::Download(format!("文件 {} 没有下载信息", info.name)))
+}
+
+/// 先写入临时文件再重命名，其他任务不会读到写了一半的缓存
+async fn write_cache(path: &Path, data: &[u8]) -> std::io::Result<()> {
+    if let Some(dir) = path.parent() {
+        tokio::fs::create_dir_all(dir).await?;
+    }
+    // 同时下载同一个媒体时各自使用不同的临时文件
+    static NEXT_TMP: AtomicUsize = AtomicUsize::new(0);
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(format!(".{}.tmp", NEXT_TMP.fetch_add(1, Ordering::Relaxed)));
+    tokio::fs::write(&tmp, data).await?;
+    if let Err(e) = tokio::fs::rename(&tmp, path).await {
+        let _ = tokio::fs::remove_file(&tmp).await;
+        return Err(e);
+    }
+    Ok(())
+}
+
+impl MessageBlock {
+    /// 下载图片、闪照、语音或短视频，使用注册在 `bot` 中的 [`Downloader`]，没有注册时使用默认的。
+    ///
+    /// 这里不知道文件所在的群，文件请使用 [`MessageBlock::download_in`] 或者 [`GroupMessage::download`]。
+    pub async fn download(&self, bot: &Bot) -> Result<Bytes> {
+        bot_downloader(bot).download(self, bot, None).await
+    }
+
+    /// 跟 [`MessageBlock::download`] 相同，文件从群 `group` 中下载
+    pub async fn download_in(&self, bot: &Bot, group: QQ) -> Result<Bytes> {
+        bot_downloader(bot).download(self, bot, Some(group)).await
+    }
+}
+
+impl GroupMessage {
+    /// 下载这条消息中的图片、语音、文件等，文件从消息所在的群中下载
+    pub async fn download(&self, block: &MessageBlock, bot: &Bot) -> Result<Bytes> {
+        block.download_in(bot, self.sender.group.id).await
+    }
+}
+
+fn bot_downloader(bot: &Bot) -> Downloader {
+    bot.data::<Downloader>().unwrap_or_default()
+}
+
+/// 根据文件头推测内容的 MIME 类型，如 `image/png`，无法识别时返回 `None`
+pub fn sniff_content_type(data: &[u8]) -> Option<&'static str> {
+    let content_type = match data {
+        [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n', ..] => "image/png",
+        [0xff, 0xd8, 0xff, ..] => "image/jpeg",
+        [b'G', b'I', b'F', b'8', b'7' | b'9', b'a', ..] => "image/gif",
+        [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'E', b'B', b'P', ..] => "image/webp",
+        [b'B', b'M', ..] => "image/bmp",
+        [b'#', b'!', b'S', b'I', b'L', b'K', ..]
+        | [0x02, b'#', b'!', b'S', b'I', b'L', b'K', ..] => "audio/silk",
+        [b'#', b'!', b'A', b'M', b'R', ..] => "audio/amr",
+        [b'I', b'D', b'3', ..] | [0xff, 0xfb | 0xf3 | 0xf2, ..] => "audio/mpeg",
+        [_, _, _, _, b'f', b't', b'y', b'p', ..] => "video/mp4",
+        _ => return None,
+    };
+    Some(content_type)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        messages::MessageChain,
+        test_utils::{group_message, mock_bot},
+    };
+    use serde_json::json;
+
+    #[derive(Default)]
+    struct MockClient {
+        requests: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl HttpClient for Arc<MockClient> {
+        async fn get(&self, url: &str) -> Result<Bytes> {
+            self.requests.fetch_add(1, Ordering::SeqCst);
+            Ok(Bytes::from(format!("content of {}", url)))
+        }
+    }
+
+    /// 不需要通过 bot 请求的下载
+    async fn download(downloader: &Downloader, block: &MessageBlock) -> Result<Bytes> {
+        let (bot, _) = mock_bot(|_, _| None);
+        downloader.download(block, &bot, None).await
+    }
+
+    fn received_image() -> MessageBlock {
+        MessageBlock::Image {
+            source: MediaSource::Id("{01E9451B-70ED-EAE3-B37C-101F1EEBF5B5}.mirai".to_string()),
+            url: Some("http://gchat.qpic.cn/a".to_string()),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_download() {
+        let client = Arc::new(MockClient::default());
+        let downloader = Downloader::new(client.clone());
+
+        let data = download(&downloader, &received_image()).await.unwrap();
+        assert_eq!(data, Bytes::from("content of http://gchat.qpic.cn/a"));
+
+        let data = download(&downloader, &MessageBlock::voice_url("http://a.com/a.silk"))
+            .await
+            .unwrap();
+        assert_eq!(data, Bytes::from("content of http://a.com/a.silk"));
+
+        // base64 不需要请求
+        let data = download(&downloader, &MessageBlock::image_bytes("hello"))
+            .await
+            .unwrap();
+        assert_eq!(data, Bytes::from("hello"));
+        assert_eq!(client.requests.load(Ordering::SeqCst), 2);
+
+        assert!(download(&downloader, &MessageBlock::text("hello"))
+            .await
+            .is_err());
+        assert!(download(&downloader, &MessageBlock::image_id("no-url"))
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_download_cache() {
+        let dir = std::env::temp_dir().join(format!("miraie-download-test-{}", std::process::id()));
+        let client = Arc::new(MockClient::default());
+        let downloader = Downloader::new(client.clone()).with_cache(&dir);
+
+        let first = download(&downloader, &received_image()).await.unwrap();
+        let second = download(&downloader, &received_image()).await.unwrap();
+        assert_eq!(first, second);
+        assert_eq!(client.requests.load(Ordering::SeqCst), 1);
+        assert!(dir
+            .join("_01E9451B-70ED-EAE3-B37C-101F1EEBF5B5_.mirai")
+            .exists());
+        // 临时文件已经被重命名
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+
+        // 缓存写入失败时仍然返回下载的内容
+        let blocked = dir.join("blocked");
+        std::fs::write(&blocked, "").unwrap();
+        let data = download(
+            &Downloader::new(client.clone()).with_cache(&blocked),
+            &received_image(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(data, first);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_download_file() {
+        let (bot, _) = mock_bot(|command, content| {
+            assert_eq!(command, "file_info");
+            assert_eq!(content["id"], "/abc");
+            assert_eq!(content["target"], 100);
+            Some(json!({
+                "name": "a.txt",
+                "id": "/abc",
+                "path": "/a.txt",
+                "isFile": true,
+                "isDirectory": false,
+                "downloadInfo": {
+                    "sha1": "",
+                    "md5": "",
+                    "downloadTimes": 0,
+                    "uploaderId": 10,
+                    "uploadTime": 0,
+                    "lastModifyTime": 0,
+                    "url": "http://a.com/a.txt"
+                }
+            }))
+        });
+        let client = Arc::new(MockClient::default());
+        let downloader = Downloader::new(client.clone());
+        let file = MessageBlock::File {
+            id: "/abc".to_string(),
+            name: "a.txt".to_string(),
+            size: 1,
+        };
+
+        let data = downloader
+            .download(&file, &bot, Some(QQ(100)))
+            .await
+            .unwrap();
+        assert_eq!(data, Bytes::from("content of http://a.com/a.txt"));
+        // 不知道文件所在的群时无法下载
+        assert!(downloader.download(&file, &bot, None).await.is_err());
+        assert_eq!(client.requests.load(Ordering::SeqCst), 1);
+
+        // 群消息中的文件从消息所在的群下载
+        let bot = bot.bot_data(downloader);
+        let msg = group_message(100, 10, MessageChain(vec![file.clone()]));
+        let data = msg.download(&file, &bot).await.unwrap();
+        assert_eq!(data, Bytes::from("content of http://a.com/a.txt"));
+        assert!(file.download(&bot).await.is_err());
+        assert_eq!(client.requests.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_sniff_content_type() {
+        assert_eq!(
+            sniff_content_type(b"\x89PNG\r\n\x1a\n\0\0"),
+            Some("image/png")
+        );
+        assert_eq!(sniff_content_type(b"\xff\xd8\xff\xe0"), Some("image/jpeg"));
+        assert_eq!(sniff_content_type(b"GIF89a"), Some("image/gif"));
+        assert_eq!(
+            sniff_content_type(b"RIFF\0\0\0\0WEBPVP8 "),
+            Some("image/webp")
+        );
+        assert_eq!(sniff_content_type(b"\x02#!SILK_V3"), Some("audio/silk"));
+        assert_eq!(sniff_content_type(b"#!AMR\n"), Some("audio/amr"));
+        assert_eq!(sniff_content_type(b"\0\0\0\x20ftypisom"), Some("video/mp4"));
+        assert_eq!(sniff_content_type(b"hello"), None);
+    }
+}
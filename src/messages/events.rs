@@ -87,6 +87,10 @@ impl crate::msg_framework::FromRequest<crate::Bot> for Event {
             _ => None,
         }
     }
+
+    fn may_extract(message: &crate::messages::Message) -> bool {
+        matches!(message, crate::messages::Message::Event(_))
+    }
 }
 
 /// Bot登录成功
@@ -417,6 +421,10 @@ macro_rules! auto_impl {
                     _ => None,
                 }
             }
+
+            fn may_extract(message: &crate::messages::Message) -> bool {
+                matches!(message, crate::messages::Message::Event(Event::$event(_)))
+            }
         }
     };
 }
@@ -3,7 +3,11 @@
 use futures::{future::ready, StreamExt};
 
 use super::{stream::MessageStream, traits::Conversation, MessageChain};
-use crate::{api, bot::QQ, Bot, Result};
+use crate::{
+    api,
+    bot::{SendTarget, QQ},
+    Bot, Result,
+};
 
 /// 私聊消息的发送者
 #[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
@@ -65,11 +69,11 @@ impl Conversation for FriendMessage {
         message: impl Into<MessageChain> + Send + 'static,
         bot: &Bot,
     ) -> Result<api::common::SendMessageResponse> {
-        bot.request(api::send_friend_message::Request {
-            target: self.sender.id,
-            quote: self.message.message_id(),
-            message: message.into(),
-        })
+        bot.send_message(
+            SendTarget::Friend(self.sender.id),
+            self.message.message_id(),
+            message.into(),
+        )
         .await
     }
 
@@ -78,12 +82,8 @@ impl Conversation for FriendMessage {
         message: impl Into<MessageChain> + Send + 'static,
         bot: &Bot,
     ) -> Result<api::common::SendMessageResponse> {
-        bot.request(api::send_friend_message::Request {
-            target: self.sender.id,
-            quote: None,
-            message: message.into(),
-        })
-        .await
+        bot.send_message(SendTarget::Friend(self.sender.id), None, message.into())
+            .await
     }
 }
 
@@ -94,4 +94,8 @@ impl crate::msg_framework::FromRequest<crate::Bot> for FriendMessage {
             _ => None,
         }
     }
+
+    fn may_extract(message: &crate::messages::Message) -> bool {
+        matches!(message, crate::messages::Message::Friend(_))
+    }
 }
@@ -3,7 +3,11 @@ use chrono::{DateTime, Utc};
 use futures::{future::ready, StreamExt};
 
 use super::{stream::MessageStream, MessageChain};
-use crate::{api, bot::QQ, Bot, Result};
+use crate::{
+    api,
+    bot::{SendTarget, QQ},
+    Bot, Result,
+};
 
 /// 一个群里的某个成员
 #[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
@@ -132,11 +136,11 @@ impl super::traits::Conversation for GroupMessage {
         message: impl Into<MessageChain> + Send + 'static,
         bot: &Bot,
     ) -> Result<api::common::SendMessageResponse> {
-        bot.request(api::send_group_message::Request {
-            target: self.sender.group.id,
-            quote: self.message.message_id(),
-            message: message.into(),
-        })
+        bot.send_message(
+            SendTarget::Group(self.sender.group.id),
+            self.message.message_id(),
+            message.into(),
+        )
         .await
     }
 
@@ -145,11 +149,11 @@ impl super::traits::Conversation for GroupMessage {
         message: impl Into<MessageChain> + Send + 'static,
         bot: &Bot,
     ) -> Result<api::common::SendMessageResponse> {
-        bot.request(api::send_group_message::Request {
-            target: self.sender.group.id,
-            quote: None,
-            message: message.into(),
-        })
+        bot.send_message(
+            SendTarget::Group(self.sender.group.id),
+            None,
+            message.into(),
+        )
         .await
     }
 }
@@ -161,4 +165,8 @@ impl crate::msg_framework::FromRequest<crate::Bot> for GroupMessage {
             _ => None,
         }
     }
+
+    fn may_extract(message: &crate::messages::Message) -> bool {
+        matches!(message, crate::messages::Message::Group(_))
+    }
 }
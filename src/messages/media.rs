@@ -0,0 +1,182 @@
+//! 图片、语音、短视频等媒体消息的来源
+
+/// 媒体（图片、闪照、语音、短视频）的来源。
+///
+/// 发送时只会序列化选中的一种；接收时 mirai 可能同时给出多种，此时按照
+/// id > url > path > base64 的优先级选取，跟 mirai 的处理方式一致。
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MediaSource {
+    /// mirai 中的 id，如 imageId、voiceId，可以用来转发收到的媒体
+    ///
+    /// 群图片格式   "{01E9451B-70ED-EAE3-B37C-101F1EEBF5B5}.mirai"
+    /// 好友图片格式 "/f8f1ab55-bf8e-4236-b55e-955848d7069f"
+    Id(String),
+    /// 网络链接
+    Url(String),
+    /// 本地路径，注意是 mirai 所在机器上的路径
+    Path(String),
+    /// 内容的 base64 编码
+    Base64(String),
+}
+
+impl MediaSource {
+    /// 来源的值，即 id、链接、路径或 base64 编码
+    pub fn as_str(&self) -> &str {
+        match self {
+            MediaSource::Id(s)
+            | MediaSource::Url(s)
+            | MediaSource::Path(s)
+            | MediaSource::Base64(s) => s,
+        }
+    }
+}
+
+/// 从 mirai 的 json 中读到的媒体字段
+#[derive(Default)]
+struct RawMedia {
+    id: Option<String>,
+    url: Option<String>,
+    path: Option<String>,
+    base64: Option<String>,
+}
+
+impl RawMedia {
+    fn source(self) -> Option<MediaSource> {
+        // 旧版本发送 url 图片时会带上空的 imageId，这里把空字符串当做没有
+        let non_empty = |s: Option<String>| s.filter(|s| !s.is_empty());
+        non_empty(self.id)
+            .map(MediaSource::Id)
+            .or_else(|| non_empty(self.url).map(MediaSource::Url))
+            .or_else(|| non_empty(self.path).map(MediaSource::Path))
+            .or_else(|| non_empty(self.base64).map(MediaSource::Base64))
+    }
+
+    /// 只有来源是 id 时，url 才是额外的下载链接
+    fn received_url(self) -> Option<String> {
+        match (&self.id, self.url) {
+            (Some(id), Some(url)) if !id.is_empty() && !url.is_empty() => Some(url),
+            _ => None,
+        }
+    }
+}
+
+mod de {
+    use super::RawMedia;
+    use serde::de::{self, IgnoredAny, MapAccess, Visitor};
+    use std::fmt;
+
+    pub(super) struct RawMediaVisitor {
+        pub(super) id_field: &'static str,
+        /// 链接的字段名，短视频是 `videoUrl`，同时兼容 `url`
+        pub(super) url_fields: &'static [&'static str],
+    }
+
+    impl<'de> Visitor<'de> for RawMediaVisitor {
+        type Value = RawMedia;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(
+                f,
+                "a map with `{}`, `url`, `path` or `base64`",
+                self.id_field
+            )
+        }
+
+        fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<RawMedia, A::Error> {
+            let mut raw = RawMedia::default();
+            while let Some(key) = map.next_key::<String>()? {
+                let field = match key.as_str() {
+                    k if k == self.id_field => &mut raw.id,
+                    k if self.url_fields.contains(&k) => &mut raw.url,
+                    "path" => &mut raw.path,
+                    "base64" => &mut raw.base64,
+                    _ => {
+                        map.next_value::<IgnoredAny>()?;
+                        continue;
+                    }
+                };
+                *field = map.next_value()?;
+            }
+            Ok(raw)
+        }
+    }
+
+    pub(super) fn missing<E: de::Error>(id_field: &str) -> E {
+        E::custom(format!(
+            "media requires one of `{}`, `url`, `path` or `base64`",
+            id_field
+        ))
+    }
+}
+
+/// 为每种媒体生成 `#[serde(flatten, with = "...")]` 使用的模块
+macro_rules! media_serde {
+    ($($name:ident => $id_field:literal, $url_fields:expr;)*) => {
+        $(
+            pub(crate) mod $name {
+                /// 来源，只序列化选中的字段
+                pub(crate) mod source {
+                    use super::super::{de, MediaSource};
+                    use serde::{ser::SerializeMap, Deserializer, Serializer};
+
+                    pub(crate) fn serialize<S: Serializer>(
+                        source: &MediaSource,
+                        serializer: S,
+                    ) -> Result<S::Ok, S::Error> {
+                        let key = match source {
+                            MediaSource::Id(_) => $id_field,
+                            MediaSource::Url(_) => "url",
+                            MediaSource::Path(_) => "path",
+                            MediaSource::Base64(_) => "base64",
+                        };
+                        let mut map = serializer.serialize_map(Some(1))?;
+                        map.serialize_entry(key, source.as_str())?;
+                        map.end()
+                    }
+
+                    pub(crate) fn deserialize<'de, D: Deserializer<'de>>(
+                        deserializer: D,
+                    ) -> Result<MediaSource, D::Error> {
+                        deserializer
+                            .deserialize_map(de::RawMediaVisitor {
+                                id_field: $id_field,
+                                url_fields: $url_fields,
+                            })?
+                            .source()
+                            .ok_or_else(|| de::missing($id_field))
+                    }
+                }
+
+                /// 接收到的下载链接，发送时不会序列化
+                pub(crate) mod url {
+                    use super::super::de;
+                    use serde::{ser::SerializeMap, Deserializer, Serializer};
+
+                    pub(crate) fn serialize<S: Serializer>(
+                        _url: &Option<String>,
+                        serializer: S,
+                    ) -> Result<S::Ok, S::Error> {
+                        serializer.serialize_map(Some(0))?.end()
+                    }
+
+                    pub(crate) fn deserialize<'de, D: Deserializer<'de>>(
+                        deserializer: D,
+                    ) -> Result<Option<String>, D::Error> {
+                        Ok(deserializer
+                            .deserialize_map(de::RawMediaVisitor {
+                                id_field: $id_field,
+                                url_fields: $url_fields,
+                            })?
+                            .received_url())
+                    }
+                }
+            }
+        )*
+    };
+}
+
+media_serde! {
+    image => "imageId", &["url"];
+    voice => "voiceId", &["url"];
+    video => "videoId", &["videoUrl", "url"];
+}
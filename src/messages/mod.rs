@@ -1,9 +1,17 @@
 //! mirai 传回的消息，群聊、私聊、事件等
+pub mod blocks;
 mod chain;
+mod chain_mirai_code;
+mod chain_query;
+mod chain_split;
 mod chain_xml;
+#[cfg(feature = "download")]
+pub mod download;
 pub mod events;
 pub mod friend;
 pub mod group;
+mod media;
+mod session;
 mod stranger;
 mod stream;
 mod temp;
@@ -11,13 +19,17 @@ mod traits;
 
 use std::convert::TryFrom;
 
-pub use chain::{MessageBlock, MessageChain};
+pub use chain::{ForwardNode, MessageBlock, MessageChain, MusicKind, PokeKind};
 pub use events::Event;
 pub use friend::FriendMessage;
 pub use group::GroupMessage;
+pub use media::MediaSource;
+pub(crate) use session::ActiveSessions;
+pub use session::{Session, SessionMessage};
 use serde::Deserialize;
 use serde_json::Value;
 pub use stranger::StrangerMessage;
+pub use stream::MessageStream;
 pub use temp::TempMessage;
 pub use traits::Conversation;
 
@@ -29,6 +41,21 @@ pub enum Message {
     Temp(TempMessage),
     Stranger(StrangerMessage),
     Event(Event),
+    /// 定时任务触发，只会交给 [`Bot::schedule`](crate::Bot::schedule) 注册的任务，不会从 mirai 收到
+    Scheduled(crate::bot::ScheduledEvent),
+}
+
+impl Message {
+    /// 消息的内容，事件没有消息内容，返回 `None`
+    pub fn message_chain(&self) -> Option<&MessageChain> {
+        match self {
+            Message::Friend(f) => Some(&f.message),
+            Message::Group(g) => Some(&g.message),
+            Message::Temp(t) => Some(&t.message),
+            Message::Stranger(s) => Some(&s.message),
+            Message::Event(_) | Message::Scheduled(_) => None,
+        }
+    }
 }
 
 impl crate::msg_framework::FromRequest<crate::Bot> for Message {
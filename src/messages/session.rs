@@ -0,0 +1,514 @@
+use futures::StreamExt;
+use parking_lot::Mutex;
+use std::{
+    collections::{HashMap, VecDeque},
+    future::Future,
+    sync::Arc,
+    time::Duration,
+};
+
+use super::{
+    stream::MessageStream, Conversation, FriendMessage, GroupMessage, Message, MessageChain,
+};
+use crate::{
+    bot::{FromArg, QQ},
+    Bot, Error, Result,
+};
+
+/// 记住最近被会话读取的消息的数量
+const MAX_CONSUMED: usize = 256;
+
+/// 可以开启 [`Session`] 的消息，即 [`GroupMessage`] 和 [`FriendMessage`]
+pub trait SessionMessage: Conversation + Clone + Send + Sync + 'static {
+    /// 消息所在的群（私聊为 `None`）以及发送者
+    #[doc(hidden)]
+    fn session_key(&self) -> (Option<QQ>, QQ);
+}
+
+impl SessionMessage for GroupMessage {
+    fn session_key(&self) -> (Option<QQ>, QQ) {
+        (Some(self.sender.group.id), self.sender.id)
+    }
+}
+
+impl SessionMessage for FriendMessage {
+    fn session_key(&self) -> (Option<QQ>, QQ) {
+        (None, self.sender.id)
+    }
+}
+
+/// 多轮对话的会话，依次向用户提问并等待回答，回答不合法时会要求重新输入，
+/// 用户回复【取消】时结束会话。
+///
+/// 会话存在期间，用户在这个聊天中的消息只会被会话读取，不会再触发指令以及通过
+/// [`App::handler_priority`](crate::App::handler_priority)、[`App::fallback`](crate::App::fallback)
+/// 注册的 handler；通过 [`App::handler`](crate::App::handler) 注册的 handler 仍然会收到这些消息。
+///
+/// 每一步的结果就是普通的变量，可以直接用来决定下一步问什么。
+///
+/// # Example
+/// ```no_run
+/// # use miraie::prelude::*;
+/// # tokio_test::block_on(async {
+/// # let (bot, _) = Bot::new("127.0.0.1", "secret", QQ(123456)).await.unwrap();
+/// bot.command("注册", |msg: GroupMessage, bot: Bot| async move {
+///     let mut session = Session::new(&bot, &msg);
+///     let name = session.ask_text("你叫什么名字？").await?;
+///     let age: u32 = session.ask("你今年多大了？").await?;
+///     if age < 18 {
+///         let guardian = session.ask_text("请输入监护人的名字").await?;
+///         // ...
+///     }
+///     if !session.confirm(format!("{}，{} 岁，确认吗？", name, age)).await? {
+///         return Ok("已放弃注册");
+///     }
+///     Ok::<_, miraie::Error>("注册成功")
+/// });
+/// # });
+/// ```
+pub struct Session<C: SessionMessage> {
+    bot: Bot,
+    /// 用户最近的一条消息，会话的回复会引用它
+    last: C,
+    messages: MessageStream<C>,
+    timeout: Duration,
+    max_retries: usize,
+    cancel_words: Vec<String>,
+    retry_message: String,
+    cancel_message: String,
+    timeout_message: String,
+    _guard: SessionGuard,
+}
+
+impl<C: SessionMessage> Session<C> {
+    /// 开启一个会话，之后这个用户在这个聊天中的消息都会被会话读取，直到会话被丢弃
+    pub fn new(bot: &Bot, message: &C) -> Self {
+        Self {
+            bot: bot.clone(),
+            last: message.clone(),
+            messages: message.followed_sender_messages(bot),
+            timeout: Duration::from_secs(60),
+            max_retries: 3,
+            cancel_words: vec!["取消".to_string()],
+            retry_message: "输入有误，请重新输入".to_string(),
+            cancel_message: "已取消".to_string(),
+            timeout_message: "等待超时，已取消".to_string(),
+            _guard: bot.sessions.enter(message.session_key()),
+        }
+    }
+
+    /// 等待每个回答的超时时间，默认 60 秒
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// 每个问题最多可以重新输入的次数，默认 3 次
+    pub fn max_retries(mut self, max_retries: usize) -> Self {
+        self.max_retries = max_retries;
+        self
+    }
+
+    /// 用户回复这些词时取消会话，默认为【取消】
+    pub fn cancel_words<S: Into<String>>(mut self, words: impl IntoIterator<Item = S>) -> Self {
+        self.cancel_words = words.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// 回答不合法时回复的消息
+    pub fn retry_message(mut self, message: impl Into<String>) -> Self {
+        self.retry_message = message.into();
+        self
+    }
+
+    /// 用户取消或者输入错误次数过多时回复的消息
+    pub fn cancel_message(mut self, message: impl Into<String>) -> Self {
+        self.cancel_message = message.into();
+        self
+    }
+
+    /// 等待回答超时时回复的消息
+    pub fn timeout_message(mut self, message: impl Into<String>) -> Self {
+        self.timeout_message = message.into();
+        self
+    }
+
+    /// 用户最近的一条消息
+    pub fn last(&self) -> &C {
+        &self.last
+    }
+
+    /// 回复用户最近的一条消息
+    pub fn reply(
+        &self,
+        message: impl Into<MessageChain> + Send + 'static,
+    ) -> impl Future<Output = Result<()>> + Send + 'static {
+        // 不借用会话，会话中的消息流不是 Sync 的
+        let last = self.last.clone();
+        let bot = self.bot.clone();
+        async move {
+            last.reply(message, &bot).await?;
+            Ok(())
+        }
+    }
+
+    /// 等待用户的下一条消息。
+    ///
+    /// 超时返回 [`Error::ResponseTimeout`]，用户取消返回 [`Error::SessionCancelled`]，
+    /// 都会先回复对应的消息。
+    pub async fn next(&mut self) -> Result<C> {
+        let next = tokio::time::timeout(self.timeout, self.messages.next()).await;
+        let message = match next {
+            Ok(Some(message)) => message,
+            Ok(None) => return Err(Error::ConnectionClosed),
+            Err(_) => {
+                self.reply(self.timeout_message.clone()).await?;
+                return Err(Error::ResponseTimeout);
+            }
+        };
+        self.bot.sessions.consume(message.as_message().message_id());
+        self.last = message;
+        let text = self.last.as_message().plain_text();
+        if self.cancel_words.iter().any(|word| word == text.trim()) {
+            self.reply(self.cancel_message.clone()).await?;
+            return Err(Error::SessionCancelled);
+        }
+        Ok(self.last.clone())
+    }
+
+    /// 发送 `prompt` 并等待回答，`parse` 返回 `None` 时要求用户重新输入
+    pub async fn prompt_with<T>(
+        &mut self,
+        prompt: impl Into<MessageChain> + Send + 'static,
+        mut parse: impl FnMut(&C) -> Option<T> + Send,
+    ) -> Result<T> {
+        self.reply(prompt).await?;
+        for retry in 0.. {
+            let message = self.next().await?;
+            if let Some(value) = parse(&message) {
+                return Ok(value);
+            }
+            if retry >= self.max_retries {
+                break;
+            }
+            self.reply(self.retry_message.clone()).await?;
+        }
+        self.reply(self.cancel_message.clone()).await?;
+        Err(Error::TooManyRetries)
+    }
+
+    /// 提问并把回答解析为 `T`，回答需要是一个参数，如一个数字、一个 at，解析方式见 [`FromArg`]
+    pub async fn ask<T: FromArg>(
+        &mut self,
+        prompt: impl Into<MessageChain> + Send + 'static,
+    ) -> Result<T> {
+        self.prompt_with(prompt, |message| {
+            match message.as_message().split_whitespace().as_slice() {
+                [token] => T::from_arg(token),
+                _ => None,
+            }
+        })
+        .await
+    }
+
+    /// 提问并返回回答的文字，不能为空
+    pub async fn ask_text(
+        &mut self,
+        prompt: impl Into<MessageChain> + Send + 'static,
+    ) -> Result<String> {
+        self.prompt_with(prompt, |message| {
+            let text = message.as_message().plain_text().trim().to_string();
+            (!text.is_empty()).then_some(text)
+        })
+        .await
+    }
+
+    /// 提问是否确认，回答的匹配方式见 [`MessageChain::as_confirm`]。
+    /// 取消词（默认为【取消】）会取消会话，而不是回答“否”。
+    pub async fn confirm(
+        &mut self,
+        prompt: impl Into<MessageChain> + Send + 'static,
+    ) -> Result<bool> {
+        self.prompt_with(prompt, |message| message.as_message().as_confirm())
+            .await
+    }
+
+    /// 列出选项让用户选择，用户可以回复序号（从 1 开始）或者选项本身，返回选项的下标（从 0 开始）
+    pub async fn choose(&mut self, prompt: impl Into<String>, options: &[&str]) -> Result<usize> {
+        let mut text = prompt.into();
+        for (i, option) in options.iter().enumerate() {
+            text.push_str(&format!("\n{}. {}", i + 1, option));
+        }
+        let options: Vec<String> = options.iter().map(|s| s.to_string()).collect();
+        self.prompt_with(text, move |message| {
+            let answer = message.as_message().plain_text();
+            let answer = answer.trim();
+            match answer.parse::<usize>() {
+                Ok(i) if (1..=options.len()).contains(&i) => Some(i - 1),
+                _ => options.iter().position(|option| option == answer),
+            }
+        })
+        .await
+    }
+}
+
+/// 正在进行的会话，用来阻止会话中的消息被分发给指令
+#[derive(Clone, Default)]
+pub(crate) struct ActiveSessions(Arc<Mutex<SessionsInner>>);
+
+#[derive(Default)]
+struct SessionsInner {
+    /// 会话的群以及用户，以及这个用户正在进行的会话数
+    active: HashMap<(Option<QQ>, QQ), usize>,
+    /// 最近被会话读取的消息，分发可能会晚于会话读取消息，如开启了串行模式时
+    consumed: VecDeque<i64>,
+}
+
+impl ActiveSessions {
+    fn enter(&self, key: (Option<QQ>, QQ)) -> SessionGuard {
+        *self.0.lock().active.entry(key).or_default() += 1;
+        SessionGuard {
+            sessions: self.clone(),
+            key,
+        }
+    }
+
+    fn consume(&self, message_id: Option<i64>) {
+        if let Some(id) = message_id {
+            let mut inner = self.0.lock();
+            if inner.consumed.len() >= MAX_CONSUMED {
+                inner.consumed.pop_front();
+            }
+            inner.consumed.push_back(id);
+        }
+    }
+
+    /// 消息是否属于某个会话
+    pub fn owns(&self, message: &Message) -> bool {
+        let (key, chain) = match message {
+            Message::Group(msg) => (msg.session_key(), &msg.message),
+            Message::Friend(msg) => (msg.session_key(), &msg.message),
+            _ => return false,
+        };
+        let inner = self.0.lock();
+        inner.active.contains_key(&key)
+            || chain
+                .message_id()
+                .is_some_and(|id| inner.consumed.contains(&id))
+    }
+}
+
+/// 会话结束时离开 [`ActiveSessions`]
+struct SessionGuard {
+    sessions: ActiveSessions,
+    key: (Option<QQ>, QQ),
+}
+
+impl Drop for SessionGuard {
+    fn drop(&mut self) {
+        let mut inner = self.sessions.0.lock();
+        if let Some(count) = inner.active.get_mut(&self.key) {
+            *count -= 1;
+            if *count == 0 {
+                inner.active.remove(&self.key);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        messages::MessageBlock,
+        test_utils::{friend_message, group_message, mock_bot},
+        App,
+    };
+
+    /// 带有 id 的群消息
+    fn with_id(group: u64, sender: u64, id: i64) -> GroupMessage {
+        let source = MessageBlock::Source {
+            id,
+            time: chrono::DateTime::from_timestamp(123, 0).unwrap(),
+        };
+        group_message(group, sender, MessageChain(vec![source]).text("hi"))
+    }
+
+    #[test]
+    fn test_active_sessions() {
+        let sessions = ActiveSessions::default();
+        let msg = with_id(100, 10, 1);
+        let first = sessions.enter(msg.session_key());
+        let second = sessions.enter(msg.session_key());
+        assert!(sessions.owns(&Message::Group(msg.clone())));
+        // 同一个人在其他群，以及同一个群里的其他人不受影响
+        assert!(!sessions.owns(&Message::Group(with_id(200, 10, 2))));
+        assert!(!sessions.owns(&Message::Group(with_id(100, 11, 3))));
+        assert!(!sessions.owns(&Message::Friend(friend_message(10, "hi"))));
+
+        drop(first);
+        assert!(sessions.owns(&Message::Group(msg.clone())));
+        drop(second);
+        assert!(!sessions.owns(&Message::Group(msg.clone())));
+
+        // 被会话读取过的消息即使会话结束了也不会再被分发
+        sessions.consume(Some(1));
+        assert!(sessions.owns(&Message::Group(msg)));
+    }
+
+    /// 收到带有 id 的群消息，等待处理完
+    async fn receive(bot: &Bot, group: u64, sender: u64, id: i64, text: &str) {
+        let mut message = with_id(group, sender, id);
+        message.message = MessageChain(message.message.0[..1].to_vec()).text(text);
+        bot.event_bus().send(Message::Group(message)).unwrap();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+
+    #[tokio::test]
+    async fn test_serial_session() {
+        let (bot, sent) = mock_bot(|_, _| None);
+        let bot = bot.serial_per_conversation().max_concurrency(2);
+        bot.clone()
+            .command("注册", |msg: GroupMessage, bot: Bot| async move {
+                let mut session = Session::new(&bot, &msg);
+                let name = session.ask_text("你叫什么名字？").await?;
+                Ok::<_, Error>(format!("你好，{}", name))
+            })
+            .command("在吗", |_: GroupMessage| async { "在" });
+
+        receive(&bot, 100, 10, 1, "注册").await;
+        assert_eq!(sent.take(), vec!["你叫什么名字？"]);
+        // 等待回答时其他群的消息，以及同一个群里其他人的消息照常处理
+        receive(&bot, 200, 10, 2, "在吗").await;
+        assert_eq!(sent.take(), vec!["在"]);
+        receive(&bot, 100, 11, 3, "在吗").await;
+        assert_eq!(sent.take(), vec!["在"]);
+        // 回答只交给会话，不会触发指令
+        receive(&bot, 100, 10, 4, "在吗").await;
+        assert_eq!(sent.take(), vec!["你好，在吗"]);
+        receive(&bot, 100, 10, 5, "在吗").await;
+        assert_eq!(sent.take(), vec!["在"]);
+
+        let metrics = bot.scheduler().metrics();
+        assert_eq!((metrics.queued, metrics.running), (0, 0));
+    }
+
+    /// 会话的结果，出错时为错误的名字
+    fn outcome<T: std::fmt::Display>(result: Result<T>) -> String {
+        match result {
+            Ok(value) => value.to_string(),
+            Err(Error::TooManyRetries) => "重试次数过多".to_string(),
+            Err(Error::SessionCancelled) => "被取消".to_string(),
+            Err(Error::ResponseTimeout) => "超时".to_string(),
+            Err(e) => e.to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_prompt_retries() {
+        let (bot, sent) = mock_bot(|_, _| None);
+        bot.clone()
+            .command("年龄", |msg: GroupMessage, bot: Bot| async move {
+                let mut session = Session::new(&bot, &msg).max_retries(1);
+                outcome(session.ask::<u32>("你几岁？").await)
+            });
+
+        receive(&bot, 100, 10, 1, "年龄").await;
+        assert_eq!(sent.take(), vec!["你几岁？"]);
+        receive(&bot, 100, 10, 2, "十八").await;
+        assert_eq!(sent.take(), vec!["输入有误，请重新输入"]);
+        receive(&bot, 100, 10, 3, "18").await;
+        assert_eq!(sent.take(), vec!["18"]);
+
+        // 重新输入的次数用完后取消
+        receive(&bot, 100, 10, 4, "年龄").await;
+        receive(&bot, 100, 10, 5, "十八").await;
+        receive(&bot, 100, 10, 6, "十九").await;
+        assert_eq!(
+            sent.take(),
+            vec!["你几岁？", "输入有误，请重新输入", "已取消", "重试次数过多"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_cancel_words() {
+        let (bot, sent) = mock_bot(|_, _| None);
+        bot.clone()
+            .command("改名", |msg: GroupMessage, bot: Bot| async move {
+                let mut session = Session::new(&bot, &msg)
+                    .cancel_words(["算了"])
+                    .cancel_message("好的");
+                outcome(session.ask::<u32>("新的编号？").await)
+            });
+
+        receive(&bot, 100, 10, 1, "改名").await;
+        // 默认的取消词被替换后只是不合法的回答
+        receive(&bot, 100, 10, 2, "取消").await;
+        receive(&bot, 100, 10, 3, " 算了 ").await;
+        assert_eq!(
+            sent.take(),
+            vec!["新的编号？", "输入有误，请重新输入", "好的", "被取消"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_timeout() {
+        let (bot, sent) = mock_bot(|_, _| None);
+        bot.clone()
+            .command("签到", |msg: FriendMessage, bot: Bot| async move {
+                let mut session = Session::new(&bot, &msg).timeout(Duration::from_millis(50));
+                outcome(session.ask_text("今天心情如何？").await)
+            });
+
+        bot.event_bus()
+            .send(Message::Friend(friend_message(10, "签到")))
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(
+            sent.take(),
+            vec!["今天心情如何？", "等待超时，已取消", "超时"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_session_blocks_dispatch() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let (bot, sent) = mock_bot(|_, _| None);
+        let seen = Arc::new(AtomicUsize::new(0));
+        let fallbacks = Arc::new(AtomicUsize::new(0));
+        let counter = seen.clone();
+        bot.clone().handler(move |_: GroupMessage| {
+            counter.fetch_add(1, Ordering::Relaxed);
+            async {}
+        });
+        let counter = fallbacks.clone();
+        bot.clone().fallback(move |_: GroupMessage| {
+            counter.fetch_add(1, Ordering::Relaxed);
+            async {}
+        });
+        bot.clone()
+            .command("投票", |msg: GroupMessage, bot: Bot| async move {
+                let mut session = Session::new(&bot, &msg);
+                outcome(session.confirm("确定要投票吗？").await)
+            })
+            .command("在吗", |_: GroupMessage| async { "在" });
+
+        receive(&bot, 100, 10, 1, "投票").await;
+        receive(&bot, 100, 10, 2, "在吗").await;
+        receive(&bot, 100, 10, 3, "是").await;
+        assert_eq!(
+            sent.take(),
+            vec!["确定要投票吗？", "输入有误，请重新输入", "true"]
+        );
+        // 会话中的消息不会触发指令和 fallback，普通的 handler 仍然收到所有消息
+        assert_eq!(seen.load(Ordering::Relaxed), 3);
+        assert_eq!(fallbacks.load(Ordering::Relaxed), 0);
+
+        // 会话结束后恢复分发
+        receive(&bot, 100, 10, 4, "在吗").await;
+        receive(&bot, 100, 10, 5, "你好").await;
+        assert_eq!(sent.take(), vec!["在"]);
+        assert_eq!(fallbacks.load(Ordering::Relaxed), 1);
+    }
+}
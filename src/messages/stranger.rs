@@ -15,4 +15,8 @@ impl crate::msg_framework::FromRequest<crate::Bot> for StrangerMessage {
             _ => None,
         }
     }
+
+    fn may_extract(message: &crate::messages::Message) -> bool {
+        matches!(message, crate::messages::Message::Stranger(_))
+    }
 }
@@ -16,4 +16,8 @@ impl crate::msg_framework::FromRequest<crate::Bot> for TempMessage {
             _ => None,
         }
     }
+
+    fn may_extract(message: &crate::messages::Message) -> bool {
+        matches!(message, crate::messages::Message::Temp(_))
+    }
 }
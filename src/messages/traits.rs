@@ -1,6 +1,6 @@
 use super::{stream::MessageStream, MessageChain};
 use crate::{api, bot::QQ, Bot, Error, Result};
-use futures::StreamExt;
+use futures::{future::ready, StreamExt};
 use std::time::{Duration, Instant};
 
 /// 消息流，实现了消息流的类型（群聊消息和私聊消息）可以：
@@ -27,8 +27,19 @@ pub trait Conversation: Sized {
     /// 获取这条消息发送者在本聊天中发送的后续消息
     fn followed_sender_messages(&self, bot: &Bot) -> MessageStream<Self>;
 
-    // TODO
-    // fn followed_quote_messages(&self, bot: &Bot) -> MessageStream<Self>;
+    /// 获取本聊天中引用回复这条消息的后续消息。这条消息没有 message id 时不会有任何消息。
+    ///
+    /// 主动发送的消息见 [`SendMessageResponse::replies`](api::common::SendMessageResponse::replies)。
+    fn followed_quote_messages(&self, bot: &Bot) -> MessageStream<Self>
+    where
+        Self: Send + 'static,
+    {
+        let id = self.as_message().message_id();
+        MessageStream::new(
+            self.followed_group_message(bot)
+                .filter(move |msg| ready(id.is_some() && msg.as_message().quote_id() == id)),
+        )
+    }
 
     /// 回复这条消息，产生“引用”。
     async fn reply(
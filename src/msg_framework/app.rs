@@ -1,10 +1,19 @@
+use futures::{future::BoxFuture, FutureExt};
 use std::{
     fmt::{Debug, Display},
     future::Future,
+    panic::AssertUnwindSafe,
+    sync::Arc,
 };
 use tokio::sync::broadcast;
 
-use super::{func::Func, FromRequest, Request};
+use super::{
+    func::Func,
+    middleware::{run_middlewares, Endpoint, Middleware, Middlewares, Outcome},
+    scheduler::{acquire, SerialQueue},
+    Dispatcher, FromRequest, HandlerHandle, Request, Scheduler,
+};
+use std::hash::Hash;
 
 /// 描述回调返回值的处理方式
 #[async_trait]
@@ -14,6 +23,11 @@ where
     A: App,
 {
     async fn on_return(self, request: Request<A>);
+
+    /// 返回值对应的处理结果，中间件可以通过 [`Next::run`](super::Next::run) 观察到
+    fn outcome(&self) -> Outcome {
+        Outcome::Done
+    }
 }
 #[async_trait]
 impl<A> Return<A> for ()
@@ -37,11 +51,77 @@ where
             debug!("handler backtrace: {:?}", e);
         }
     }
+
+    fn outcome(&self) -> Outcome {
+        match self {
+            Ok(()) => Outcome::Done,
+            Err(e) => Outcome::Failed(e.to_string()),
+        }
+    }
+}
+
+/// 直接返回处理结果，通常用于返回 [`Outcome::Handled`] 消费消息
+#[async_trait]
+impl<A> Return<A> for Outcome
+where
+    A: App,
+{
+    async fn on_return(self, _request: Request<A>) {}
+
+    fn outcome(&self) -> Outcome {
+        self.clone()
+    }
+}
+
+/// 处理完成后消费消息，更低优先级的 handler、指令以及 fallback handler 都不会再收到这条消息。
+/// 见 [`App::handler_priority`]。
+///
+/// 内部的返回值会照常处理，如 `Handled("收到")` 会回复【收到】。内部的返回值失败时不会消费消息。
+pub struct Handled<T = ()>(pub T);
+
+/// 处理完成后继续传递消息，和直接返回内部的值相同
+pub struct Continue<T = ()>(pub T);
+
+#[async_trait]
+impl<A, T> Return<A> for Handled<T>
+where
+    A: App,
+    T: Return<A>,
+{
+    async fn on_return(self, request: Request<A>) {
+        self.0.on_return(request).await
+    }
+
+    fn outcome(&self) -> Outcome {
+        match self.0.outcome() {
+            Outcome::Failed(e) => Outcome::Failed(e),
+            _ => Outcome::Handled,
+        }
+    }
+}
+
+#[async_trait]
+impl<A, T> Return<A> for Continue<T>
+where
+    A: App,
+    T: Return<A>,
+{
+    async fn on_return(self, request: Request<A>) {
+        self.0.on_return(request).await
+    }
+
+    fn outcome(&self) -> Outcome {
+        match self.0.outcome() {
+            Outcome::Handled => Outcome::Done,
+            outcome => outcome,
+        }
+    }
 }
 
 /// 对一个 App 行为的抽象
 ///
-/// App 需要提供一个 broadcast 类型的通信信道。
+/// App 需要提供一个 broadcast 类型的通信信道，以及在所有克隆之间共享的
+/// [`Dispatcher`]、[`Middlewares`] 和 [`Scheduler`]。
 ///
 pub trait App: Sized + Clone + Send + 'static {
     /// App 内广播的消息类型。对于 [`Bot`](crate::Bot) 来说，传递的是 [`Message`](crate::prelude::Message)。
@@ -50,53 +130,283 @@ pub trait App: Sized + Clone + Send + 'static {
     /// 获取 App 内传递的消息广播通道。
     fn event_bus(&self) -> broadcast::Sender<Self::Message>;
 
-    /// 注册一个新的消息广播处理 handler。注册之后将会永远存在，无法取消订阅。
+    /// 注册一个新的消息广播处理 handler。返回的 [`HandlerHandle`] 可以用来暂停或者取消注册 handler。
     ///
     /// # 参数
     /// - `f`: 一个回调接口，其入参均实现了 [`FromRequest`](`crate::msg_framework::FromRequest`)，
-    /// 如 [`Message`](crate::prelude::Message), [`FriendMessage`](crate::prelude::FriendMessage),
-    /// [`Bot`](crate::Bot) 等。
-    /// 其返回值应该是空（`()`）或 `Result<()>` 或 `Return<T>` 等，其中 T 可以被转换为 [`MessageChain`](crate::prelude::MessageChain`)。
-    fn handler<F, I, Fut>(self, f: F) -> Self
+    ///   如 [`Message`](crate::prelude::Message), [`FriendMessage`](crate::prelude::FriendMessage),
+    ///   [`Bot`](crate::Bot) 等。
+    ///   其返回值应该是空（`()`）或 `Result<()>` 或 `Return<T>` 等，其中 T 可以被转换为 [`MessageChain`](crate::prelude::MessageChain`)。
+    ///
+    /// 通过 `handler` 注册的 handler 会并发地收到所有的消息，不参与优先级，也无法被其他 handler 拦截。
+    /// 需要控制顺序时使用 [`App::handler_priority`]。
+    fn handler<F, I, Fut>(self, f: F) -> HandlerHandle<Self>
     where
         F: Func<I, Fut>,
         I: FromRequest<Self> + Send + 'static,
         Fut: Future + Send + 'static,
         Fut::Output: Return<Self>,
     {
-        let receiver = self.event_bus().subscribe();
-        let app = self.clone();
-        let task = async move {
-            let mut receiver = receiver;
-            loop {
-                let recv = receiver.recv().await;
-                match recv {
-                    Ok(message) => {
-                        // convert message to request
-                        let request = Request::<Self> {
-                            // carries message & context
-                            message,
-                            // carries data, e.g., database connections, etc.
-                            app: app.clone(),
-                        };
-                        if let Some(input) = I::from_request(&request) {
-                            let fut = (f).call(input);
-                            let fut = async move {
-                                fut.await.on_return(request).await;
-                            };
-                            tokio::spawn(fut);
-                        };
-                    }
-                    Err(broadcast::error::RecvError::Lagged(i)) => {
-                        warn!("broadcast lagged {} messages.", i);
-                    }
-                    Err(broadcast::error::RecvError::Closed) => {
-                        break;
+        subscribe(self, f)
+    }
+
+    /// 注册一个带有优先级的 handler，优先级高的先执行，返回 [`Handled`] 可以阻止更低优先级的 handler 收到消息。
+    /// 对于 [`Bot`](crate::Bot) 来说，关键词指令的优先级是 0，匹配到指令时会消费消息。
+    ///
+    /// 分发的规则见 [`Dispatcher`]。
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use miraie::prelude::*;
+    /// use miraie::msg_framework::Outcome;
+    /// # tokio_test::block_on(async {
+    /// # let (bot, _) = Bot::new("127.0.0.1", "secret", QQ(123456)).await.unwrap();
+    /// // 在指令之前处理所有的群消息，含有屏蔽词的消息不会触发任何指令
+    /// bot.handler_priority(10, |msg: GroupMessage| async move {
+    ///     if msg.message.plain_text().contains("屏蔽词") {
+    ///         Outcome::Handled
+    ///     } else {
+    ///         Outcome::Done
+    ///     }
+    /// });
+    /// # });
+    /// ```
+    fn handler_priority<F, I, Fut>(self, priority: i32, f: F) -> HandlerHandle<Self>
+    where
+        F: Func<I, Fut>,
+        I: FromRequest<Self> + Send + 'static,
+        Fut: Future + Send + 'static,
+        Fut::Output: Return<Self>,
+    {
+        self.dispatcher().register(self, Some(priority), f, true)
+    }
+
+    /// 注册一个 fallback handler，只有消息没有被任何带有优先级的 handler 或者指令消费时才会执行，
+    /// 通常用来回复【未知指令】。
+    fn fallback<F, I, Fut>(self, f: F) -> HandlerHandle<Self>
+    where
+        F: Func<I, Fut>,
+        I: FromRequest<Self> + Send + 'static,
+        Fut: Future + Send + 'static,
+        Fut::Output: Return<Self>,
+    {
+        self.dispatcher().register(self, None, f, true)
+    }
+
+    /// App 上按照优先级分发消息的 [`Dispatcher`]。
+    ///
+    /// App 应该在内部保存一个 [`Dispatcher`] 并在这里返回它的克隆，每次返回新的 [`Dispatcher`]
+    /// 会让 [`App::handler_priority`] 和 [`App::fallback`] 注册的 handler 收不到消息。
+    fn dispatcher(&self) -> Dispatcher<Self>;
+
+    /// App 上注册的全局中间件。
+    ///
+    /// App 应该在内部保存一个 [`Middlewares`] 并在这里返回它的克隆，[`App::middleware`] 会注册到其中。
+    fn middlewares(&self) -> Middlewares<Self>;
+
+    /// 注册一个全局中间件，对之后所有 handler 的调用都生效，包括在此之前注册的 handler。
+    ///
+    /// 中间件的执行顺序见 [`Middleware`]。
+    fn middleware(self, middleware: impl Middleware<Self>) -> Self {
+        self.middlewares().push(middleware);
+        self
+    }
+
+    /// App 上的全局调度设置。
+    ///
+    /// App 应该在内部保存一个 [`Scheduler`] 并在这里返回它的克隆，
+    /// [`App::max_concurrency`] 和 [`App::serial_by`] 会修改其中的设置。
+    fn scheduler(&self) -> Scheduler<Self>;
+
+    /// 设置所有 handler 和指令同时处理的消息数的上限，超过上限的消息会排队等待，
+    /// 避免消息过多时同时发起大量的请求。排队的情况可以通过 [`Scheduler::metrics`] 观察。
+    ///
+    /// 排队的消息留在 [`App::event_bus`] 的广播通道中，不会为它们启动任务；
+    /// 通道满了之后最早的消息会被丢弃，日志中会有 `broadcast lagged` 的警告。
+    ///
+    /// 单个 handler 的上限见 [`HandlerHandle::max_concurrency`]。
+    fn max_concurrency(self, max: usize) -> Self {
+        self.scheduler().set_max_concurrency(max);
+        self
+    }
+
+    /// 开启串行模式：对于每个 handler，`key` 相同的消息（如同一个群、同一个用户）按照收到的顺序依次处理，
+    /// 前一条消息处理完之后才会处理下一条。`key` 不同的消息之间、不同的 handler 之间仍然并发执行。
+    ///
+    /// 按照优先级分发的 handler 以及指令作为一个整体串行，见 [`Dispatcher`]。
+    fn serial_by<K, F>(self, key: F) -> Self
+    where
+        K: Hash,
+        F: Fn(&Self::Message) -> Option<K> + Send + Sync + 'static,
+    {
+        self.scheduler().set_serial_by(key);
+        self
+    }
+}
+
+/// 提取好参数的 handler 调用，handler 不处理这个请求（参数提取失败）时为 `None`
+type Prepare<A> = dyn Fn(Request<A>) -> Option<BoxFuture<'static, Outcome>> + Send + Sync;
+
+/// 包装后的 handler，可以在执行之前检查是否处理一个请求
+pub(crate) struct Handler<A: App> {
+    prepare: Arc<Prepare<A>>,
+    endpoint: Arc<Endpoint<A>>,
+    accepts: fn(&Request<A>) -> bool,
+}
+
+fn accepts<A: App, I: FromRequest<A>>(request: &Request<A>) -> bool {
+    I::may_extract(&request.message)
+}
+
+impl<A: App> Handler<A> {
+    pub fn new<F, I, Fut>(f: F) -> Self
+    where
+        F: Func<I, Fut>,
+        I: FromRequest<A> + Send + 'static,
+        Fut: Future + Send + 'static,
+        Fut::Output: Return<A>,
+    {
+        let f = Arc::new(f);
+        let prepare: Arc<Prepare<A>> = Arc::new(move |request: Request<A>| {
+            let input = I::from_request(&request)?;
+            let f = f.clone();
+            Some(Box::pin(async move {
+                match catch_panic(f.call(input)).await {
+                    Ok(ret) => {
+                        let outcome = ret.outcome();
+                        ret.on_return(request).await;
+                        outcome
                     }
+                    Err(outcome) => outcome,
                 }
-            }
+            }))
+        });
+        let endpoint = {
+            let prepare = prepare.clone();
+            Arc::new(move |request: Request<A>| -> BoxFuture<'static, Outcome> {
+                prepare(request).unwrap_or_else(|| Box::pin(async { Outcome::Skipped }))
+            })
         };
-        tokio::spawn(task);
-        self
+        Self {
+            prepare,
+            endpoint,
+            accepts: accepts::<A, I>,
+        }
+    }
+
+    /// 中间件最内层的 [`Endpoint`]，参数提取失败时返回 [`Outcome::Skipped`]
+    pub fn endpoint(&self) -> Arc<Endpoint<A>> {
+        self.endpoint.clone()
     }
+
+    /// 经过 `middlewares` 处理请求的任务，handler 不处理这个请求时返回 `None`，中间件也不会执行。
+    ///
+    /// 中间件可能会修改请求，所以有中间件时只用 [`FromRequest::may_extract`] 检查消息的类型，
+    /// 经过中间件之后再提取参数；没有中间件时直接使用检查时提取好的参数。
+    pub fn run(
+        &self,
+        middlewares: Vec<Arc<dyn Middleware<A>>>,
+        request: Request<A>,
+    ) -> Option<BoxFuture<'static, Outcome>> {
+        if middlewares.is_empty() {
+            return (self.prepare)(request);
+        }
+        if !(self.accepts)(&request) {
+            return None;
+        }
+        let endpoint = self.endpoint.clone();
+        Some(Box::pin(async move {
+            run_middlewares(&middlewares, &*endpoint, request).await
+        }))
+    }
+}
+
+impl<A: App> Clone for Handler<A> {
+    fn clone(&self) -> Self {
+        Self {
+            prepare: self.prepare.clone(),
+            endpoint: self.endpoint.clone(),
+            accepts: self.accepts,
+        }
+    }
+}
+
+/// 把 handler 包装成中间件最内层的 [`Endpoint`]
+pub(crate) fn endpoint<A, F, I, Fut>(f: F) -> Arc<Endpoint<A>>
+where
+    A: App,
+    F: Func<I, Fut>,
+    I: FromRequest<A> + Send + 'static,
+    Fut: Future + Send + 'static,
+    Fut::Output: Return<A>,
+{
+    Handler::new(f).endpoint()
+}
+
+/// 执行 handler，捕获 handler 中的 panic
+pub(crate) async fn catch_panic<Fut: Future>(fut: Fut) -> Result<Fut::Output, Outcome> {
+    AssertUnwindSafe(fut)
+        .catch_unwind()
+        .await
+        .map_err(|payload| {
+            let message = payload
+                .downcast_ref::<&str>()
+                .map(|s| s.to_string())
+                .or_else(|| payload.downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| "unknown panic".to_string());
+            error!("handler panicked: {}", message);
+            Outcome::Panicked(message)
+        })
+}
+
+/// 订阅 App 的消息并交给 `f` 处理
+fn subscribe<A, F, I, Fut>(app: A, f: F) -> HandlerHandle<A>
+where
+    A: App,
+    F: Func<I, Fut>,
+    I: FromRequest<A> + Send + 'static,
+    Fut: Future + Send + 'static,
+    Fut::Output: Return<A>,
+{
+    let receiver = app.event_bus().subscribe();
+    let handle = HandlerHandle::new(app.clone());
+    let state = handle.state();
+    let handler = Handler::new(f);
+    let serial = SerialQueue::default();
+    let task = async move {
+        let mut receiver = receiver;
+        loop {
+            let recv = receiver.recv().await;
+            match recv {
+                Ok(_) if state.is_paused() => {}
+                Ok(message) => {
+                    // convert message to request
+                    // message carries message & context,
+                    // app carries data, e.g., database connections, etc.
+                    let request = Request::<A>::new(app.clone(), message);
+                    // 提前过滤掉 handler 不处理的消息，不需要为它们启动任务
+                    let scheduler = app.scheduler();
+                    let serial_key = scheduler.serial_key(&request.message);
+                    let run = match handler.run(app.middlewares().snapshot(), request) {
+                        Some(run) => run,
+                        None => continue,
+                    };
+                    // 串行模式下在这里按照收到的顺序预约
+                    let ticket = serial_key.map(|key| serial.reserve(key));
+                    // 先拿到并发许可再启动任务，超过上限时在这里等待，后面的消息留在广播通道中
+                    let permits = acquire(&[state.limiter.clone(), scheduler.limiter()]).await;
+                    tokio::spawn(permits.run(ticket, run));
+                }
+                Err(broadcast::error::RecvError::Lagged(i)) => {
+                    warn!("broadcast lagged {} messages.", i);
+                }
+                Err(broadcast::error::RecvError::Closed) => {
+                    break;
+                }
+            }
+        }
+    };
+    let task = tokio::spawn(task);
+    handle.on_unregister(move || task.abort());
+    handle
 }
@@ -0,0 +1,202 @@
+use futures::future::join_all;
+use parking_lot::{Mutex, RwLock};
+use std::{
+    fmt,
+    future::Future,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Once,
+    },
+};
+use tokio::sync::broadcast;
+
+use super::{
+    app::Handler,
+    middleware::Outcome,
+    scheduler::{acquire, run_limited, SerialQueue},
+    App, FromRequest, Func, HandlerHandle, HandlerState, Request, Return,
+};
+
+/// 按照优先级分发消息，通过 [`App::handler_priority`] 和 [`App::fallback`] 注册的 handler 由它来调用。
+///
+/// 对于每条消息：
+/// - 优先级高的 handler 先执行，优先级相同的 handler 并发执行；
+/// - 某个 handler 返回 [`Handled`](super::Handled)（即 [`Outcome::Handled`]）后，
+///   同一优先级的其他 handler 仍会执行完，但是更低优先级的 handler 不会再收到这条消息；
+/// - 所有的 handler 都没有消费这条消息时，才会执行 fallback handler。
+///
+/// 开启 [`App::serial_by`] 后，同一个会话的消息会按照收到的顺序依次分发，
+/// 前一条消息的所有 handler 都执行完之后才会分发下一条消息。
+/// [`Bot`](crate::Bot) 的关键词指令是例外，指令开始执行后就算分发完成，不等待指令执行完。
+pub struct Dispatcher<A: App>(Arc<Inner<A>>);
+
+struct Inner<A: App> {
+    started: Once,
+    next_id: AtomicUsize,
+    /// 按照优先级从高到低排列，优先级相同的按照注册的顺序排列
+    handlers: RwLock<Vec<Entry<A>>>,
+    fallbacks: RwLock<Vec<Entry<A>>>,
+    serial: SerialQueue,
+}
+
+#[derive(Clone)]
+struct Entry<A: App> {
+    id: usize,
+    /// 注册 handler 时的 App，请求中的 App 以及中间件都来自这里。
+    /// 放在锁里，这样 App 不需要是 `Sync` 的
+    app: Arc<Mutex<A>>,
+    priority: i32,
+    state: Arc<HandlerState>,
+    handler: Handler<A>,
+    /// 为 `false` 时不经过全局中间件和全局并发限制，由内部自己处理，如关键词指令的分发
+    with_middlewares: bool,
+}
+
+impl<A: App> Entry<A> {
+    async fn run(&self, message: A::Message) -> Outcome {
+        if self.state.is_paused() {
+            return Outcome::Skipped;
+        }
+        let app = self.app.lock().clone();
+        let request = Request::new(app, message);
+        let (middlewares, limiters) = match self.with_middlewares {
+            true => (
+                request.app.middlewares().snapshot(),
+                vec![
+                    self.state.limiter.clone(),
+                    request.app.scheduler().limiter(),
+                ],
+            ),
+            false => (Vec::new(), vec![self.state.limiter.clone()]),
+        };
+        match self.handler.run(middlewares, request) {
+            Some(run) => run_limited(&limiters, run).await,
+            None => Outcome::Skipped,
+        }
+    }
+}
+
+impl<A: App> Dispatcher<A> {
+    /// 没有任何 handler 的分发器
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 注册 handler，`priority` 为 `None` 时注册为 fallback handler
+    pub(crate) fn register<F, I, Fut>(
+        &self,
+        app: A,
+        priority: Option<i32>,
+        f: F,
+        with_middlewares: bool,
+    ) -> HandlerHandle<A>
+    where
+        F: Func<I, Fut>,
+        I: FromRequest<A> + Send + 'static,
+        Fut: Future + Send + 'static,
+        Fut::Output: Return<A>,
+    {
+        self.start(&app);
+        let handle = HandlerHandle::new(app.clone());
+        let id = self.0.next_id.fetch_add(1, Ordering::Relaxed);
+        let entry = Entry {
+            id,
+            app: Arc::new(Mutex::new(app)),
+            priority: priority.unwrap_or_default(),
+            state: handle.state(),
+            handler: Handler::new(f),
+            with_middlewares,
+        };
+        match priority {
+            Some(priority) => {
+                let mut handlers = self.0.handlers.write();
+                let pos = handlers.partition_point(|e| e.priority >= priority);
+                handlers.insert(pos, entry);
+            }
+            None => self.0.fallbacks.write().push(entry),
+        }
+        let dispatcher = self.clone();
+        handle.on_unregister(move || dispatcher.remove(id));
+        handle
+    }
+
+    fn remove(&self, id: usize) {
+        self.0.handlers.write().retain(|e| e.id != id);
+        self.0.fallbacks.write().retain(|e| e.id != id);
+    }
+
+    /// 第一次注册 handler 时开始订阅 App 的消息
+    fn start(&self, app: &A) {
+        self.0.started.call_once(|| {
+            let mut receiver = app.event_bus().subscribe();
+            let app = app.clone();
+            let dispatcher = self.clone();
+            tokio::spawn(async move {
+                loop {
+                    match receiver.recv().await {
+                        Ok(message) => {
+                            // 串行模式下在这里按照收到的顺序预约
+                            let scheduler = app.scheduler();
+                            let ticket = scheduler
+                                .serial_key(&message)
+                                .map(|key| dispatcher.0.serial.reserve(key));
+                            // 超过上限时在这里等待，后面的消息留在广播通道中
+                            let permits = acquire(&[scheduler.dispatch_limiter()]).await;
+                            let dispatcher = dispatcher.clone();
+                            tokio::spawn(async move {
+                                permits.run(ticket, dispatcher.dispatch(message)).await;
+                            });
+                        }
+                        Err(broadcast::error::RecvError::Lagged(i)) => {
+                            warn!("broadcast lagged {} messages.", i);
+                        }
+                        Err(broadcast::error::RecvError::Closed) => {
+                            break;
+                        }
+                    }
+                }
+            });
+        });
+    }
+
+    /// 把消息按照优先级交给 handler，返回消息是否被消费
+    async fn dispatch(&self, message: A::Message) -> bool {
+        let handlers = self.0.handlers.read().clone();
+        for level in handlers.chunk_by(|a, b| a.priority == b.priority) {
+            let outcomes = join_all(level.iter().map(|e| e.run(message.clone()))).await;
+            if outcomes.contains(&Outcome::Handled) {
+                return true;
+            }
+        }
+        let fallbacks = self.0.fallbacks.read().clone();
+        join_all(fallbacks.iter().map(|e| e.run(message.clone()))).await;
+        false
+    }
+}
+
+impl<A: App> Default for Dispatcher<A> {
+    fn default() -> Self {
+        Self(Arc::new(Inner {
+            started: Once::new(),
+            next_id: AtomicUsize::new(0),
+            handlers: RwLock::default(),
+            fallbacks: RwLock::default(),
+            serial: SerialQueue::default(),
+        }))
+    }
+}
+
+impl<A: App> Clone for Dispatcher<A> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<A: App> fmt::Debug for Dispatcher<A> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Dispatcher")
+            .field("handlers", &self.0.handlers.read().len())
+            .field("fallbacks", &self.0.fallbacks.read().len())
+            .finish()
+    }
+}
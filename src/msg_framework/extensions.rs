@@ -3,16 +3,18 @@
 use std::{
     any::{Any, TypeId},
     collections::HashMap,
+    sync::Arc,
 };
 
-type BoxedAny = Box<dyn Any + Send + Sync>;
+type SharedAny = Arc<dyn Any + Send + Sync>;
 
 /// A type map for request extensions.
 ///
 /// All entries into this map must be owned types (or static references).
-#[derive(Default)]
+/// 值使用 `Arc` 保存，克隆 [`Extensions`] 的开销很小，可以随着 [`Request`](super::Request) 一起克隆。
+#[derive(Default, Clone)]
 pub struct Extensions {
-    map: HashMap<TypeId, BoxedAny>,
+    map: HashMap<TypeId, SharedAny>,
 }
 
 #[allow(unused)]
@@ -23,7 +25,7 @@ impl Extensions {
 
     pub fn insert<T: Send + Sync + 'static>(&mut self, val: T) -> Option<T> {
         self.map
-            .insert(TypeId::of::<T>(), Box::new(val))
+            .insert(TypeId::of::<T>(), Arc::new(val))
             .and_then(downcast_owned)
     }
 
@@ -34,20 +36,26 @@ impl Extensions {
     pub fn get<T: 'static>(&self) -> Option<&T> {
         self.map
             .get(&TypeId::of::<T>())
-            .and_then(|boxed| boxed.downcast_ref())
+            .and_then(|value| value.downcast_ref())
     }
 
+    /// 只有在值没有被其他克隆共享时才能获取可变引用
     pub fn get_mut<T: 'static>(&mut self) -> Option<&mut T> {
         self.map
             .get_mut(&TypeId::of::<T>())
-            .and_then(|boxed| boxed.downcast_mut())
+            .and_then(Arc::get_mut)
+            .and_then(|value| value.downcast_mut())
     }
 
-    pub fn remove<T: 'static>(&mut self) -> Option<T> {
+    /// 移除并返回值，值被其他克隆共享时只移除，返回 `None`
+    pub fn remove<T: Send + Sync + 'static>(&mut self) -> Option<T> {
         self.map.remove(&TypeId::of::<T>()).and_then(downcast_owned)
     }
 }
 
-fn downcast_owned<T: 'static>(boxed: BoxedAny) -> Option<T> {
-    boxed.downcast().ok().map(|boxed| *boxed)
+fn downcast_owned<T: Send + Sync + 'static>(value: SharedAny) -> Option<T> {
+    value
+        .downcast()
+        .ok()
+        .and_then(|value| Arc::try_unwrap(value).ok())
 }
@@ -0,0 +1,174 @@
+use parking_lot::Mutex;
+use std::{
+    ops::Deref,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+use super::{scheduler::Limiter, App, FromRequest, Func, Return, TaskMetrics};
+use futures::Future;
+
+/// handler 的状态，在句柄和 handler 之间共享
+#[derive(Default)]
+pub(crate) struct HandlerState {
+    paused: AtomicBool,
+    unregistered: AtomicBool,
+    on_unregister: Mutex<Option<Box<dyn FnOnce() + Send>>>,
+    /// 只对这个 handler 生效的并发限制
+    pub(crate) limiter: Limiter,
+}
+
+impl HandlerState {
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::Relaxed)
+    }
+
+    pub fn set_paused(&self, paused: bool) {
+        self.paused.store(paused, Ordering::Relaxed);
+    }
+}
+
+/// 注册 handler 后返回的句柄，可以用来暂停或者取消注册 handler。
+///
+/// 丢弃句柄不会影响 handler，handler 会一直存在直到调用 [`HandlerHandle::unregister`]。
+///
+/// 句柄可以通过 `Deref` 直接访问 App，也可以继续调用 `handler` 链式注册。
+///
+/// # Example
+/// ```no_run
+/// # use miraie::prelude::*;
+/// # tokio_test::block_on(async {
+/// # let (bot, _) = Bot::new("127.0.0.1", "secret", QQ(123456)).await.unwrap();
+/// let handle = bot.handler(|msg: GroupMessage| async move {
+///     println!("{:?}", msg);
+/// });
+/// // 暂停期间收到的消息会被忽略
+/// handle.pause();
+/// handle.resume();
+/// // 取消注册后无法恢复
+/// handle.unregister();
+/// # });
+/// ```
+#[derive(Clone)]
+pub struct HandlerHandle<A> {
+    app: A,
+    state: Arc<HandlerState>,
+}
+
+impl<A: App> HandlerHandle<A> {
+    pub(crate) fn new(app: A) -> Self {
+        Self {
+            app,
+            state: Arc::default(),
+        }
+    }
+
+    /// 取消注册时调用 `f`，如果已经取消注册会立即调用
+    pub(crate) fn on_unregister(&self, f: impl FnOnce() + Send + 'static) {
+        let mut on_unregister = self.state.on_unregister.lock();
+        if self.is_unregistered() {
+            std::mem::drop(on_unregister);
+            f();
+        } else {
+            *on_unregister = Some(Box::new(f));
+        }
+    }
+
+    pub(crate) fn state(&self) -> Arc<HandlerState> {
+        self.state.clone()
+    }
+
+    /// 暂停 handler，暂停期间的消息会被忽略
+    pub fn pause(&self) {
+        self.state.set_paused(true);
+    }
+
+    /// 恢复暂停的 handler
+    pub fn resume(&self) {
+        self.state.set_paused(false);
+    }
+
+    /// handler 是否被暂停
+    pub fn is_paused(&self) -> bool {
+        self.state.is_paused()
+    }
+
+    /// 取消注册 handler，之后的消息都不会再被处理，已经开始处理的消息不受影响。
+    /// 重复调用没有效果。
+    pub fn unregister(&self) {
+        let on_unregister = {
+            let mut on_unregister = self.state.on_unregister.lock();
+            self.state.unregistered.store(true, Ordering::Relaxed);
+            on_unregister.take()
+        };
+        if let Some(f) = on_unregister {
+            f();
+        }
+    }
+
+    /// handler 是否已经取消注册
+    pub fn is_unregistered(&self) -> bool {
+        self.state.unregistered.load(Ordering::Relaxed)
+    }
+
+    /// 设置这个 handler 同时处理的消息数的上限，超过上限的消息会排队等待。
+    ///
+    /// 全局的上限见 [`App::max_concurrency`]，两者同时生效。
+    pub fn max_concurrency(self, max: usize) -> Self {
+        self.state.limiter.set_max_concurrency(max);
+        self
+    }
+
+    /// 这个 handler 的任务统计
+    pub fn metrics(&self) -> TaskMetrics {
+        self.state.limiter.metrics()
+    }
+
+    /// 继续注册 handler，见 [`App::handler`]
+    pub fn handler<F, I, Fut>(self, f: F) -> HandlerHandle<A>
+    where
+        F: Func<I, Fut>,
+        I: FromRequest<A> + Send + 'static,
+        Fut: Future + Send + 'static,
+        Fut::Output: Return<A>,
+    {
+        self.app.handler(f)
+    }
+
+    /// 继续注册带有优先级的 handler，见 [`App::handler_priority`]
+    pub fn handler_priority<F, I, Fut>(self, priority: i32, f: F) -> HandlerHandle<A>
+    where
+        F: Func<I, Fut>,
+        I: FromRequest<A> + Send + 'static,
+        Fut: Future + Send + 'static,
+        Fut::Output: Return<A>,
+    {
+        self.app.handler_priority(priority, f)
+    }
+
+    /// 继续注册 fallback handler，见 [`App::fallback`]
+    pub fn fallback<F, I, Fut>(self, f: F) -> HandlerHandle<A>
+    where
+        F: Func<I, Fut>,
+        I: FromRequest<A> + Send + 'static,
+        Fut: Future + Send + 'static,
+        Fut::Output: Return<A>,
+    {
+        self.app.fallback(f)
+    }
+
+    /// 取回 App
+    pub fn into_app(self) -> A {
+        self.app
+    }
+}
+
+impl<A> Deref for HandlerHandle<A> {
+    type Target = A;
+
+    fn deref(&self) -> &A {
+        &self.app
+    }
+}
@@ -0,0 +1,174 @@
+use futures::future::BoxFuture;
+use parking_lot::RwLock;
+use std::{fmt, sync::Arc};
+
+use super::{App, Request};
+
+/// handler 处理一次请求的结果，中间件可以通过 [`Next::run`] 的返回值观察到
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    /// handler 正常执行完成
+    Done,
+    /// handler 执行完成，并且消费了消息，见 [`Handled`](super::Handled)
+    Handled,
+    /// handler 返回了错误
+    Failed(String),
+    /// handler panic 了，panic 会被捕获，不会影响其他的 handler
+    Panicked(String),
+    /// 没有执行 handler，如参数提取失败，或者被中间件拦截
+    Skipped,
+}
+
+/// 中间件，包裹在 handler 的外面，可以在 handler 执行前后做一些事情，如黑名单、日志、计时等。
+///
+/// 中间件可以：
+/// - 修改 [`Request`] 后再交给 `next`；
+/// - 不调用 `next` 直接返回，拦截这次请求；
+/// - 观察 `next` 返回的 [`Outcome`]。
+///
+/// 通过 [`App::middleware`] 注册的全局中间件对所有的 handler 和指令都生效，
+/// 通过 [`Command::middleware`](crate::bot::Command::middleware) 注册的中间件只对这个指令生效。
+/// 全局中间件在指令的中间件外层，同一层的中间件按照注册的顺序执行，先注册的在外层。
+///
+/// handler 不处理的消息（参数提取失败）不会交给中间件。中间件修改请求后，handler 的参数会重新提取，
+/// 这时提取失败得到的结果是 [`Outcome::Skipped`]。
+///
+/// # Example
+/// ```no_run
+/// # use miraie::prelude::*;
+/// use miraie::msg_framework::{Middleware, Next, Outcome, Request};
+///
+/// struct Blacklist(Vec<QQ>);
+///
+/// #[async_trait::async_trait]
+/// impl Middleware<Bot> for Blacklist {
+///     async fn handle(&self, request: Request<Bot>, next: Next<'_, Bot>) -> Outcome {
+///         let sender = match &request.message {
+///             Message::Group(msg) => Some(msg.sender.id),
+///             Message::Friend(msg) => Some(msg.sender.id),
+///             _ => None,
+///         };
+///         if sender.is_some_and(|qq| self.0.contains(&qq)) {
+///             return Outcome::Skipped;
+///         }
+///         let start = std::time::Instant::now();
+///         let outcome = next.run(request).await;
+///         println!("handled in {:?}: {:?}", start.elapsed(), outcome);
+///         outcome
+///     }
+/// }
+///
+/// # tokio_test::block_on(async {
+/// # let (bot, _) = Bot::new("127.0.0.1", "secret", QQ(123456)).await.unwrap();
+/// let bot = bot.middleware(Blacklist(vec![QQ(10000)]));
+/// # });
+/// ```
+#[async_trait]
+pub trait Middleware<A: App>: Send + Sync + 'static {
+    async fn handle(&self, request: Request<A>, next: Next<'_, A>) -> Outcome;
+}
+
+/// 中间件之后的部分，调用 [`Next::run`] 继续执行剩下的中间件以及 handler
+pub struct Next<'a, A: App> {
+    middlewares: &'a [Arc<dyn Middleware<A>>],
+    endpoint: &'a Endpoint<A>,
+}
+
+impl<A: App> Next<'_, A> {
+    /// 继续处理请求
+    pub async fn run(mut self, request: Request<A>) -> Outcome {
+        match self.middlewares.split_first() {
+            Some((middleware, rest)) => {
+                self.middlewares = rest;
+                middleware.handle(request, self).await
+            }
+            None => (self.endpoint)(request).await,
+        }
+    }
+}
+
+/// 中间件包裹的最内层，即 handler 本身
+pub(crate) type Endpoint<A> = dyn Fn(Request<A>) -> BoxFuture<'static, Outcome> + Send + Sync;
+
+/// 经过 `middlewares` 执行 `endpoint`
+pub(crate) async fn run_middlewares<A: App>(
+    middlewares: &[Arc<dyn Middleware<A>>],
+    endpoint: &Endpoint<A>,
+    request: Request<A>,
+) -> Outcome {
+    Next {
+        middlewares,
+        endpoint,
+    }
+    .run(request)
+    .await
+}
+
+/// 一组中间件，可以在多个地方共享
+pub struct Middlewares<A: App> {
+    list: Arc<RwLock<Vec<Arc<dyn Middleware<A>>>>>,
+    /// 外层的中间件，见 [`Middlewares::child`]
+    parent: Option<Box<Middlewares<A>>>,
+}
+
+impl<A: App> Middlewares<A> {
+    /// 空的中间件列表
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 在这一组中间件的内层新建一组中间件，外层的中间件之后的变化同样会生效，
+    /// 内层添加的中间件不影响外层。
+    pub(crate) fn child(&self) -> Self {
+        Self {
+            list: Arc::default(),
+            parent: Some(Box::new(self.clone())),
+        }
+    }
+
+    /// 添加一个中间件到最内层
+    pub fn push(&self, middleware: impl Middleware<A>) {
+        self.list.write().push(Arc::new(middleware));
+    }
+
+    /// 添加一个中间件到最外层，有外层的中间件时只添加到这一层的最外层
+    pub(crate) fn push_front(&self, middleware: impl Middleware<A>) {
+        self.list.write().insert(0, Arc::new(middleware));
+    }
+
+    /// 当前所有的中间件，包括外层的中间件
+    pub fn snapshot(&self) -> Vec<Arc<dyn Middleware<A>>> {
+        let mut middlewares = match &self.parent {
+            Some(parent) => parent.snapshot(),
+            None => Vec::new(),
+        };
+        middlewares.extend(self.list.read().iter().cloned());
+        middlewares
+    }
+}
+
+impl<A: App> Default for Middlewares<A> {
+    fn default() -> Self {
+        Self {
+            list: Arc::default(),
+            parent: None,
+        }
+    }
+}
+
+impl<A: App> Clone for Middlewares<A> {
+    fn clone(&self) -> Self {
+        Self {
+            list: self.list.clone(),
+            parent: self.parent.clone(),
+        }
+    }
+}
+
+impl<A: App> fmt::Debug for Middlewares<A> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Middlewares")
+            .field("len", &self.snapshot().len())
+            .finish()
+    }
+}
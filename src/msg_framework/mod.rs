@@ -1,10 +1,24 @@
 //! 核心事件框架
 mod app;
+mod dispatcher;
+mod extensions;
 mod func;
+mod handle;
+mod middleware;
 mod requests;
+mod scheduler;
 #[cfg(test)]
 mod test_msg_framework;
 
-pub use app::{App, Return};
+pub(crate) use app::{catch_panic, endpoint};
+pub use app::{App, Continue, Handled, Return};
+pub use dispatcher::Dispatcher;
+pub use extensions::Extensions;
 pub use func::Func;
+pub use handle::HandlerHandle;
+pub(crate) use handle::HandlerState;
+pub(crate) use middleware::{run_middlewares, Endpoint};
+pub use middleware::{Middleware, Middlewares, Next, Outcome};
 pub use requests::{FromRequest, Request};
+pub(crate) use scheduler::{acquire, run_limited, Limiter};
+pub use scheduler::{Scheduler, TaskMetrics};
@@ -1,4 +1,4 @@
-use super::App;
+use super::{App, Extensions};
 
 #[derive(Clone)]
 pub struct Request<A>
@@ -7,6 +7,22 @@ where
 {
     pub app: A,
     pub message: A::Message,
+    /// 只在本次请求中有效的数据，如匹配到的指令等
+    pub extensions: Extensions,
+}
+
+impl<A> Request<A>
+where
+    A: App,
+{
+    /// 新建一个请求，扩展数据为空
+    pub fn new(app: A, message: A::Message) -> Self {
+        Self {
+            app,
+            message,
+            extensions: Extensions::new(),
+        }
+    }
 }
 
 pub trait FromRequest<A>: Sized
@@ -14,6 +30,15 @@ where
     A: App,
 {
     fn from_request(request: &Request<A>) -> Option<Self>;
+
+    /// 是否可能从 `message` 中提取，只看消息的类型，不看请求中的其他数据。
+    /// 默认为 `true`，返回 `false` 的消息不会交给 handler 和它的中间件，
+    /// [`Bot`](crate::Bot) 的帮助也根据它过滤掉在当前聊天中不能使用的指令。
+    ///
+    /// 中间件可能在请求中加入扩展数据，所以这里不能调用 [`FromRequest::from_request`]。
+    fn may_extract(_message: &A::Message) -> bool {
+        true
+    }
 }
 
 mod _impl_from_request {
@@ -37,6 +62,10 @@ mod _impl_from_request {
             let r = T::from_request(request)?;
             Some((r,))
         }
+
+        fn may_extract(message: &A::Message) -> bool {
+            T::may_extract(message)
+        }
     }
 
     macro_rules! f {
@@ -56,6 +85,10 @@ mod _impl_from_request {
                         ),*
                     ))
                 }
+
+                fn may_extract(message: &A::Message) -> bool {
+                    $($Ts::may_extract(message))&&*
+                }
             }
         };
     }
@@ -0,0 +1,312 @@
+use parking_lot::{Mutex, RwLock};
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    fmt,
+    future::Future,
+    hash::{Hash, Hasher},
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+};
+use tokio::sync::{oneshot, OwnedSemaphorePermit, Semaphore};
+
+use super::App;
+
+/// handler 任务的统计
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TaskMetrics {
+    /// 等待执行的任务数，包括等待并发许可的任务，以及串行模式下等待同一会话之前的消息处理完的任务
+    pub queued: usize,
+    /// 正在执行的任务数
+    pub running: usize,
+    /// 已经执行完成的任务数
+    pub completed: u64,
+}
+
+/// 基于信号量的并发限制，同时记录任务的统计
+#[derive(Clone, Default)]
+pub(crate) struct Limiter(Arc<LimiterInner>);
+
+#[derive(Default)]
+struct LimiterInner {
+    /// 没有限制时为 `None`
+    semaphore: RwLock<Option<Arc<Semaphore>>>,
+    queued: AtomicUsize,
+    running: AtomicUsize,
+    completed: AtomicU64,
+}
+
+impl Limiter {
+    /// 设置最大的并发数，已经在执行的任务不受影响
+    pub fn set_max_concurrency(&self, max: usize) {
+        assert!(max > 0, "最大并发数必须大于 0");
+        *self.0.semaphore.write() = Some(Arc::new(Semaphore::new(max)));
+    }
+
+    pub fn metrics(&self) -> TaskMetrics {
+        TaskMetrics {
+            queued: self.0.queued.load(Ordering::Relaxed),
+            running: self.0.running.load(Ordering::Relaxed),
+            completed: self.0.completed.load(Ordering::Relaxed),
+        }
+    }
+
+    fn semaphore(&self) -> Option<Arc<Semaphore>> {
+        self.0.semaphore.read().clone()
+    }
+
+    fn enqueue(&self) -> Queued {
+        self.0.queued.fetch_add(1, Ordering::Relaxed);
+        Queued {
+            limiter: self.clone(),
+            permit: None,
+        }
+    }
+}
+
+/// 排队中的任务，丢弃时离开队列
+struct Queued {
+    limiter: Limiter,
+    permit: Option<OwnedSemaphorePermit>,
+}
+
+impl Queued {
+    /// 等待并发许可，拿到许可后仍然算作排队，直到开始执行
+    async fn acquire(&mut self) {
+        if let Some(semaphore) = self.limiter.semaphore() {
+            self.permit = Some(semaphore.acquire_owned().await.expect("信号量不会被关闭"));
+        }
+    }
+
+    /// 开始执行
+    fn start(mut self) -> Running {
+        let limiter = self.limiter.clone();
+        let permit = self.permit.take();
+        std::mem::drop(self);
+        limiter.0.running.fetch_add(1, Ordering::Relaxed);
+        Running {
+            limiter,
+            _permit: permit,
+        }
+    }
+}
+
+impl Drop for Queued {
+    fn drop(&mut self) {
+        self.limiter.0.queued.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// 执行中的任务，丢弃时释放并发许可
+struct Running {
+    limiter: Limiter,
+    _permit: Option<OwnedSemaphorePermit>,
+}
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        self.limiter.0.running.fetch_sub(1, Ordering::Relaxed);
+        self.limiter.0.completed.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// 已经拿到所有并发许可、等待执行的任务，丢弃时释放许可
+pub(crate) struct Permits(Vec<Queued>);
+
+/// 按照顺序获取 `limiters` 的许可。
+///
+/// 先获取的许可在等待之后的许可时不会被释放，所以 handler 自己的限制要放在全局的限制之前。
+/// 需要在启动任务之前获取，这样超过上限时不会为排队的消息启动大量的任务。
+pub(crate) async fn acquire(limiters: &[Limiter]) -> Permits {
+    let mut queued: Vec<_> = limiters.iter().map(Limiter::enqueue).collect();
+    for queued in queued.iter_mut() {
+        queued.acquire().await;
+    }
+    Permits(queued)
+}
+
+impl Permits {
+    /// 执行 `fut`，有 `ticket` 时先等待轮到它，等待时仍然占用着许可
+    pub async fn run<Fut: Future>(self, ticket: Option<SerialTicket>, fut: Fut) -> Fut::Output {
+        let _turn = match ticket {
+            Some(ticket) => Some(ticket.wait().await),
+            None => None,
+        };
+        let _running: Vec<_> = self.0.into_iter().map(Queued::start).collect();
+        fut.await
+    }
+}
+
+/// 在 `limiters` 的限制下执行 `fut`，见 [`acquire`]
+pub(crate) async fn run_limited<Fut: Future>(limiters: &[Limiter], fut: Fut) -> Fut::Output {
+    acquire(limiters).await.run(None, fut).await
+}
+
+/// 每个会话最后一个预约的任务，以及它执行完的通知
+type Slots = HashMap<u64, (usize, oneshot::Receiver<()>)>;
+
+/// 串行模式下的队列，同一个会话的任务按照预约的顺序依次执行，不同会话之间互不影响
+#[derive(Default)]
+pub(crate) struct SerialQueue {
+    next_id: AtomicUsize,
+    slots: Arc<Mutex<Slots>>,
+}
+
+impl SerialQueue {
+    /// 为会话 `key` 预约一个位置，需要在收到消息时按照消息的顺序调用
+    pub fn reserve(&self, key: u64) -> SerialTicket {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (done, receiver) = oneshot::channel();
+        let prev = self
+            .slots
+            .lock()
+            .insert(key, (id, receiver))
+            .map(|(_, prev)| prev);
+        SerialTicket {
+            prev,
+            turn: Some(SerialTurn {
+                key,
+                id,
+                slots: self.slots.clone(),
+                _done: done,
+            }),
+        }
+    }
+}
+
+/// 串行队列中预约的位置
+pub(crate) struct SerialTicket {
+    /// 前一个任务执行完的通知，它的发送端被丢弃时即执行完
+    prev: Option<oneshot::Receiver<()>>,
+    turn: Option<SerialTurn>,
+}
+
+impl SerialTicket {
+    /// 等待前一个任务执行完，返回的 [`SerialTurn`] 被丢弃时轮到下一个任务
+    pub async fn wait(mut self) -> SerialTurn {
+        if let Some(prev) = &mut self.prev {
+            let _ = prev.await;
+            self.prev = None;
+        }
+        self.turn.take().expect("只会等待一次")
+    }
+}
+
+impl Drop for SerialTicket {
+    fn drop(&mut self) {
+        // 没有执行就被丢弃时，仍然要等前一个任务执行完才能轮到下一个任务
+        if let (Some(prev), Some(turn)) = (self.prev.take(), self.turn.take()) {
+            tokio::spawn(async move {
+                let _ = prev.await;
+                std::mem::drop(turn);
+            });
+        }
+    }
+}
+
+/// 轮到执行的任务，丢弃时通知下一个任务
+pub(crate) struct SerialTurn {
+    key: u64,
+    id: usize,
+    slots: Arc<Mutex<Slots>>,
+    _done: oneshot::Sender<()>,
+}
+
+impl Drop for SerialTurn {
+    fn drop(&mut self) {
+        // 后面没有任务时清理这个会话
+        let mut slots = self.slots.lock();
+        if slots.get(&self.key).is_some_and(|(id, _)| *id == self.id) {
+            slots.remove(&self.key);
+        }
+    }
+}
+
+type SerialKey<A> = dyn Fn(&<A as App>::Message) -> Option<u64> + Send + Sync;
+
+/// App 全局的调度设置：所有 handler 共享的并发限制，以及按会话串行执行的模式。
+///
+/// 见 [`App::max_concurrency`] 和 [`App::serial_by`]。
+pub struct Scheduler<A: App>(Arc<SchedulerInner<A>>);
+
+struct SchedulerInner<A: App> {
+    limiter: Limiter,
+    /// [`Dispatcher`](super::Dispatcher) 同时分发的消息数的限制，和全局的限制一样大。
+    /// 分发中的 handler 还需要全局的许可，所以不能共用一个限制。
+    dispatch: Limiter,
+    serial_key: RwLock<Option<Arc<SerialKey<A>>>>,
+}
+
+impl<A: App> Scheduler<A> {
+    /// 没有并发限制，也不串行执行的调度设置
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 设置所有 handler 同时执行的任务数的上限
+    pub fn set_max_concurrency(&self, max: usize) {
+        self.0.limiter.set_max_concurrency(max);
+        self.0.dispatch.set_max_concurrency(max);
+    }
+
+    /// 开启串行模式，`key` 相同的消息会按照收到的顺序依次处理，返回 `None` 的消息不受影响
+    pub fn set_serial_by<K, F>(&self, key: F)
+    where
+        K: Hash,
+        F: Fn(&A::Message) -> Option<K> + Send + Sync + 'static,
+    {
+        let key = move |message: &A::Message| {
+            key(message).map(|key| {
+                let mut hasher = DefaultHasher::new();
+                key.hash(&mut hasher);
+                hasher.finish()
+            })
+        };
+        *self.0.serial_key.write() = Some(Arc::new(key));
+    }
+
+    /// 所有 handler 的任务统计
+    pub fn metrics(&self) -> TaskMetrics {
+        self.0.limiter.metrics()
+    }
+
+    pub(crate) fn limiter(&self) -> Limiter {
+        self.0.limiter.clone()
+    }
+
+    pub(crate) fn dispatch_limiter(&self) -> Limiter {
+        self.0.dispatch.clone()
+    }
+
+    /// 串行模式下消息所属的会话
+    pub(crate) fn serial_key(&self, message: &A::Message) -> Option<u64> {
+        let key = self.0.serial_key.read().clone();
+        key.and_then(|key| key(message))
+    }
+}
+
+impl<A: App> Default for Scheduler<A> {
+    fn default() -> Self {
+        Self(Arc::new(SchedulerInner {
+            limiter: Limiter::default(),
+            dispatch: Limiter::default(),
+            serial_key: RwLock::default(),
+        }))
+    }
+}
+
+impl<A: App> Clone for Scheduler<A> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<A: App> fmt::Debug for Scheduler<A> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Scheduler")
+            .field("metrics", &self.metrics())
+            .field("serial", &self.0.serial_key.read().is_some())
+            .finish()
+    }
+}
@@ -1,9 +1,16 @@
-use std::sync::{
-    atomic::{AtomicBool, Ordering::Relaxed},
-    Arc,
+use std::{
+    cell::Cell,
+    marker::PhantomData,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering::Relaxed},
+        Arc,
+    },
 };
 
-use crate::msg_framework::{App, FromRequest, Request};
+use crate::msg_framework::{
+    App, Continue, Dispatcher, FromRequest, Handled, Middleware, Middlewares, Next, Outcome,
+    Request, Scheduler, TaskMetrics,
+};
 use log::*;
 use tokio::sync::broadcast;
 
@@ -37,6 +44,11 @@ struct Application {
     channel: broadcast::Sender<Msg>,
     msg_received: Arc<AtomicBool>,
     num_received: Arc<AtomicBool>,
+    middlewares: Middlewares<Application>,
+    dispatcher: Dispatcher<Application>,
+    scheduler: Scheduler<Application>,
+    /// App 不需要是 `Sync` 的
+    _not_sync: PhantomData<Cell<()>>,
 }
 impl Application {
     pub fn new() -> Self {
@@ -47,6 +59,10 @@ impl Application {
             channel: tx,
             msg_received,
             num_received,
+            middlewares: Middlewares::new(),
+            dispatcher: Dispatcher::new(),
+            scheduler: Scheduler::new(),
+            _not_sync: PhantomData,
         }
     }
 }
@@ -55,6 +71,18 @@ impl App for Application {
     fn event_bus(&self) -> broadcast::Sender<Self::Message> {
         self.channel.clone()
     }
+
+    fn middlewares(&self) -> Middlewares<Self> {
+        self.middlewares.clone()
+    }
+
+    fn dispatcher(&self) -> Dispatcher<Self> {
+        self.dispatcher.clone()
+    }
+
+    fn scheduler(&self) -> Scheduler<Self> {
+        self.scheduler.clone()
+    }
 }
 
 #[tokio::test]
@@ -65,6 +93,331 @@ async fn test_simple_server() {
     event_bus.send(Msg::Text("test".to_string())).unwrap();
     event_bus.send(Msg::Number(123)).unwrap();
     tokio::time::sleep(std::time::Duration::from_millis(1)).await;
-    assert_eq!(app.msg_received.load(Relaxed), true);
-    assert_eq!(app.num_received.load(Relaxed), true);
+    assert!(app.msg_received.load(Relaxed));
+    assert!(app.num_received.load(Relaxed));
+}
+
+#[tokio::test]
+async fn test_pause_and_unregister() {
+    let handle = Application::new().handler(handler);
+    let event_bus = handle.event_bus();
+
+    handle.pause();
+    event_bus.send(Msg::Text("test".to_string())).unwrap();
+    tokio::time::sleep(std::time::Duration::from_millis(1)).await;
+    assert!(!handle.msg_received.load(Relaxed));
+
+    handle.resume();
+    event_bus.send(Msg::Text("test".to_string())).unwrap();
+    tokio::time::sleep(std::time::Duration::from_millis(1)).await;
+    assert!(handle.msg_received.load(Relaxed));
+
+    handle.unregister();
+    assert!(handle.is_unregistered());
+    tokio::time::sleep(std::time::Duration::from_millis(1)).await;
+    event_bus.send(Msg::Number(123)).unwrap_err();
+    assert!(!handle.num_received.load(Relaxed));
+}
+
+type Log = Arc<parking_lot::Mutex<Vec<String>>>;
+
+/// 记录中间件执行的顺序以及 handler 的结果
+struct Record(&'static str, Log);
+
+#[async_trait]
+impl Middleware<Application> for Record {
+    async fn handle(&self, request: Request<Application>, next: Next<'_, Application>) -> Outcome {
+        self.1.lock().push(format!("{}>", self.0));
+        let outcome = next.run(request).await;
+        self.1.lock().push(format!("<{} {:?}", self.0, outcome));
+        outcome
+    }
+}
+
+/// 拦截数字消息，给文字消息打上标记
+struct Filter;
+
+#[derive(Clone)]
+struct Tag(&'static str);
+
+impl FromRequest<Application> for Tag {
+    fn from_request(request: &Request<Application>) -> Option<Self> {
+        request.extensions.get::<Tag>().cloned()
+    }
+}
+
+#[async_trait]
+impl Middleware<Application> for Filter {
+    async fn handle(
+        &self,
+        mut request: Request<Application>,
+        next: Next<'_, Application>,
+    ) -> Outcome {
+        match request.message {
+            Msg::Number(_) => Outcome::Skipped,
+            Msg::Text(_) => {
+                request.extensions.insert(Tag("tagged"));
+                next.run(request).await
+            }
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_middleware() {
+    let log = Log::default();
+    let handler_log = log.clone();
+    let app = Application::new()
+        .middleware(Record("outer", log.clone()))
+        .middleware(Filter)
+        .handler(move |msg: Msg, tag: Tag| {
+            let log = handler_log.clone();
+            async move {
+                log.lock().push(format!("handler {}", tag.0));
+                match msg {
+                    Msg::Text(s) if s == "fail" => Err("failed"),
+                    _ => Ok(()),
+                }
+            }
+        })
+        .into_app()
+        // 在 handler 之后注册的中间件也会生效
+        .middleware(Record("inner", log.clone()));
+
+    let event_bus = app.event_bus();
+    event_bus.send(Msg::Number(1)).unwrap();
+    tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    assert_eq!(*log.lock(), vec!["outer>", "<outer Skipped"]);
+
+    log.lock().clear();
+    event_bus.send(Msg::Text("fail".to_string())).unwrap();
+    tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    assert_eq!(
+        *log.lock(),
+        vec![
+            "outer>",
+            "inner>",
+            "handler tagged",
+            "<inner Failed(\"failed\")",
+            "<outer Failed(\"failed\")",
+        ]
+    );
+}
+
+#[tokio::test]
+async fn test_priority_and_fallback() {
+    let log = Log::default();
+    let record = |name: &'static str| {
+        let log = log.clone();
+        move |msg: Msg| {
+            let log = log.clone();
+            async move {
+                log.lock().push(name.to_string());
+                match msg {
+                    Msg::Text(s) if s == "stop" => Outcome::Handled,
+                    _ => Outcome::Done,
+                }
+            }
+        }
+    };
+    let app = Application::new()
+        .handler_priority(-1, record("low"))
+        .handler_priority(10, record("high"))
+        .into_app();
+    let low_log = log.clone();
+    let app = app
+        .handler_priority(0, move |_: Msg| {
+            let log = low_log.clone();
+            async move {
+                log.lock().push("middle".to_string());
+                Continue(())
+            }
+        })
+        .fallback(record("fallback"))
+        .into_app();
+
+    let send = |msg: Msg| {
+        log.lock().clear();
+        app.event_bus().send(msg).unwrap();
+    };
+
+    send(Msg::Text("stop".to_string()));
+    tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    assert_eq!(*log.lock(), vec!["high"]);
+
+    send(Msg::Number(1));
+    tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    assert_eq!(*log.lock(), vec!["high", "middle", "low", "fallback"]);
+
+    // Handled 包裹的返回值失败时不消费消息
+    let handle = app
+        .clone()
+        .handler_priority(5, |_: Msg| async { Handled(Err::<(), _>("failed")) });
+    send(Msg::Number(2));
+    tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    assert_eq!(*log.lock(), vec!["high", "middle", "low", "fallback"]);
+    handle.unregister();
+
+    let handle = app
+        .clone()
+        .handler_priority(5, |_: Msg| async { Handled(()) });
+    send(Msg::Number(3));
+    tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    assert_eq!(*log.lock(), vec!["high"]);
+
+    handle.unregister();
+    send(Msg::Number(4));
+    tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    assert_eq!(*log.lock(), vec!["high", "middle", "low", "fallback"]);
+}
+
+#[tokio::test]
+async fn test_concurrency_limit() {
+    let sleep = |_: Msg| tokio::time::sleep(std::time::Duration::from_millis(20));
+    let metrics = |queued, running, completed| TaskMetrics {
+        queued,
+        running,
+        completed,
+    };
+
+    // 单个 handler 的限制
+    let app = Application::new();
+    let handle = app.clone().handler(sleep).max_concurrency(1);
+    for i in 0..3 {
+        app.event_bus().send(Msg::Number(i)).unwrap();
+    }
+    tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    // 拿到许可之前不会接收下一条消息，第三条消息还留在广播通道中
+    assert_eq!(handle.metrics(), metrics(1, 1, 0));
+    assert_eq!(app.scheduler().metrics(), metrics(1, 1, 0));
+    tokio::time::sleep(std::time::Duration::from_millis(80)).await;
+    assert_eq!(handle.metrics(), metrics(0, 0, 3));
+
+    // 全局的限制对所有的 handler 生效
+    let app = Application::new().max_concurrency(2);
+    let handle = app.clone().handler(sleep).handler_priority(0, sleep);
+    app.event_bus().send(Msg::Number(0)).unwrap();
+    app.event_bus().send(Msg::Number(1)).unwrap();
+    tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    assert_eq!(app.scheduler().metrics(), metrics(2, 2, 0));
+    tokio::time::sleep(std::time::Duration::from_millis(80)).await;
+    assert_eq!(app.scheduler().metrics(), metrics(0, 0, 4));
+    assert_eq!(handle.metrics().completed, 2);
+}
+
+#[tokio::test]
+async fn test_serial() {
+    let log = Log::default();
+    let record = |prefix: &'static str| {
+        let log = log.clone();
+        move |msg: Msg| {
+            let log = log.clone();
+            async move {
+                if let Msg::Number(i) = msg {
+                    let delay = [40, 30, 10, 5][i as usize];
+                    tokio::time::sleep(std::time::Duration::from_millis(delay)).await;
+                    log.lock().push(format!("{}{}", prefix, i));
+                }
+            }
+        }
+    };
+    let app = Application::new().serial_by(|msg: &Msg| match msg {
+        Msg::Number(i) => Some(i % 2),
+        Msg::Text(_) => None,
+    });
+    app.clone().handler(record("h")).fallback(record("f"));
+    for i in 0..4 {
+        app.event_bus().send(Msg::Number(i)).unwrap();
+    }
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    // 同一个会话按照顺序处理，不同的会话并发处理
+    let log = log.lock();
+    let order = |prefix| {
+        log.iter()
+            .filter(|s| s.starts_with(prefix))
+            .cloned()
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(order("h"), vec!["h1", "h3", "h0", "h2"]);
+    assert_eq!(order("f"), vec!["f1", "f3", "f0", "f2"]);
+}
+
+struct Number(i64);
+
+impl FromRequest<Application> for Number {
+    fn from_request(request: &Request<Application>) -> Option<Self> {
+        match request.message {
+            Msg::Number(i) => Some(Number(i)),
+            Msg::Text(_) => None,
+        }
+    }
+
+    fn may_extract(message: &Msg) -> bool {
+        matches!(message, Msg::Number(_))
+    }
+}
+
+/// 记录经过的请求数的中间件
+struct Counter(Arc<AtomicUsize>);
+
+#[async_trait]
+impl Middleware<Application> for Counter {
+    async fn handle(&self, request: Request<Application>, next: Next<'_, Application>) -> Outcome {
+        self.0.fetch_add(1, Relaxed);
+        next.run(request).await
+    }
+}
+
+#[tokio::test]
+async fn test_middleware_skips_rejected_messages() {
+    let count = Arc::new(AtomicUsize::new(0));
+    let app = Application::new().middleware(Counter(count.clone()));
+    let app = app
+        .handler(|Number(i): Number, app: Application| async move {
+            assert_eq!(i, 123);
+            app.num_received.store(true, Relaxed);
+        })
+        .into_app();
+    let event_bus = app.event_bus();
+    event_bus.send(Msg::Text("test".to_string())).unwrap();
+    event_bus.send(Msg::Number(123)).unwrap();
+    tokio::time::sleep(std::time::Duration::from_millis(1)).await;
+    assert!(app.num_received.load(Relaxed));
+    // 参数提取失败的消息不会交给中间件
+    assert_eq!(count.load(Relaxed), 1);
+}
+
+#[tokio::test]
+async fn test_wait_for_permit_before_spawning() {
+    let app = Application::new();
+    let (release, released) = tokio::sync::watch::channel(false);
+    let handle = app
+        .clone()
+        .handler(move |_: Msg| {
+            let mut released = released.clone();
+            async move {
+                let _ = released.wait_for(|released| *released).await;
+            }
+        })
+        .max_concurrency(1);
+    for i in 0..5 {
+        app.event_bus().send(Msg::Number(i)).unwrap();
+    }
+    tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    // 只有一条消息在等待许可，剩下的留在广播通道中
+    let metrics = handle.metrics();
+    assert_eq!((metrics.queued, metrics.running), (1, 1));
+
+    release.send(true).unwrap();
+    tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    assert_eq!(handle.metrics().completed, 5);
+}
+
+#[tokio::test]
+async fn test_priority_handler_without_sync() {
+    // Application 不是 `Sync` 的，也可以通过 Dispatcher 分发
+    let app = Application::new().handler_priority(1, handler).into_app();
+    app.event_bus().send(Msg::Text("test".to_string())).unwrap();
+    tokio::time::sleep(std::time::Duration::from_millis(1)).await;
+    assert!(app.msg_received.load(Relaxed));
 }
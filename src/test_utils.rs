@@ -0,0 +1,83 @@
+//! 测试中共用的工具：构造收到的消息，以及可以回复 API 请求的 mock bot
+use parking_lot::Mutex;
+use serde_json::{json, Value};
+use std::sync::Arc;
+
+use crate::{
+    bot::QQ,
+    messages::{
+        friend::FriendMember,
+        group::{Group, GroupMember, Permission},
+        FriendMessage, GroupMessage, MessageChain,
+    },
+    Bot,
+};
+
+/// 群 `group` 中的普通群员 `sender` 发送的消息
+pub(crate) fn group_message(
+    group: u64,
+    sender: u64,
+    message: impl Into<MessageChain>,
+) -> GroupMessage {
+    GroupMessage {
+        sender: GroupMember {
+            id: QQ(sender),
+            member_name: String::new(),
+            special_title: String::new(),
+            permission: Permission::Member,
+            join: None,
+            last_speak: None,
+            group: Group {
+                id: QQ(group),
+                name: String::new(),
+                permission: Permission::Member,
+            },
+        },
+        message: message.into(),
+    }
+}
+
+/// 好友 `sender` 发送的私聊消息
+pub(crate) fn friend_message(sender: u64, message: impl Into<MessageChain>) -> FriendMessage {
+    FriendMessage {
+        sender: FriendMember {
+            id: QQ(sender),
+            nickname: String::new(),
+            remark: String::new(),
+        },
+        message: message.into(),
+    }
+}
+
+/// mock 的 bot 发送的所有消息的文字，按照发送的顺序排列
+#[derive(Clone, Default)]
+pub(crate) struct Sent(Arc<Mutex<Vec<String>>>);
+
+impl Sent {
+    /// 取出目前为止发送的消息
+    pub fn take(&self) -> Vec<String> {
+        std::mem::take(&mut *self.0.lock())
+    }
+}
+
+/// 不连接服务器的 bot：发送消息的请求会被记录在 [`Sent`] 中并且成功，
+/// 其他的 API 请求交给 `respond`，参数是请求的指令和内容，返回 `None` 时请求失败
+pub(crate) fn mock_bot(
+    respond: impl Fn(&str, &Value) -> Option<Value> + Send + 'static,
+) -> (Bot, Sent) {
+    let sent = Sent::default();
+    let record = sent.clone();
+    let bot = Bot::mock_api(QQ(1), move |command, content| match command {
+        "sendGroupMessage" | "sendFriendMessage" => {
+            let chain: MessageChain =
+                serde_json::from_value(content["messageChain"].clone()).unwrap();
+            record.0.lock().push(chain.plain_text());
+            json!({ "code": 0, "msg": "", "messageId": 1 })
+        }
+        _ => match respond(command, content) {
+            Some(data) => json!({ "code": 0, "msg": "", "data": data }),
+            None => json!({ "code": 400, "msg": "unexpected request" }),
+        },
+    });
+    (bot, sent)
+}